@@ -0,0 +1,84 @@
+//! Cost of the voice handlers per voice at growing polyphony, with the mock host.
+//!
+//! Run with `cargo bench --features testing --bench voicers`. The time per voice should stay
+//! flat as the polyphony grows. It includes the mock host recording the calls.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use fpsdk::testing::MockHost;
+use fpsdk::voice::{LevelParams, Params, SendVoiceHandler, Tag};
+
+const ROUNDS: usize = 50;
+
+fn params() -> Params {
+    let levels = LevelParams {
+        pan: 0.0,
+        vol: 0.8,
+        pitch: 0.0,
+        mod_x: 0.0,
+        mod_y: 0.0,
+    };
+    Params {
+        init_levels: levels.clone(),
+        final_levels: levels,
+    }
+}
+
+// trigger, release and kill `polyphony` output voices, the kills in another order
+fn out_voices(host: &MockHost, polyphony: usize) -> Duration {
+    let mut out = host.host().out_voice_handler(polyphony);
+    let mut total = Duration::ZERO;
+    for round in 0..ROUNDS {
+        let first = (round * polyphony) as isize;
+        let start = Instant::now();
+        for tag in first..first + polyphony as isize {
+            black_box(out.trigger(params(), 0, Tag(tag)).is_some());
+        }
+        for tag in first..first + polyphony as isize {
+            out.release(Tag(tag));
+        }
+        for tag in (first..first + polyphony as isize).rev() {
+            out.kill(Tag(tag));
+        }
+        total += start.elapsed();
+        host.take_calls();
+    }
+    total
+}
+
+fn voices(host: &MockHost, polyphony: usize) -> Duration {
+    let mut voicer = host.host().voice_handler();
+    let mut total = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        for tag in 0..polyphony as isize {
+            voicer.release(Tag(tag));
+            voicer.kill(Tag(tag));
+        }
+        total += start.elapsed();
+        host.take_calls();
+    }
+    total
+}
+
+fn per_voice(total: Duration, polyphony: usize) -> f64 {
+    total.as_nanos() as f64 / (ROUNDS * polyphony) as f64
+}
+
+fn main() {
+    let host = MockHost::new();
+    println!(
+        "{:>10} {:>16} {:>16}",
+        "polyphony", "out voice (ns)", "voice (ns)"
+    );
+    for polyphony in [16, 64, 256, 1024, 4096] {
+        let out = out_voices(&host, polyphony);
+        let voices = voices(&host, polyphony);
+        println!(
+            "{:>10} {:>16.1} {:>16.1}",
+            polyphony,
+            per_voice(out, polyphony),
+            per_voice(voices, polyphony)
+        );
+    }
+}
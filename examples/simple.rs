@@ -1,29 +1,30 @@
 use std::collections::HashMap;
 #[cfg(unix)]
 use std::fs::OpenOptions;
-use std::io::{self, Read};
-use std::sync::{Arc, Mutex, Once};
+use std::sync::Once;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use bincode;
 use log::{error, info, trace, LevelFilter};
 use serde::{Deserialize, Serialize};
-#[cfg(windows)]
-use simple_logging;
 #[cfg(unix)]
 use simplelog::{ConfigBuilder, WriteLogger};
 
+use fpsdk::host::context::{AudioContext, GuiContext, TickContext};
 use fpsdk::host::{self, prompt::PromptBuilder, Event, GetName, Host, OutVoicer, Voicer};
+use fpsdk::midi::MidiAction;
 use fpsdk::plugin::message;
-use fpsdk::plugin::{self, Info, InfoBuilder, Plugin, StateReader, StateWriter};
+use fpsdk::plugin::state::{self, Container};
+use fpsdk::plugin::{self, Generator, Info, InfoBuilder, Plugin, StateReader, StateWriter};
 use fpsdk::voice::{self, ReceiveVoiceHandler, SendVoiceHandler, Voice};
 use fpsdk::{
     create_plugin, AsRawPtr, FromRawPtr, MessageBoxFlags, MidiMessage, Note, Notes, NotesFlags,
-    ProcessParamFlags, TimeFormat, ValuePtr,
+    NotesTarget, ProcessParamFlags, TimeFormat, ValuePtr,
 };
 
 static ONCE: Once = Once::new();
 const LOG_PATH: &str = "simple.log";
+const STATE_UID: u32 = u32::from_le_bytes(*b"Smpl");
+const STATE_VERSION: u32 = 1;
 
 #[derive(Debug)]
 struct Simple {
@@ -31,7 +32,6 @@ struct Simple {
     tag: plugin::Tag,
     param_names: Vec<String>,
     state: State,
-    voice_handler: SimpleVoiceHandler,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -47,11 +47,7 @@ impl Plugin for Simple {
 
         info!("init plugin with tag {}", tag);
 
-        let voice_h = host.voice_handler();
-        let out_voice_h = host.out_voice_handler();
-
         Self {
-            voice_handler: SimpleVoiceHandler::new(voice_h, out_voice_h),
             host,
             tag,
             param_names: vec![
@@ -66,8 +62,8 @@ impl Plugin for Simple {
     fn info(&self) -> Info {
         info!("plugin {} will return info", self.tag);
 
-        InfoBuilder::new_full_gen("Simple", "Simple", self.param_names.len() as u32)
-            // InfoBuilder::new_effect("Simple", "Simple", self.param_names.len() as u32)
+        InfoBuilder::new("Simple", "Simple", self.param_names.len() as u32)
+            .get_note_input()
             .want_new_tick()
             .with_out_ctrls(1)
             .with_out_voices(1)
@@ -85,27 +81,30 @@ impl Plugin for Simple {
         self.state._time = time;
         self.state._param_1 = time as f64 * 0.001;
         self.state._param_2 = time as i64 / 2;
-        match bincode::serialize_into(writer, &self.state) {
+        let mut container = Container::new(STATE_UID, STATE_VERSION);
+        match bincode::serialize(&self.state) {
+            Ok(data) => container.insert(*b"main", data),
+            Err(e) => return error!("error serializing state {}", e),
+        }
+        match container.write_to(writer) {
             Ok(_) => info!("state {:?} saved", self.state),
-            Err(e) => error!("error serializing state {}", e),
+            Err(e) => error!("error saving state {}", e),
         }
     }
 
-    fn load_state(&mut self, mut reader: StateReader) {
-        let mut buf = [0; std::mem::size_of::<State>()];
-        reader
-            .read(&mut buf)
-            .and_then(|_| {
-                bincode::deserialize::<State>(&buf).map_err(|e| {
-                    io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("error deserializing value {}", e),
-                    )
-                })
+    fn load_state(&mut self, reader: StateReader) {
+        Container::read_from(reader, STATE_UID)
+            .and_then(|mut container| {
+                // there's nothing to migrate yet
+                container.migrate(STATE_VERSION, |_, _| Ok(()))?;
+                container
+                    .get(*b"main")
+                    .and_then(|data| bincode::deserialize::<State>(data).ok())
+                    .ok_or(state::Error::Chunk(*b"main"))
             })
-            .and_then(|value| {
+            .map(|value| {
                 self.state = value;
-                Ok(info!("read state {:?}", self.state))
+                info!("read state {:?}", self.state);
             })
             .unwrap_or_else(|e| error!("error reading value from state {}", e));
     }
@@ -143,12 +142,12 @@ impl Plugin for Simple {
         info!("{} host sends event {:?}", self.tag, event);
     }
 
-    fn tick(&mut self) {
+    fn tick(&mut self, ctx: &mut TickContext<'_>) {
         // assign to itself to see it in log
-        self.host.on_controller(self.tag, 0, 12345_u16);
+        ctx.on_controller(0, 12345_u16);
     }
 
-    fn idle(&mut self) {
+    fn idle(&mut self, _ctx: &mut GuiContext<'_>) {
         trace!("{} idle", self.tag);
     }
 
@@ -179,22 +178,26 @@ impl Plugin for Simple {
         Box::new(0)
     }
 
-    fn midi_in(&mut self, message: MidiMessage) {
+    fn midi_in(&mut self, message: MidiMessage) -> MidiAction {
         trace!("receive MIDI message {:?}", message);
+        MidiAction::Pass
     }
+}
 
-    fn render(&mut self, input: &[[f32; 2]], output: &mut [[f32; 2]]) {
-        if self.voice_handler.voices.len() < 1 {
-            // consider it an effect
-            input.iter().zip(output).for_each(|(inp, outp)| {
-                outp[0] = inp[0] * 0.25;
-                outp[1] = inp[1] * 0.25;
-            });
-        }
+impl Generator for Simple {
+    type Voices = SimpleVoiceHandler;
+
+    fn voices(host: &Host, _tag: plugin::Tag) -> Self::Voices {
+        SimpleVoiceHandler::new(host.voice_handler(), host.out_voice_handler(16))
     }
 
-    fn voice_handler(&mut self) -> Option<&mut dyn ReceiveVoiceHandler> {
-        Some(&mut self.voice_handler)
+    fn render(
+        &mut self,
+        _ctx: &mut AudioContext<'_>,
+        _output: &mut [[f32; 2]],
+        _voices: &mut Self::Voices,
+    ) {
+        // the voices are silent, the output is left as is
     }
 }
 
@@ -211,7 +214,7 @@ impl Simple {
         self.host.on_parameter(
             self.tag,
             0,
-            ValuePtr::from_raw_ptr(0.123456789_f32.as_raw_ptr()),
+            ValuePtr::from_raw_ptr(0.123_456_79_f32.as_raw_ptr()),
         );
     }
 
@@ -233,6 +236,7 @@ impl Simple {
 
     fn add_notes(&mut self) {
         let notes = Notes {
+            target: NotesTarget::PianoRoll,
             notes: vec![
                 Note {
                     position: 0,
@@ -311,15 +315,15 @@ impl Simple {
 struct SimpleVoiceHandler {
     voices: HashMap<voice::Tag, SimpleVoice>,
     out_handler: SimpleOutVoiceHandler,
-    send_handler: Arc<Mutex<Voicer>>,
-    send_out_handler: Arc<Mutex<OutVoicer>>,
+    send_handler: Voicer,
+    send_out_handler: OutVoicer,
 }
 
 impl SimpleVoiceHandler {
-    fn new(send_handler: Arc<Mutex<Voicer>>, send_out_handler: Arc<Mutex<OutVoicer>>) -> Self {
+    fn new(send_handler: Voicer, send_out_handler: OutVoicer) -> Self {
         Self {
             voices: HashMap::new(),
-            out_handler: SimpleOutVoiceHandler::default(),
+            out_handler: SimpleOutVoiceHandler,
             send_handler,
             send_out_handler,
         }
@@ -327,42 +331,42 @@ impl SimpleVoiceHandler {
 }
 
 impl SimpleVoiceHandler {
-    fn log_velocity(&self, tag: voice::Tag) {
-        let mut send_handler = self.send_handler.lock().unwrap();
-        if let Some(velocity) = send_handler.on_event(tag, voice::Event::GetVelocity) {
+    fn log_velocity(&mut self, tag: voice::Tag) {
+        if let Some(velocity) = self.send_handler.on_event(tag, voice::Event::GetVelocity) {
             trace!("get velocity {} for voice {}", velocity.get::<f32>(), tag);
         }
     }
 
-    fn log_color(&self, tag: voice::Tag) {
-        let mut send_handler = self.send_handler.lock().unwrap();
-        if let Some(color) = send_handler.on_event(tag, voice::Event::GetColor) {
+    fn log_color(&mut self, tag: voice::Tag) {
+        if let Some(color) = self.send_handler.on_event(tag, voice::Event::GetColor) {
             trace!("get color {} for voice {}", color.get::<u8>(), tag);
         }
     }
 }
 
 impl ReceiveVoiceHandler for SimpleVoiceHandler {
-    fn trigger(&mut self, params: voice::Params, tag: voice::Tag) -> &mut dyn Voice {
+    fn trigger(&mut self, params: voice::Params, tag: voice::Tag) {
         let voice = SimpleVoice::new(params.clone(), tag);
         trace!("trigger voice {:?}", voice);
         self.voices.insert(tag, voice);
 
-        let mut send_out_handler = self.send_out_handler.lock().unwrap();
-
-        send_out_handler.trigger(params, 0, tag);
+        self.send_out_handler.trigger(params, 0, tag);
 
         self.log_velocity(tag);
         self.log_color(tag);
-
-        self.voices.get_mut(&tag).unwrap()
     }
 
     fn release(&mut self, tag: voice::Tag) {
-        trace!("release voice {:?}", self.voices.get(&tag));
-        self.send_out_handler.lock().unwrap().release(tag);
+        if let Some(voice) = self.voices.get(&tag) {
+            trace!(
+                "release voice {:?} with volume {}",
+                voice,
+                voice.params.final_levels.vol
+            );
+        }
+        self.send_out_handler.release(tag);
         trace!("send kill voice {}", tag);
-        self.send_handler.lock().unwrap().kill(tag);
+        self.send_handler.kill(tag);
     }
 
     fn kill(&mut self, tag: voice::Tag) {
@@ -440,7 +444,7 @@ fn _init_log() {
         .open(LOG_PATH)
         .unwrap();
     let config = ConfigBuilder::new().set_time_to_local(true).build();
-    let _ = WriteLogger::init(LevelFilter::Trace, config, file).unwrap();
+    WriteLogger::init(LevelFilter::Trace, config, file).unwrap();
 }
 
-create_plugin!(Simple);
+create_plugin!(generator Simple);
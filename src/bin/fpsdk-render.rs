@@ -0,0 +1,607 @@
+//! Render a plugin library offline.
+//!
+//! Effects process a WAV file, generators play a standard MIDI file. The result is written to a
+//! 32-bit float stereo WAV file.
+//!
+//! ```text
+//! fpsdk-render <plugin> <input.wav|input.mid> <output.wav> [options]
+//!
+//! --sample-rate <hz>    sample rate (default: input WAV rate or 44100)
+//! --block-size <n>      maximum number of samples per render call (default: 512)
+//! --tempo <bpm>         tempo, instead of the MIDI file's tempo changes (default: 120)
+//! --state <file>        state blob to load before rendering
+//! --tail <seconds>      extra time to render after the last MIDI event (default: 2)
+//! ```
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use fpsdk::host::{Event, Message};
+use fpsdk::intptr_t;
+use fpsdk::testing::{CreatePlugInstance, HostCall, MockHost, PluginInstance};
+use fpsdk::voice::{LevelParams, Params};
+use libloading::{Library, Symbol};
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+
+// FL Studio's pulses per quarter note
+const PPQ: f64 = 96.0;
+// tempo of MIDI files without Set Tempo events
+const DEFAULT_TEMPO: f64 = 120.0;
+// see FPF_Generator
+const FLAG_GENERATOR: i32 = 1;
+// see FPF_WantNewTick
+const FLAG_WANT_NEW_TICK: i32 = 1 << 5;
+
+const USAGE: &str = "usage: fpsdk-render <plugin> <input.wav|input.mid> <output.wav> \
+[--sample-rate <hz>] [--block-size <n>] [--tempo <bpm>] [--state <file>] [--tail <seconds>]";
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+#[derive(Debug)]
+struct Options {
+    plugin: PathBuf,
+    input: PathBuf,
+    output: PathBuf,
+    sample_rate: Option<u32>,
+    block_size: usize,
+    tempo: Option<f64>,
+    state: Option<PathBuf>,
+    tail: f64,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut paths = Vec::new();
+        let mut options = Self {
+            plugin: PathBuf::new(),
+            input: PathBuf::new(),
+            output: PathBuf::new(),
+            sample_rate: None,
+            block_size: 512,
+            tempo: None,
+            state: None,
+            tail: 2.0,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--sample-rate" => options.sample_rate = Some(value()?.parse()?),
+                "--block-size" => options.block_size = value()?.parse()?,
+                "--tempo" => options.tempo = Some(value()?.parse()?),
+                "--state" => options.state = Some(value()?.into()),
+                "--tail" => options.tail = value()?.parse()?,
+                "-h" | "--help" => return Err(USAGE.into()),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg).into()),
+                _ => paths.push(PathBuf::from(arg)),
+            }
+        }
+
+        if paths.len() != 3 || options.block_size == 0 || options.tempo.is_some_and(|t| t <= 0.0) {
+            return Err(USAGE.into());
+        }
+        options.output = paths.pop().unwrap();
+        options.input = paths.pop().unwrap();
+        options.plugin = paths.pop().unwrap();
+
+        Ok(options)
+    }
+}
+
+fn main() {
+    if let Err(error) = Options::parse(env::args().skip(1)).and_then(|options| run(&options)) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<()> {
+    // the library has to outlive the instance
+    let library = unsafe { Library::new(&options.plugin)? };
+    let create: Symbol<'_, CreatePlugInstance> = unsafe { library.get(b"CreatePlugInstance")? };
+    let host = MockHost::new();
+    let mut plugin = unsafe { host.instantiate(*create) };
+    let info = plugin.info();
+    eprintln!("loaded {}", info.long_name);
+
+    let generator = info.flags & FLAG_GENERATOR != 0;
+    let input = if generator {
+        None
+    } else {
+        Some(read_wav(options)?)
+    };
+    let sample_rate = options
+        .sample_rate
+        .or_else(|| input.as_ref().map(|(rate, _)| *rate))
+        .unwrap_or(44100);
+    let song = if generator {
+        read_midi(&fs::read(&options.input)?, options.tempo, sample_rate)?
+    } else {
+        Song {
+            notes: Vec::new(),
+            tempos: vec![TempoChange {
+                position: 0,
+                tempo: options.tempo.unwrap_or(DEFAULT_TEMPO),
+            }],
+        }
+    };
+
+    plugin.dispatch(Message::SetSampleRate(sample_rate));
+    plugin.dispatch(Message::SetBlockSize(options.block_size as u32));
+    let mut renderer = Renderer {
+        host: &host,
+        plugin: &mut plugin,
+        sample_rate,
+        block_size: options.block_size,
+        samples_per_tick: 0.0,
+        want_new_tick: info.flags & FLAG_WANT_NEW_TICK != 0,
+        next_tick: 0.0,
+        voices: HashMap::new(),
+        output: Vec::new(),
+    };
+    // the tempo at the start is set before enabling the plugin
+    renderer.set_tempo(song.tempos[0].tempo);
+    if let Some(state) = &options.state {
+        renderer.plugin.load_state(&fs::read(state)?);
+    }
+    renderer.plugin.dispatch(Message::SetEnabled(true));
+
+    match input {
+        Some((_, samples)) => renderer.effect(&samples),
+        None => {
+            let len = song.notes.last().map(|note| note.position).unwrap_or(0)
+                + (options.tail * sample_rate as f64) as usize;
+            renderer.generator(&song, len)
+        }
+    }
+    let output = renderer.finish();
+    plugin.dispatch(Message::SetEnabled(false));
+
+    write_wav(options, sample_rate, &output)?;
+    eprintln!(
+        "wrote {} samples to {}",
+        output.len(),
+        options.output.display()
+    );
+
+    Ok(())
+}
+
+struct Renderer<'a, 'h> {
+    host: &'h MockHost,
+    plugin: &'a mut PluginInstance<'h>,
+    sample_rate: u32,
+    block_size: usize,
+    samples_per_tick: f64,
+    want_new_tick: bool,
+    next_tick: f64,
+    // host voice tag -> plugin voice handle
+    voices: HashMap<intptr_t, intptr_t>,
+    output: Vec<[f32; 2]>,
+}
+
+impl Renderer<'_, '_> {
+    fn effect(&mut self, input: &[[f32; 2]]) {
+        let mut position = 0;
+        while position < input.len() {
+            let end = self.block_end(position, input.len());
+            self.tick_if_due(position);
+            let mut block = vec![[0.0; 2]; end - position];
+            self.plugin.eff_render(&input[position..end], &mut block);
+            self.output.extend_from_slice(&block);
+            position = end;
+        }
+    }
+
+    fn generator(&mut self, song: &Song, len: usize) {
+        // (channel, key) -> host voice tags
+        let mut playing: HashMap<(u8, u8), Vec<intptr_t>> = HashMap::new();
+        let mut next_tag = 0;
+        let mut notes = song.notes.iter().peekable();
+        let mut tempos = song.tempos.iter().peekable();
+        let mut position = 0;
+
+        while position < len {
+            while let Some(change) = tempos.next_if(|change| change.position <= position) {
+                if change.position > 0 {
+                    self.set_tempo(change.tempo);
+                }
+            }
+            while let Some(note) = notes.next_if(|note| note.position <= position) {
+                let key = (note.channel, note.key);
+                match note.velocity {
+                    Some(velocity) => {
+                        next_tag += 1;
+                        let handle = self
+                            .plugin
+                            .trigger_voice(voice_params(note.key, velocity), next_tag);
+                        self.voices.insert(next_tag, handle);
+                        playing.entry(key).or_default().push(next_tag);
+                    }
+                    None => {
+                        let tag = playing.get_mut(&key).and_then(|tags| tags.pop());
+                        if let Some(handle) = tag.and_then(|tag| self.voices.get(&tag)) {
+                            self.plugin.release_voice(*handle);
+                        }
+                    }
+                }
+            }
+
+            let mut end = self.block_end(position, len);
+            if let Some(note) = notes.peek() {
+                end = end.min(note.position.max(position + 1));
+            }
+            if let Some(change) = tempos.peek() {
+                end = end.min(change.position.max(position + 1));
+            }
+            self.tick_if_due(position);
+            let mut block = vec![[0.0; 2]; end - position];
+            self.plugin.gen_render(&mut block);
+            self.output.extend_from_slice(&block);
+            self.kill_finished_voices();
+            position = end;
+        }
+    }
+
+    fn set_tempo(&mut self, tempo: f64) {
+        self.samples_per_tick = self.sample_rate as f64 * 60.0 / (tempo * PPQ);
+        self.plugin.process_event(Event::Tempo(
+            tempo as f32,
+            self.samples_per_tick.round() as u32,
+        ));
+        self.plugin
+            .dispatch(Message::SetSamplesPerTick(self.samples_per_tick as f32));
+    }
+
+    // blocks end at the next tick, so NewTick comes right on time
+    fn block_end(&self, position: usize, len: usize) -> usize {
+        let mut end = (position + self.block_size).min(len);
+        if self.want_new_tick {
+            let tick = self.next_tick.ceil() as usize;
+            if tick > position {
+                end = end.min(tick);
+            }
+        }
+        end
+    }
+
+    fn tick_if_due(&mut self, position: usize) {
+        while self.next_tick <= position as f64 {
+            if self.want_new_tick {
+                self.plugin.tick();
+            }
+            self.next_tick += self.samples_per_tick;
+        }
+    }
+
+    // plugins ask the host to kill voices, then the host tells the plugin to do it
+    fn kill_finished_voices(&mut self) {
+        for call in self.host.take_calls() {
+            if let HostCall::VoiceKill(tag) = call {
+                if let Some(handle) = self.voices.remove(&tag) {
+                    self.plugin.kill_voice(handle);
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<[f32; 2]> {
+        for (_, handle) in self.voices.drain() {
+            self.plugin.kill_voice(handle);
+        }
+        self.output
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Song {
+    notes: Vec<NoteEvent>,
+    // the first change is at the start
+    tempos: Vec<TempoChange>,
+}
+
+#[derive(Debug, PartialEq)]
+struct TempoChange {
+    position: usize,
+    // BPM
+    tempo: f64,
+}
+
+#[derive(Debug, PartialEq)]
+struct NoteEvent {
+    position: usize,
+    channel: u8,
+    key: u8,
+    // None for note off
+    velocity: Option<u8>,
+}
+
+fn voice_params(key: u8, velocity: u8) -> Params {
+    let levels = LevelParams {
+        pan: 0.0,
+        vol: vel_to_vol(velocity as f32 / 127.0),
+        pitch: key as f32 * 100.0,
+        mod_x: 0.0,
+        mod_y: 0.0,
+    };
+    Params {
+        init_levels: levels.clone(),
+        final_levels: levels,
+    }
+}
+
+// inverse of fpsdk::voice::vol_to_vel
+fn vel_to_vol(velocity: f32) -> f32 {
+    ((2610.0_f32 / 127.0 + 1.0).powf(velocity) - 1.0) / 10.0
+}
+
+// The file's tempo changes are followed, unless `tempo` is given.
+fn read_midi(data: &[u8], tempo: Option<f64>, sample_rate: u32) -> Result<Song> {
+    let smf = Smf::parse(data)?;
+    let ticks_per_beat = match smf.header.timing {
+        Timing::Metrical(ticks) => ticks.as_int() as f64,
+        Timing::Timecode(..) => return Err("SMPTE timing is not supported".into()),
+    };
+
+    // (tick, BPM), in any track
+    let mut changes = vec![(0, tempo.unwrap_or(DEFAULT_TEMPO))];
+    if tempo.is_none() {
+        for track in &smf.tracks {
+            let mut ticks = 0_u64;
+            for event in track {
+                ticks += event.delta.as_int() as u64;
+                if let TrackEventKind::Meta(MetaMessage::Tempo(micros)) = event.kind {
+                    changes.push((ticks, 60_000_000.0 / micros.as_int() as f64));
+                }
+            }
+        }
+        // stable, so the last change at a tick wins
+        changes.sort_by_key(|change| change.0);
+    }
+    let map = TempoMap::new(&changes, ticks_per_beat, sample_rate);
+
+    let mut notes = Vec::new();
+    for track in &smf.tracks {
+        let mut ticks = 0_u64;
+        for event in track {
+            ticks += event.delta.as_int() as u64;
+            if let TrackEventKind::Midi { channel, message } = event.kind {
+                let velocity = match message {
+                    MidiMessage::NoteOn { key, vel } if vel > 0 => Some((key, Some(vel.as_int()))),
+                    MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                        Some((key, None))
+                    }
+                    _ => None,
+                };
+                if let Some((key, velocity)) = velocity {
+                    notes.push(NoteEvent {
+                        position: map.position(ticks),
+                        channel: channel.as_int(),
+                        key: key.as_int(),
+                        velocity,
+                    });
+                }
+            }
+        }
+    }
+    // note offs go first, so retriggered notes don't get released right away
+    notes.sort_by_key(|note| (note.position, note.velocity.is_some()));
+
+    Ok(Song {
+        notes,
+        tempos: map.changes(),
+    })
+}
+
+// Sample positions of MIDI ticks, one segment per tempo.
+struct TempoMap {
+    // (start tick, start sample, samples per tick, BPM)
+    segments: Vec<(u64, f64, f64, f64)>,
+}
+
+impl TempoMap {
+    // `changes` are sorted and start at tick 0.
+    fn new(changes: &[(u64, f64)], ticks_per_beat: f64, sample_rate: u32) -> Self {
+        let mut segments: Vec<(u64, f64, f64, f64)> = Vec::new();
+        for &(tick, tempo) in changes {
+            let samples_per_tick = sample_rate as f64 * 60.0 / (tempo * ticks_per_beat);
+            match segments.last_mut() {
+                Some(last) if last.0 == tick => {
+                    last.2 = samples_per_tick;
+                    last.3 = tempo;
+                }
+                Some(&mut (start, sample, last_samples_per_tick, _)) => {
+                    let sample = sample + (tick - start) as f64 * last_samples_per_tick;
+                    segments.push((tick, sample, samples_per_tick, tempo));
+                }
+                None => segments.push((tick, 0.0, samples_per_tick, tempo)),
+            }
+        }
+        Self { segments }
+    }
+
+    fn position(&self, tick: u64) -> usize {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.0 <= tick)
+            .saturating_sub(1);
+        let (start, sample, samples_per_tick, _) = self.segments[index];
+        (sample + (tick - start) as f64 * samples_per_tick) as usize
+    }
+
+    fn changes(&self) -> Vec<TempoChange> {
+        self.segments
+            .iter()
+            .map(|&(_, sample, _, tempo)| TempoChange {
+                position: sample as usize,
+                tempo,
+            })
+            .collect()
+    }
+}
+
+fn read_wav(options: &Options) -> Result<(u32, Vec<[f32; 2]>)> {
+    let mut reader = hound::WavReader::open(&options.input)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .collect::<std::result::Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|s| s as f32 / scale))
+                .collect::<std::result::Result<_, _>>()?
+        }
+    };
+
+    Ok((
+        spec.sample_rate,
+        to_frames(&samples, spec.channels as usize)?,
+    ))
+}
+
+// mono is played on both sides, channels past the second are dropped
+fn to_frames(samples: &[f32], channels: usize) -> Result<Vec<[f32; 2]>> {
+    if channels == 0 {
+        return Err("the WAV file has no channels".into());
+    }
+    Ok(samples
+        .chunks_exact(channels)
+        .map(|frame| [frame[0], frame[channels.min(2) - 1]])
+        .collect())
+}
+
+fn write_wav(options: &Options, sample_rate: u32, samples: &[[f32; 2]]) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(&options.output, spec)?;
+    for frame in samples {
+        writer.write_sample(frame[0])?;
+        writer.write_sample(frame[1])?;
+    }
+    writer.finalize()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use fpsdk::host::context::AudioContext;
+    use fpsdk::plugin::{Effect, InfoBuilder};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Half;
+
+    fpsdk::test_plugin!(Half, InfoBuilder::new("Half", "Half", 0).build());
+
+    impl Effect for Half {
+        fn render(
+            &mut self,
+            _ctx: &mut AudioContext<'_>,
+            input: &[[f32; 2]],
+            output: &mut [[f32; 2]],
+        ) {
+            for (input, output) in input.iter().zip(output) {
+                *output = [input[0] * 0.5, input[1] * 0.5];
+            }
+        }
+    }
+
+    #[test]
+    fn renders_effect() {
+        let host = MockHost::new();
+        let mut plugin = host.create_effect::<Half>();
+        let mut renderer = Renderer {
+            host: &host,
+            plugin: &mut plugin,
+            sample_rate: 44100,
+            block_size: 3,
+            samples_per_tick: 4.0,
+            want_new_tick: false,
+            next_tick: 0.0,
+            voices: HashMap::new(),
+            output: Vec::new(),
+        };
+
+        let input = to_frames(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 1).unwrap();
+        renderer.effect(&input);
+        let expected: Vec<_> = (0..7).map(|n| [n as f32 * 0.5; 2]).collect();
+        assert_eq!(expected, renderer.finish());
+
+        assert!(to_frames(&[1.0], 0).is_err());
+        assert_eq!(vec![[1.0, 2.0]], to_frames(&[1.0, 2.0, 3.0], 3).unwrap());
+    }
+
+    #[test]
+    fn follows_tempo_changes() {
+        #[rustfmt::skip]
+        let track = [
+            // 120 BPM
+            0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20,
+            0x00, 0x90, 60, 100,
+            0x60, 0x80, 60, 0,
+            // 240 BPM after a beat
+            0x00, 0xff, 0x51, 0x03, 0x03, 0xd0, 0x90,
+            0x00, 0x90, 62, 100,
+            0x60, 0x80, 62, 0,
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        // format 0, one track, 96 ticks per beat
+        let mut data = b"MThd\x00\x00\x00\x06\x00\x00\x00\x01\x00\x60MTrk".to_vec();
+        data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        data.extend_from_slice(&track);
+        let note = |position, key, velocity| NoteEvent {
+            position,
+            channel: 0,
+            key,
+            velocity,
+        };
+
+        // a beat is 24000 samples, then 12000
+        let song = read_midi(&data, None, 48000).unwrap();
+        assert_eq!(
+            vec![
+                note(0, 60, Some(100)),
+                note(24000, 60, None),
+                note(24000, 62, Some(100)),
+                note(36000, 62, None),
+            ],
+            song.notes
+        );
+        assert_eq!(
+            vec![
+                TempoChange {
+                    position: 0,
+                    tempo: 120.0,
+                },
+                TempoChange {
+                    position: 24000,
+                    tempo: 240.0,
+                },
+            ],
+            song.tempos
+        );
+
+        // --tempo overrides the file
+        let song = read_midi(&data, Some(60.0), 48000).unwrap();
+        assert_eq!(96000, song.notes[3].position);
+        assert_eq!(
+            vec![TempoChange {
+                position: 0,
+                tempo: 60.0,
+            }],
+            song.tempos
+        );
+    }
+}
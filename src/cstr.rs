@@ -0,0 +1,139 @@
+//! C strings exchanged with the host.
+//!
+//! Rust strings can hold NULs and the host's buffers have a fixed size. The functions here never
+//! panic:
+//!
+//! - NULs are removed, so the host sees the whole string instead of a prefix;
+//! - strings copied to a host buffer are truncated on a char boundary, so the host never gets a
+//!   cut UTF-8 sequence;
+//! - invalid UTF-8 and UTF-16 coming from the host are replaced with `U+FFFD`.
+use std::ffi::CString;
+
+use crate::intptr_t;
+
+/// The size of the host's string buffers, including the NUL: the name for
+/// [`Plugin::name_of`](../plugin/trait.Plugin.html#tymethod.name_of), the names in
+/// [`NameColor`](../struct.NameColor.html), sample file names and the prompt value.
+pub const BUF_LEN: usize = 256;
+
+/// Make a C string, removing NULs.
+pub fn to_c_string(value: &str) -> CString {
+    let bytes: Vec<u8> = value.bytes().filter(|&b| b != 0).collect();
+    CString::new(bytes).unwrap_or_default()
+}
+
+/// The longest prefix of `value` which fits `max_len` bytes and ends on a char boundary.
+pub fn truncate(value: &str, max_len: usize) -> &str {
+    if value.len() <= max_len {
+        return value;
+    }
+    let mut len = max_len;
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+    &value[..len]
+}
+
+/// Copy `value` to `buf` and NUL-terminate it. NULs are removed and the string is truncated to
+/// fit.
+///
+/// Returns the number of bytes written, without the NUL.
+pub fn write_to(value: &str, buf: &mut [u8]) -> usize {
+    let max_len = match buf.len().checked_sub(1) {
+        Some(max_len) => max_len,
+        None => return 0,
+    };
+    let mut len = 0;
+    for ch in value.chars().filter(|&ch| ch != '\0') {
+        let ch_len = ch.len_utf8();
+        if len + ch_len > max_len {
+            break;
+        }
+        ch.encode_utf8(&mut buf[len..len + ch_len]);
+        len += ch_len;
+    }
+    buf[len] = 0;
+    len
+}
+
+/// Read a string from a host buffer. It stops at the first NUL or at the end of the buffer.
+pub fn read_from(buf: &[u8]) -> String {
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Like [`read_from`](fn.read_from.html), but reuses the memory of `value`.
+pub fn read_into(buf: &[u8], value: &mut String) {
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    value.clear();
+    value.push_str(&String::from_utf8_lossy(&buf[..len]));
+}
+
+/// Copy a NUL-terminated UTF-16 string (`PWideChar`) the host returned. A null pointer gives an
+/// empty string.
+///
+/// # Safety
+///
+/// The pointer must be null or point to a NUL-terminated UTF-16 string which is valid during this
+/// call. It doesn't have to be aligned.
+pub unsafe fn read_wide(value: intptr_t) -> String {
+    if value == 0 {
+        return String::new();
+    }
+    let ptr = value as *const u16;
+    let mut units = Vec::new();
+    loop {
+        let unit = ptr.add(units.len()).read_unaligned();
+        if unit == 0 {
+            break;
+        }
+        units.push(unit);
+    }
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::Host;
+    use crate::plugin::message::GetProjectInfo;
+    use crate::plugin::Tag;
+    use crate::testing::{id, MockHost, Reply};
+
+    #[test]
+    fn fits_host_buffers() {
+        assert_eq!(b"ab", to_c_string("a\0b").as_bytes());
+
+        // "é" is two bytes, the second one doesn't fit
+        let long = format!("{}é", "a".repeat(BUF_LEN - 2));
+        let mut buf = [0xff_u8; BUF_LEN];
+        assert_eq!(BUF_LEN - 2, write_to(&long, &mut buf));
+        assert_eq!(0, buf[BUF_LEN - 2]);
+        assert_eq!("a".repeat(BUF_LEN - 2), read_from(&buf));
+        assert_eq!(truncate(&long, BUF_LEN - 1), read_from(&buf));
+
+        assert_eq!(3, write_to("x\0yz", &mut buf[..4]));
+        assert_eq!("xyz", read_from(&buf));
+        assert_eq!(0, write_to("x", &mut []));
+        assert_eq!("full", read_from(b"full"));
+
+        let mut value = String::with_capacity(BUF_LEN);
+        read_into(b"ab\0c", &mut value);
+        assert_eq!("ab", value);
+        assert_eq!(BUF_LEN, value.capacity());
+    }
+
+    #[test]
+    fn decodes_wide_project_info() {
+        let mock = MockHost::new();
+        let mut host: Host = mock.host();
+        mock.queue_reply(
+            id::GET_PROJECT_INFO,
+            Reply::String("Ünïcode 🎹".to_string()),
+        );
+
+        assert_eq!("Ünïcode 🎹", host.on_message(Tag(1), GetProjectInfo::Title));
+        assert_eq!("", host.on_message(Tag(1), GetProjectInfo::Author));
+        assert_eq!("", unsafe { read_wide(0) });
+    }
+}
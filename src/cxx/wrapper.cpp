@@ -3,21 +3,6 @@
 #include <cstring>
 #include <stdlib.h>
 
-intptr_t init_p_notes_params(int target, int flags, int ch_num, int pat_num,
-                             TNoteParams *notes, int len) {
-    TNotesParams *params = (TNotesParams *)malloc(sizeof(TNotesParams) +
-                                                  sizeof(TNoteParams) * len);
-    params->Target = target;
-    params->Flags = flags;
-    params->PatNum = pat_num;
-    params->ChanNum = ch_num;
-    params->Count = len;
-    params->NoteParams[0] = *notes;
-    memmove(params->NoteParams, notes, sizeof(TNoteParams) * len);
-
-    return (intptr_t)params;
-}
-
 char *alloc_real_cstr(char *rust_cstr) {
     char *result = (char *)malloc(strlen(rust_cstr) + 1);
     strcpy(result, rust_cstr);
@@ -31,7 +16,12 @@ int istream_read(void *istream, unsigned char *data, unsigned int size,
     if (!data || size < 1)
         return 0x80004003; // E_POINTER
 
-    return (int)((IStream *)istream)->Read(data, size, (unsigned long *)read);
+    // ULONG is 64-bit on some platforms
+    unsigned long read_long = 0;
+    int result = (int)((IStream *)istream)->Read(data, size, &read_long);
+    *read = (unsigned int)read_long;
+
+    return result;
 }
 
 int istream_write(void *istream, const unsigned char *data, unsigned int size,
@@ -39,7 +29,11 @@ int istream_write(void *istream, const unsigned char *data, unsigned int size,
     if (!data || size < 1)
         return 0x80004003; // E_POINTER
 
-    return (int)((IStream *)istream)->Write(data, size, (unsigned long *)write);
+    unsigned long write_long = 0;
+    int result = (int)((IStream *)istream)->Write(data, size, &write_long);
+    *write = (unsigned int)write_long;
+
+    return result;
 }
 
 void *create_plug_instance_c(void *host, intptr_t tag, void *adapter) {
@@ -56,7 +50,7 @@ void *create_plug_instance_c(void *host, intptr_t tag, void *adapter) {
                                                  (int)info->num_out_voices,
                                                  {*reserved}};
 
-    free_rbox_raw(info);
+    free_info(info);
 
     PluginWrapper *wrapper = new PluginWrapper(
         (TFruityPlugHost *)host, tag, (PluginAdapter *)adapter, c_info);
@@ -77,7 +71,7 @@ PluginWrapper::~PluginWrapper() {
     free(Info->LongName);
     free(Info->ShortName);
     delete Info;
-    free_rbox_raw(adapter);
+    plugin_destroy(adapter);
 }
 
 void _stdcall PluginWrapper::SaveRestoreState(IStream *stream, BOOL save) {
@@ -108,9 +102,7 @@ void _stdcall PluginWrapper::GetName(int section, int index, int value,
         (intptr_t)value,
     };
 
-    char *name_of = plugin_name_of(adapter, message);
-    strcpy(name, name_of);
-    free_rstring(name_of);
+    plugin_name_of(adapter, message, name);
 }
 
 int _stdcall PluginWrapper::ProcessEvent(int event_id, int event_value,
@@ -170,11 +162,11 @@ TVoiceHandle _stdcall PluginWrapper::TriggerVoice(PVoiceParams voice_params,
 }
 
 void _stdcall PluginWrapper::Voice_Release(TVoiceHandle handle) {
-    voice_handler_release(adapter, (void *)handle);
+    voice_handler_release(adapter, handle);
 }
 
 void _stdcall PluginWrapper::Voice_Kill(TVoiceHandle handle) {
-    voice_handler_kill(adapter, (void *)handle);
+    voice_handler_kill(adapter, handle);
 }
 
 int _stdcall PluginWrapper::Voice_ProcessEvent(TVoiceHandle handle,
@@ -186,7 +178,7 @@ int _stdcall PluginWrapper::Voice_ProcessEvent(TVoiceHandle handle,
         (intptr_t)flags,
     };
 
-    return (int)voice_handler_on_event(adapter, (void *)handle, message);
+    return (int)voice_handler_on_event(adapter, handle, message);
 }
 
 int _stdcall PluginWrapper::Voice_Render(TVoiceHandle, PWAV32FS, int &) {
@@ -301,10 +293,11 @@ TIOBuffer host_get_input_buf(void *host, TPluginTag tag, intptr_t offset) {
     return buf;
 }
 
-TIOBuffer host_get_output_buf(void *host, TPluginTag tag, intptr_t offset) {
+TIOBuffer host_get_output_buf(void *host, TPluginTag tag, intptr_t offset,
+                              DWORD flags) {
     TIOBuffer buf = {
         0,
-        0,
+        flags,
     };
     ((TFruityPlugHost *)host)->GetOutBuffer(tag, offset, &buf);
 
@@ -323,6 +316,38 @@ void *host_get_send_buf(void *host, intptr_t offset) {
     return ((TFruityPlugHost *)host)->GetSendBuffer(offset);
 }
 
+bool host_load_sample(void *host, intptr_t *handle, char *file_name,
+                      int flags) {
+    // only 16 bit 44 kHz stereo is supported by the host. The frames are read
+    // as 32 bit float with GetSampleInfo.
+    TWaveFormatExtensible format;
+    memset(&format, 0, sizeof(format));
+    format.WaveFormatEx.wFormatTag = WAVE_FORMAT_PCM;
+    format.WaveFormatEx.nChannels = 2;
+    format.WaveFormatEx.nSamplesPerSec = 44100;
+    format.WaveFormatEx.wBitsPerSample = 16;
+    format.WaveFormatEx.nBlockAlign = 4;
+    format.WaveFormatEx.nAvgBytesPerSec = 44100 * 4;
+    format.stream.wValidBitsPerSample = 16;
+    format.stream.dwChannelMask = 3;
+
+    return ((TFruityPlugHost *)host)
+        ->LoadSample(*handle, file_name, &format, flags);
+}
+
+void host_close_sample(void *host, intptr_t handle) {
+    ((TFruityPlugHost *)host)->CloseSample(handle);
+}
+
+void host_get_sample_info(void *host, intptr_t handle, TSampleInfo *info) {
+    ((TFruityPlugHost *)host)->GetSampleInfo(handle, info);
+}
+
+void host_get_sample_region(void *host, intptr_t handle, int region_num,
+                            TSampleRegion *region) {
+    ((TFruityPlugHost *)host)->GetSampleRegion(handle, region_num, region);
+}
+
 bool prompt_show(void *host, int x, int y, char *msg, char *result,
                  int &color) {
 
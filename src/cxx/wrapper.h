@@ -87,8 +87,8 @@ extern "C" intptr_t plugin_dispatcher(PluginAdapter *adapter,
 extern "C" int plugin_process_event(PluginAdapter *adapter, FlMessage event);
 extern "C" intptr_t plugin_process_param(PluginAdapter *adapter,
                                          FlMessage event);
-extern "C" char *plugin_name_of(const PluginAdapter *adapter,
-                                FlMessage message);
+extern "C" void plugin_name_of(const PluginAdapter *adapter, FlMessage message,
+                               char *name);
 extern "C" void plugin_idle(PluginAdapter *adapter);
 extern "C" void plugin_tick(PluginAdapter *adapter);
 extern "C" void plugin_midi_tick(PluginAdapter *adapter);
@@ -101,14 +101,15 @@ extern "C" void plugin_midi_in(PluginAdapter *adapter, int &message);
 extern "C" void plugin_save_state(PluginAdapter *adapter, IStream *istream);
 extern "C" void plugin_load_state(PluginAdapter *adapter, IStream *istream);
 extern "C" void plugin_loop_in(PluginAdapter *adapter, intptr_t message);
+extern "C" void plugin_destroy(PluginAdapter *adapter);
 
 // Voice handler
 extern "C" intptr_t voice_handler_trigger(PluginAdapter *adapter, Params params,
                                           intptr_t tag);
-extern "C" void voice_handler_release(PluginAdapter *adapter, void *voice);
-extern "C" void voice_handler_kill(PluginAdapter *adapter, void *voice);
-extern "C" intptr_t voice_handler_on_event(PluginAdapter *adapter, void *voice,
-                                           FlMessage message);
+extern "C" void voice_handler_release(PluginAdapter *adapter, intptr_t handle);
+extern "C" void voice_handler_kill(PluginAdapter *adapter, intptr_t handle);
+extern "C" intptr_t voice_handler_on_event(PluginAdapter *adapter,
+                                           intptr_t handle, FlMessage message);
 extern "C" void out_voice_handler_kill(PluginAdapter *adapter, intptr_t tag);
 extern "C" intptr_t out_voice_handler_on_event(PluginAdapter *adapter,
                                                intptr_t tag, FlMessage message);
@@ -144,11 +145,18 @@ extern "C" void host_resume_out(void *host);
 extern "C" TIOBuffer host_get_input_buf(void *host, TPluginTag tag,
                                         intptr_t offset);
 extern "C" TIOBuffer host_get_output_buf(void *host, TPluginTag tag,
-                                         intptr_t offset);
+                                         intptr_t offset, DWORD flags);
 extern "C" void *host_get_insert_buf(void *host, TPluginTag tag,
                                      intptr_t offset);
 extern "C" void *host_get_mix_buf(void *host, intptr_t offset);
 extern "C" void *host_get_send_buf(void *host, intptr_t offset);
+extern "C" bool host_load_sample(void *host, intptr_t *handle, char *file_name,
+                                 int flags);
+extern "C" void host_close_sample(void *host, intptr_t handle);
+extern "C" void host_get_sample_info(void *host, intptr_t handle,
+                                     TSampleInfo *info);
+extern "C" void host_get_sample_region(void *host, intptr_t handle,
+                                       int region_num, TSampleRegion *region);
 
 extern "C" bool prompt_show(void *host, int x, int y, char *msg, char *result,
                             int &color);
@@ -167,11 +175,8 @@ extern "C" intptr_t host_on_out_voice_event(void *host, intptr_t tag,
 
 // Utility
 extern "C" void fplog(const char *msg);
-extern "C" intptr_t init_p_notes_params(int target, int flags, int ch_num,
-                                        int pat_num, TNoteParams *notes,
-                                        int len);
 
-extern "C" void free_rbox_raw(void *raw_ptr);
+extern "C" void free_info(Info *info);
 extern "C" void free_rstring(char *raw_str);
 // FFI to make C string (`char *`) managed by C side. Because `char *`
 // produced by `CString::into_raw` leads to memory leak. Here's what docs
@@ -1,12 +1,14 @@
 //! Plugin's host (FL Studio).
+pub mod context;
 pub mod prompt;
+pub mod routing;
+pub mod sample;
+pub mod transport;
 
-use std::collections::HashMap;
 use std::ffi::c_void;
 use std::os::raw::{c_char, c_int, c_uchar};
 use std::slice;
-use std::sync::atomic::AtomicPtr;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicPtr, Ordering};
 
 use log::trace;
 
@@ -20,19 +22,13 @@ use crate::{
 /// Plugin host.
 #[derive(Debug)]
 pub struct Host {
-    voicer: Arc<Mutex<Voicer>>,
-    out_voicer: Arc<Mutex<OutVoicer>>,
     pub(crate) host_ptr: AtomicPtr<c_void>,
 }
 
 impl Host {
     /// Initializer.
     pub fn new(host_ptr: *mut c_void) -> Self {
-        let voicer = Arc::new(Mutex::new(Voicer::new(AtomicPtr::new(host_ptr))));
-        let out_voicer = Arc::new(Mutex::new(OutVoicer::new(AtomicPtr::new(host_ptr))));
         Self {
-            voicer,
-            out_voicer,
             host_ptr: AtomicPtr::new(host_ptr),
         }
     }
@@ -95,11 +91,11 @@ impl Host {
     /// of those. Note that these have to be inserted at the BEGINNING of the string.
     ///
     /// - `"^a"` - shows a little icon that informs the user that the parameter that the hint is
-    /// about can be linked to a MIDI controller.
+    ///   about can be linked to a MIDI controller.
     /// - `"^b"` - informs the user that the parameter is recordable.
     /// - `"^c"` - shows a little smiley. No real use, just for fun.
     /// - `"^d"` - shows a mouse with the right button clicked, to denote a control that has a
-    /// popup menu.
+    ///   popup menu.
     /// - `"^e"` - shows an unhappy smiley, to use when something went wrong.
     /// - `"^f"` - shows a left-pointing arrow.
     /// - `"^g"` - shows a double right-pointing arrow, for fast forward.
@@ -121,7 +117,10 @@ impl Host {
     /// To be able to use this method, you should enable MIDI out for the plugin (see
     /// [`InfoBuilder::midi_out`](../plugin/struct.InfoBuilder.html#method.midi_out)) and send
     /// [`plugin::message::ActivateMidi`](../plugin/message/struct.ActivateMidi.html) to the host.
-    pub fn midi_out(&mut self, tag: plugin::Tag, message: MidiMessage) {
+    ///
+    /// It accepts a [`midi::MidiEvent`](../midi/enum.MidiEvent.html) as well.
+    pub fn midi_out(&mut self, tag: plugin::Tag, message: impl Into<MidiMessage>) {
+        let message = message.into();
         // We could use MidiMessage directly with Box::into_raw, but we can't because of the Rust
         // memory layer. We couldn't free the allocated memory properly, because it's managed by
         // the host. So we just send parameters and instantiate FL's TMIDIOutMsg on the C side.
@@ -144,7 +143,8 @@ impl Host {
     ///
     /// To be able to use this method, you should enable MIDI out for the plugin (see
     /// [`InfoBuilder::midi_out`](../plugin/struct.InfoBuilder.html#method.midi_out)).
-    pub fn midi_out_del(&mut self, tag: plugin::Tag, message: MidiMessage) {
+    pub fn midi_out_del(&mut self, tag: plugin::Tag, message: impl Into<MidiMessage>) {
+        let message = message.into();
         unsafe {
             host_midi_out_del(
                 *self.host_ptr.get_mut(),
@@ -179,13 +179,13 @@ impl Host {
     /// This is a function for thread synchronization. When this is called, no more voices shall be
     /// created and there will be no more rendering until
     /// [`Host::unlock_mix`](struct.Host.html#method.unlock_mix) has been called.
-    pub fn lock_mix(&mut self) {
+    pub(crate) fn lock_mix(&mut self) {
         unsafe { host_lock_mix(*self.host_ptr.get_mut()) };
     }
 
     /// Unlocks the mix thread if it was previously locked with
     /// [`Host::lock_mix`](struct.Host.html#method.lock_mix).
-    pub fn unlock_mix(&mut self) {
+    pub(crate) fn unlock_mix(&mut self) {
         unsafe { host_unlock_mix(*self.host_ptr.get_mut()) };
     }
 
@@ -193,7 +193,7 @@ impl Host {
     ///
     /// This is an alternative to [`Host::lock_mix`](struct.Host.html#method.lock_mix).
     /// It won't freeze the audio. This function can only be called from the GUI thread!
-    pub fn lock_plugin(&mut self, tag: plugin::Tag) {
+    pub(crate) fn lock_plugin(&mut self, tag: plugin::Tag) {
         unsafe { host_lock_plugin(*self.host_ptr.get_mut(), tag.0) };
     }
 
@@ -201,7 +201,7 @@ impl Host {
     ///
     /// Unlocks the mix thread if it was previously locked with
     /// [`Host::lock_plugin`](struct.Host.html#method.lock_plugin).
-    pub fn unlock_plugin(&mut self, tag: plugin::Tag) {
+    pub(crate) fn unlock_plugin(&mut self, tag: plugin::Tag) {
         unsafe { host_unlock_plugin(*self.host_ptr.get_mut(), tag.0) };
     }
 
@@ -209,27 +209,30 @@ impl Host {
     /// created and there will be no more rendering until
     /// [`Host::resume_out`](struct.Host.html#method.resume_out) has been called. Unlike
     /// [`Host::lock_mix`](struct.Host.html#method.lock_mix), this function also stops all sound.
-    pub fn suspend_out(&mut self) {
+    pub(crate) fn suspend_out(&mut self) {
         unsafe { host_suspend_out(*self.host_ptr.get_mut()) };
     }
 
     /// Unlocks the mixer thread if it was previously locked with
     /// [`Host::suspend_out`](struct.Host.html#method.suspend_out).
-    pub fn resume_out(&mut self) {
+    pub(crate) fn resume_out(&mut self) {
         unsafe { host_resume_out(*self.host_ptr.get_mut()) };
     }
 
     /// Get one of the buffers.
     ///
-    /// - `kind` the kind of the buffer you want to get 
+    /// - `kind` the kind of the buffer you want to get
     ///   (see [`host::Buffer`](../host/enum.Buffer.html)).
     /// - `length` is the buffer length (use the length of the output buffer passed to the render
     ///   function).
     ///
-    /// The buffers are valid only during
-    /// [`Plugin::render`](../plugin/trait.Plugin.html#method.render) (i.e. it's supposed to be used
-    /// inside this method only).
-    pub fn buffer(
+    /// [`routing::Routing`](routing/struct.Routing.html) does the same safely: it sizes the
+    /// buffers to the block and unlocks the output buffers.
+    ///
+    /// The buffers are valid only during [`Effect::render`](../plugin/trait.Effect.html#tymethod.render)
+    /// or [`Generator::render`](../plugin/trait.Generator.html#tymethod.render) (i.e. it's supposed
+    /// to be used inside these methods only).
+    pub(crate) fn buffer(
         &mut self,
         tag: plugin::Tag,
         kind: Buffer,
@@ -264,9 +267,10 @@ impl Host {
         offset: usize,
         length: usize,
     ) -> Option<&mut [[f32; 2]]> {
+        // the flags are IO_Lock, they're not set by the host
         let out_buf =
-            unsafe { host_get_output_buf(*self.host_ptr.get_mut(), tag.0, offset as intptr_t) };
-        if out_buf.flags == 0 || length == 0 {
+            unsafe { host_get_output_buf(*self.host_ptr.get_mut(), tag.0, offset as intptr_t, 0) };
+        if out_buf.buffer.is_null() || length == 0 {
             return None;
         }
         Some(unsafe { slice::from_raw_parts_mut(out_buf.buffer as *mut [f32; 2], length) })
@@ -301,18 +305,22 @@ impl Host {
         Some(unsafe { slice::from_raw_parts_mut(mix_buf as *mut [f32; 2], length) })
     }
 
-    /// Get [`Voicer`](struct.Voicer.html)
-    pub fn voice_handler(&self) -> Arc<Mutex<Voicer>> {
-        Arc::clone(&self.voicer)
+    /// Get [`Voicer`](struct.Voicer.html). It's only a handle to the host, so every part of the
+    /// plugin can have its own.
+    pub fn voice_handler(&self) -> Voicer {
+        Voicer::new(AtomicPtr::new(self.host_ptr.load(Ordering::Relaxed)))
     }
 
-    /// Get [`OutVoicer`](struct.OutVoicer.html).
-    pub fn out_voice_handler(&self) -> Arc<Mutex<OutVoicer>> {
-        Arc::clone(&self.out_voicer)
+    /// Get [`OutVoicer`](struct.OutVoicer.html) for up to `capacity` output voices playing at
+    /// once.
+    pub fn out_voice_handler(&self, capacity: usize) -> OutVoicer {
+        OutVoicer::new(
+            AtomicPtr::new(self.host_ptr.load(Ordering::Relaxed)),
+            capacity,
+        )
     }
 }
 
-#[no_mangle]
 extern "C" {
     fn host_on_parameter(host: *mut c_void, tag: intptr_t, index: c_int, value: c_int);
     fn host_on_controller(host: *mut c_void, tag: intptr_t, index: intptr_t, value: intptr_t);
@@ -342,14 +350,19 @@ extern "C" {
     fn host_suspend_out(host: *mut c_void);
     fn host_resume_out(host: *mut c_void);
     fn host_get_input_buf(host: *mut c_void, tag: intptr_t, offset: intptr_t) -> TIOBuffer;
-    fn host_get_output_buf(host: *mut c_void, tag: intptr_t, offset: intptr_t) -> TIOBuffer;
+    fn host_get_output_buf(
+        host: *mut c_void,
+        tag: intptr_t,
+        offset: intptr_t,
+        flags: u32,
+    ) -> TIOBuffer;
     fn host_get_insert_buf(host: *mut c_void, tag: intptr_t, offset: intptr_t) -> *mut c_void;
     fn host_get_mix_buf(host: *mut c_void, offset: intptr_t) -> *mut c_void;
     fn host_get_send_buf(host: *mut c_void, offset: intptr_t) -> *mut c_void;
 }
 
 /// Type of the write-only buffer you want to get, using
-/// [`Host::buf_write`](../struct.Host.html#method.buf_write).
+/// [`AudioContext::buffer`](context/struct.AudioContext.html#method.buffer).
 #[derive(Debug)]
 pub enum Buffer {
     /// Multi input buffer for effects.
@@ -372,7 +385,8 @@ pub enum Buffer {
     /// reserved for the Fruity wrapper (so it may change in the future).
     ///
     /// The value is offset to the current buffer. 0 means the same buffer as passed to
-    /// [`Plugin::render`](../plugin/trait.Plugin.html#method.render), 1 means next insert track.
+    /// [`Generator::render`](../plugin/trait.Generator.html#tymethod.render), 1 means next insert
+    /// track.
     InsertWrite(isize),
     /// Mixer track buffer relative to the current generator's track.
     ///
@@ -395,6 +409,8 @@ struct TIOBuffer {
 }
 
 /// Use this to manually release, kill and notify voices about events.
+///
+/// It doesn't lock or allocate, so it can be used from the mixer thread.
 #[derive(Debug)]
 pub struct Voicer {
     host_ptr: AtomicPtr<c_void>,
@@ -406,6 +422,12 @@ impl Voicer {
     }
 }
 
+impl Clone for Voicer {
+    fn clone(&self) -> Self {
+        Self::new(AtomicPtr::new(self.host_ptr.load(Ordering::Relaxed)))
+    }
+}
+
 impl SendVoiceHandler for Voicer {
     /// Tell the host the specified voice should be silent (Note Off).
     fn release(&mut self, tag: voice::Tag) {
@@ -429,77 +451,189 @@ impl SendVoiceHandler for Voicer {
     }
 }
 
-#[no_mangle]
 extern "C" {
     fn host_release_voice(host: *mut c_void, tag: intptr_t);
     fn host_kill_voice(host: *mut c_void, tag: intptr_t);
     fn host_on_voice_event(host: *mut c_void, tag: intptr_t, message: FlMessage) -> intptr_t;
 }
 
+// marks an empty entry in the tag index
+const NO_SLOT: u32 = u32::MAX;
+
 /// Use this for operations with output voices (i.e. for VFX inside [patcher](
 /// https://www.image-line.com/support/flstudio_online_manual/html/plugins/Patcher.htm)).
+///
+/// The voices live in slots allocated up front (see
+/// [`Host::out_voice_handler`](struct.Host.html#method.out_voice_handler)), so triggering, killing
+/// and looking up a voice doesn't lock or allocate and takes the same time at any polyphony.
+/// Dropping it kills the output voices which are still playing.
 #[derive(Debug)]
 pub struct OutVoicer {
-    voices: HashMap<voice::Tag, OutVoice>,
+    // boxed slice, so the params given to the host never move
+    slots: Box<[OutVoice]>,
+    // indexes of the free slots
+    free: Vec<u32>,
+    // voice tag to slot index, open addressing with linear probing
+    index: Box<[u32]>,
     host_ptr: AtomicPtr<c_void>,
 }
 
 impl OutVoicer {
-    fn new(host_ptr: AtomicPtr<c_void>) -> Self {
+    fn new(host_ptr: AtomicPtr<c_void>, capacity: usize) -> Self {
+        let capacity = capacity.min(NO_SLOT as usize);
+        let levels = voice::LevelParams {
+            pan: 0.0,
+            vol: 0.0,
+            pitch: 0.0,
+            mod_x: 0.0,
+            mod_y: 0.0,
+        };
         Self {
-            voices: HashMap::new(),
+            slots: (0..capacity)
+                .map(|_| OutVoice {
+                    tag: voice::Tag(0),
+                    params: voice::Params {
+                        init_levels: levels.clone(),
+                        final_levels: levels.clone(),
+                    },
+                    inner_tag: voice::Tag(0),
+                })
+                .collect(),
+            free: (0..capacity as u32).rev().collect(),
+            // at most half full
+            index: vec![NO_SLOT; (capacity * 2).next_power_of_two()].into_boxed_slice(),
             host_ptr,
         }
     }
+
+    /// The number of output voices which can play at once.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// The number of output voices playing.
+    pub fn active(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    /// Get a playing output voice.
+    pub fn voice_mut(&mut self, tag: voice::Tag) -> Option<&mut OutVoice> {
+        let slot = self.index[self.find(tag)?];
+        Some(&mut self.slots[slot as usize])
+    }
+
+    fn home(&self, tag: voice::Tag) -> usize {
+        // Fibonacci hashing, the tags are often sequential
+        let hash = (tag.0 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        (hash >> 32) as usize & (self.index.len() - 1)
+    }
+
+    // position of the tag in the index
+    fn find(&self, tag: voice::Tag) -> Option<usize> {
+        let mask = self.index.len() - 1;
+        let mut pos = self.home(tag);
+        loop {
+            let slot = self.index[pos];
+            if slot == NO_SLOT {
+                return None;
+            }
+            if self.slots[slot as usize].tag == tag {
+                return Some(pos);
+            }
+            pos = (pos + 1) & mask;
+        }
+    }
+
+    fn insert(&mut self, tag: voice::Tag, slot: u32) {
+        let mask = self.index.len() - 1;
+        let mut pos = self.home(tag);
+        while self.index[pos] != NO_SLOT {
+            pos = (pos + 1) & mask;
+        }
+        self.index[pos] = slot;
+    }
+
+    // frees the slot at `pos` in the index and shifts the following entries back, so the probes
+    // don't stop early
+    fn remove(&mut self, mut pos: usize) -> u32 {
+        let mask = self.index.len() - 1;
+        let removed = self.index[pos];
+        self.index[pos] = NO_SLOT;
+        let mut next = (pos + 1) & mask;
+        while self.index[next] != NO_SLOT {
+            let slot = self.index[next];
+            let home = self.home(self.slots[slot as usize].tag);
+            // move it if its home isn't between the hole and its position
+            if (next.wrapping_sub(home) & mask) >= (next.wrapping_sub(pos) & mask) {
+                self.index[pos] = slot;
+                self.index[next] = NO_SLOT;
+                pos = next;
+            }
+            next = (next + 1) & mask;
+        }
+        self.free.push(removed);
+        removed
+    }
 }
 
 impl SendVoiceHandler for OutVoicer {
-    /// It returns `None` if the output has no destination.
+    /// It returns `None` if the output has no destination or all the slots are taken.
     fn trigger(
         &mut self,
         params: voice::Params,
         index: usize,
         tag: voice::Tag,
     ) -> Option<&mut dyn Voice> {
-        let params_ptr = Box::into_raw(Box::new(params));
+        // the host treats it as a new voice, the old one is killed
+        self.kill(tag);
+        let Some(&slot) = self.free.last() else {
+            trace!("no free slot for output voice {}", tag);
+            return None;
+        };
+
+        let voice = &mut self.slots[slot as usize];
+        voice.tag = tag;
+        voice.params = params;
+        let params_ptr: *mut voice::Params = &mut voice.params;
         let inner_tag = unsafe {
             host_trig_out_voice(*self.host_ptr.get_mut(), params_ptr, index as i32, tag.0)
         };
 
         if inner_tag == -1 {
             // if FVH_Null
-            unsafe { Box::from_raw(params_ptr) }; // free the memory
             trace!("send trigger voice is null");
             return None;
         }
 
-        let voice = OutVoice::new(tag, AtomicPtr::new(params_ptr), voice::Tag(inner_tag));
+        self.free.pop();
+        self.insert(tag, slot);
+        let voice = &mut self.slots[slot as usize];
+        voice.inner_tag = voice::Tag(inner_tag);
         trace!("send trigger output voice {:?}", voice);
-        self.voices.insert(tag, voice);
-        Some(self.voices.get_mut(&tag).unwrap())
+        Some(voice)
     }
 
     fn release(&mut self, tag: voice::Tag) {
-        if let Some(voice) = self.voices.get_mut(&tag) {
+        if let Some(voice) = self.voice_mut(tag) {
             trace!("send release output voice {:?}", voice);
-            unsafe { host_release_out_voice(*self.host_ptr.get_mut(), voice.inner_tag().0) }
+            let inner_tag = voice.inner_tag().0;
+            unsafe { host_release_out_voice(*self.host_ptr.get_mut(), inner_tag) }
         }
     }
 
     fn kill(&mut self, tag: voice::Tag) {
-        if let Some(mut voice) = self.voices.remove(&tag) {
+        if let Some(pos) = self.find(tag) {
             trace!("send kill output voice {}", tag);
-            unsafe {
-                host_kill_out_voice(*self.host_ptr.get_mut(), voice.inner_tag().0);
-                Box::from_raw(*voice.params_ptr.get_mut());
-            };
+            let slot = self.remove(pos);
+            let inner_tag = self.slots[slot as usize].inner_tag().0;
+            unsafe { host_kill_out_voice(*self.host_ptr.get_mut(), inner_tag) };
         }
     }
 
     fn on_event(&mut self, tag: voice::Tag, event: voice::Event) -> Option<ValuePtr> {
         trace!("send event {:?} for out voice {:?}", event, tag);
         let host_ptr = *self.host_ptr.get_mut();
-        self.voices.get_mut(&tag).and_then(|voice| {
+        self.voice_mut(tag).and_then(|voice| {
             Option::<FlMessage>::from(event).map(|message| {
                 ValuePtr(unsafe { host_on_out_voice_event(host_ptr, voice.inner_tag().0, message) })
             })
@@ -507,29 +641,35 @@ impl SendVoiceHandler for OutVoicer {
     }
 }
 
+impl Drop for OutVoicer {
+    // the host reads the params from the slots, so the live voices are killed before they're freed
+    fn drop(&mut self) {
+        let host_ptr = *self.host_ptr.get_mut();
+        for &slot in self.index.iter().filter(|&&slot| slot != NO_SLOT) {
+            let inner_tag = self.slots[slot as usize].inner_tag().0;
+            trace!("kill output voice {} on drop", inner_tag);
+            unsafe { host_kill_out_voice(host_ptr, inner_tag) };
+        }
+    }
+}
+
 /// Output voice.
 #[derive(Debug)]
 pub struct OutVoice {
     tag: voice::Tag,
-    params_ptr: AtomicPtr<voice::Params>,
+    params: voice::Params,
     inner_tag: voice::Tag,
 }
 
 impl OutVoice {
-    fn new(tag: voice::Tag, params_ptr: AtomicPtr<voice::Params>, inner_tag: voice::Tag) -> Self {
-        Self {
-            tag,
-            params_ptr,
-            inner_tag,
-        }
+    /// Get voice parameters.
+    pub fn params(&self) -> &voice::Params {
+        &self.params
     }
 
-    /// Get voice parameters.
-    pub fn params(&mut self) -> voice::Params {
-        let boxed_params = unsafe { Box::from_raw(*self.params_ptr.get_mut()) };
-        let params = boxed_params.clone();
-        self.params_ptr = AtomicPtr::new(Box::into_raw(boxed_params));
-        *params
+    /// Change voice parameters. The host reads them from here.
+    pub fn params_mut(&mut self) -> &mut voice::Params {
+        &mut self.params
     }
 
     /// Get inner tag.
@@ -613,6 +753,12 @@ pub enum Message<'a> {
     ///
     /// The value holds the new shape.
     ChanSampleChanged(&'a [f32]),
+    /// A sample has been loaded into the parent channel. This is given to the plugin as a sample
+    /// handle. Also see
+    /// [`InfoBuilder::get_chan_sample`](../plugin/struct.InfoBuilder.html#method.get_chan_sample).
+    ///
+    /// Use [`Sample::from_channel`](sample/struct.Sample.html#method.from_channel) to access it.
+    ChanSample(sample::Handle),
     /// The host has enabled/disabled the plugin.
     ///
     /// The value will contain the new state (`false` for disabled, `true` for enabled)
@@ -680,7 +826,7 @@ pub enum Message<'a> {
     ///
     /// The value has the packed MIDI message. Only note on/off for now.
     ///
-    /// Result should be `true` if handled, `false` otherwise.
+    /// Result should be [`midi::PreviewAction`](../midi/enum.PreviewAction.html).
     MidiIn(MidiMessage),
     /// Mixer routing changed, must use
     /// [`PluginMessage::GetInOuts`](../plugin/enum.PluginMessage.html#variant.GetInOuts) if
@@ -777,8 +923,11 @@ impl Message<'_> {
     }
 
     fn from_chan_sample_changed(message: FlMessage) -> Self {
-        let slice =
-            unsafe { std::slice::from_raw_parts_mut(message.value as *mut f32, WAVETABLE_SIZE) };
+        // the sample handle is in index, the wavetable is in value
+        if message.value == 0 {
+            return Message::ChanSample(sample::Handle(message.index));
+        }
+        let slice = unsafe { slice::from_raw_parts_mut(message.value as *mut f32, WAVETABLE_SIZE) };
         Message::ChanSampleChanged(slice)
     }
 }
@@ -972,3 +1121,84 @@ impl From<FlMessage> for Event {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{HostCall, MockHost};
+    use crate::voice::{LevelParams, Params};
+
+    fn params(vol: f32) -> Params {
+        let levels = LevelParams {
+            pan: 0.0,
+            vol,
+            pitch: 0.0,
+            mod_x: 0.0,
+            mod_y: 0.0,
+        };
+        Params {
+            init_levels: levels.clone(),
+            final_levels: levels,
+        }
+    }
+
+    #[test]
+    fn out_voices_in_slots() {
+        let host = MockHost::new();
+        let mut out = host.host().out_voice_handler(64);
+
+        for tag in 0..64 {
+            assert!(out
+                .trigger(params(tag as f32), 0, voice::Tag(tag))
+                .is_some());
+        }
+        // all slots are taken
+        assert!(out.trigger(params(0.0), 0, voice::Tag(64)).is_none());
+        assert_eq!(64, out.active());
+
+        for tag in (0..64).step_by(2) {
+            out.kill(voice::Tag(tag));
+        }
+        // killing twice is ignored
+        out.kill(voice::Tag(0));
+        assert_eq!(32, out.active());
+
+        // the others are still found after the kills shifted the index
+        for tag in (1..64).step_by(2) {
+            let voice = out.voice_mut(voice::Tag(tag)).unwrap();
+            assert_eq!(voice::Tag(tag + 1), voice.inner_tag());
+            assert_eq!(tag as f32, voice.params().final_levels.vol);
+        }
+        assert!(out.voice_mut(voice::Tag(2)).is_none());
+
+        host.take_calls();
+        out.trigger(params(1.0), 0, voice::Tag(100));
+        out.release(voice::Tag(100));
+        out.release(voice::Tag(2));
+        out.kill(voice::Tag(100));
+        assert_eq!(
+            vec![
+                HostCall::OutVoiceTrigger {
+                    params: params(1.0),
+                    index: 0,
+                    tag: 100,
+                    handle: 65,
+                },
+                HostCall::OutVoiceRelease(65),
+                HostCall::OutVoiceKill(65),
+            ],
+            host.take_calls()
+        );
+
+        // triggering a live tag kills its voice first
+        out.trigger(params(1.0), 0, voice::Tag(1));
+        assert_eq!(HostCall::OutVoiceKill(2), host.take_calls()[0]);
+        assert_eq!(32, out.active());
+
+        drop(out);
+        let calls = host.take_calls();
+        assert_eq!(32, calls.len());
+        assert!(calls.contains(&HostCall::OutVoiceKill(66)));
+        assert!(!calls.contains(&HostCall::OutVoiceKill(2)));
+    }
+}
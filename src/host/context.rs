@@ -0,0 +1,308 @@
+//! Host access by thread.
+//!
+//! Some host functions can only be called from one thread: `LockPlugin` is for the GUI thread,
+//! the buffers are valid only during render. The callbacks get a context with the functions they
+//! can use:
+//!
+//! - [`GuiContext`](struct.GuiContext.html) in
+//!   [`Plugin::idle`](../../plugin/trait.Plugin.html#method.idle) and
+//!   [`Plugin::show_editor`](../../plugin/trait.Plugin.html#method.show_editor);
+//! - [`AudioContext`](struct.AudioContext.html) in
+//!   [`Effect::render`](../../plugin/trait.Effect.html#tymethod.render) and
+//!   [`Generator::render`](../../plugin/trait.Generator.html#tymethod.render);
+//! - [`TickContext`](struct.TickContext.html) in
+//!   [`Plugin::tick`](../../plugin/trait.Plugin.html#method.tick).
+//!
+//! Each context only sends the messages meant for its thread (see
+//! [`GuiMessage`](../../plugin/message/trait.GuiMessage.html) and
+//! [`MixerMessage`](../../plugin/message/trait.MixerMessage.html)).
+//!
+//! ```ignore
+//! fn render(&mut self, ctx: &mut AudioContext<'_>, input: &[[f32; 2]], output: &mut [[f32; 2]]) {
+//!     // ...
+//!     if let Some(next) = ctx.buffer(Buffer::InsertWrite(1)) {
+//!         // ...
+//!     }
+//! }
+//!
+//! fn idle(&mut self, ctx: &mut GuiContext<'_>) {
+//!     let _lock = ctx.lock_plugin();
+//!     // swap the state the mixer thread uses
+//! }
+//! ```
+use crate::host::routing::Routing;
+use crate::host::{Buffer, Host};
+use crate::plugin;
+use crate::plugin::message::{GuiMessage, MixerMessage};
+use crate::{MidiMessage, ValuePtr};
+
+/// Host functions for the GUI thread.
+#[derive(Debug)]
+pub struct GuiContext<'a> {
+    host: &'a mut Host,
+    tag: plugin::Tag,
+}
+
+impl<'a> GuiContext<'a> {
+    pub(crate) fn new(host: &'a mut Host, tag: plugin::Tag) -> Self {
+        Self { host, tag }
+    }
+
+    /// The plugin's tag.
+    pub fn tag(&self) -> plugin::Tag {
+        self.tag
+    }
+
+    /// See [`Host::on_message`](../struct.Host.html#method.on_message).
+    pub fn on_message<T: GuiMessage>(&mut self, message: T) -> T::Return {
+        self.host.on_message(self.tag, message)
+    }
+
+    /// See [`Host::on_parameter`](../struct.Host.html#method.on_parameter).
+    pub fn on_parameter(&mut self, index: usize, value: ValuePtr) {
+        self.host.on_parameter(self.tag, index, value);
+    }
+
+    /// See [`Host::on_hint`](../struct.Host.html#method.on_hint).
+    pub fn on_hint(&mut self, text: String) {
+        self.host.on_hint(self.tag, text);
+    }
+
+    /// See [`Host::midi_out`](../struct.Host.html#method.midi_out).
+    pub fn midi_out(&mut self, message: impl Into<MidiMessage>) {
+        self.host.midi_out(self.tag, message);
+    }
+
+    /// **Warning: this function is not very performant, so avoid using it if possible.**
+    ///
+    /// Keep the mixer thread out of the plugin until the lock is dropped. Unlike
+    /// [`lock_mix`](#method.lock_mix), it doesn't freeze the audio.
+    pub fn lock_plugin(&mut self) -> Lock<'_> {
+        self.host.lock_plugin(self.tag);
+        Lock {
+            host: self.host,
+            tag: self.tag,
+            kind: LockKind::Plugin,
+        }
+    }
+
+    /// No more voices are created and there's no rendering until the lock is dropped.
+    pub fn lock_mix(&mut self) -> Lock<'_> {
+        self.host.lock_mix();
+        Lock {
+            host: self.host,
+            tag: self.tag,
+            kind: LockKind::Mix,
+        }
+    }
+
+    /// Like [`lock_mix`](#method.lock_mix), but also stops all sound.
+    pub fn suspend_out(&mut self) -> Lock<'_> {
+        self.host.suspend_out();
+        Lock {
+            host: self.host,
+            tag: self.tag,
+            kind: LockKind::Output,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum LockKind {
+    Plugin,
+    Mix,
+    Output,
+}
+
+/// Lock taken with [`GuiContext`](struct.GuiContext.html). Released when dropped.
+#[derive(Debug)]
+#[must_use = "the lock is released right away if it's not kept"]
+pub struct Lock<'a> {
+    host: &'a mut Host,
+    tag: plugin::Tag,
+    kind: LockKind,
+}
+
+impl Drop for Lock<'_> {
+    fn drop(&mut self) {
+        match self.kind {
+            LockKind::Plugin => self.host.unlock_plugin(self.tag),
+            LockKind::Mix => self.host.unlock_mix(),
+            LockKind::Output => self.host.resume_out(),
+        }
+    }
+}
+
+/// Host functions for the mixer thread, while rendering.
+#[derive(Debug)]
+pub struct AudioContext<'a> {
+    host: &'a mut Host,
+    routing: &'a mut Routing,
+    tag: plugin::Tag,
+    block_len: usize,
+}
+
+impl<'a> AudioContext<'a> {
+    pub(crate) fn new(
+        host: &'a mut Host,
+        routing: &'a mut Routing,
+        tag: plugin::Tag,
+        block_len: usize,
+    ) -> Self {
+        Self {
+            host,
+            routing,
+            tag,
+            block_len,
+        }
+    }
+
+    /// The plugin's tag.
+    pub fn tag(&self) -> plugin::Tag {
+        self.tag
+    }
+
+    /// Length of the block being rendered.
+    pub fn block_len(&self) -> usize {
+        self.block_len
+    }
+
+    /// See [`Host::on_message`](../struct.Host.html#method.on_message).
+    pub fn on_message<T: MixerMessage>(&mut self, message: T) -> T::Return {
+        self.host.on_message(self.tag, message)
+    }
+
+    /// See [`Host::on_parameter`](../struct.Host.html#method.on_parameter).
+    pub fn on_parameter(&mut self, index: usize, value: ValuePtr) {
+        self.host.on_parameter(self.tag, index, value);
+    }
+
+    /// See [`Host::on_controller`](../struct.Host.html#method.on_controller).
+    pub fn on_controller(&mut self, index: usize, value: u16) {
+        self.host.on_controller(self.tag, index, value);
+    }
+
+    /// See [`Host::midi_out`](../struct.Host.html#method.midi_out).
+    pub fn midi_out(&mut self, message: impl Into<MidiMessage>) {
+        self.host.midi_out(self.tag, message);
+    }
+
+    /// See [`Host::midi_out_del`](../struct.Host.html#method.midi_out_del).
+    pub fn midi_out_del(&mut self, message: impl Into<MidiMessage>) {
+        self.host.midi_out_del(self.tag, message);
+    }
+
+    /// The plugin's inputs and outputs (see [`Routing`](../routing/struct.Routing.html)). They're
+    /// refreshed when the host changes them.
+    pub fn routing(&mut self) -> &mut Routing {
+        self.routing
+    }
+
+    /// Get one of the host's buffers, sized to the block (see [`Buffer`](../enum.Buffer.html)).
+    ///
+    /// Returns `None` if there's no such buffer.
+    pub fn buffer(&mut self, kind: Buffer) -> Option<&mut [[f32; 2]]> {
+        self.host.buffer(self.tag, kind, self.block_len)
+    }
+}
+
+/// Host functions for the mixer thread, between blocks. There are no buffers to get.
+#[derive(Debug)]
+pub struct TickContext<'a> {
+    host: &'a mut Host,
+    tag: plugin::Tag,
+}
+
+impl<'a> TickContext<'a> {
+    pub(crate) fn new(host: &'a mut Host, tag: plugin::Tag) -> Self {
+        Self { host, tag }
+    }
+
+    /// The plugin's tag.
+    pub fn tag(&self) -> plugin::Tag {
+        self.tag
+    }
+
+    /// See [`Host::on_message`](../struct.Host.html#method.on_message).
+    pub fn on_message<T: MixerMessage>(&mut self, message: T) -> T::Return {
+        self.host.on_message(self.tag, message)
+    }
+
+    /// See [`Host::on_parameter`](../struct.Host.html#method.on_parameter).
+    pub fn on_parameter(&mut self, index: usize, value: ValuePtr) {
+        self.host.on_parameter(self.tag, index, value);
+    }
+
+    /// See [`Host::on_controller`](../struct.Host.html#method.on_controller).
+    pub fn on_controller(&mut self, index: usize, value: u16) {
+        self.host.on_controller(self.tag, index, value);
+    }
+
+    /// See [`Host::midi_out`](../struct.Host.html#method.midi_out).
+    pub fn midi_out(&mut self, message: impl Into<MidiMessage>) {
+        self.host.midi_out(self.tag, message);
+    }
+
+    /// See [`Host::midi_out_del`](../struct.Host.html#method.midi_out_del).
+    pub fn midi_out_del(&mut self, message: impl Into<MidiMessage>) {
+        self.host.midi_out_del(self.tag, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::{Effect, InfoBuilder};
+    use crate::testing::{HostCall, MockHost};
+
+    // Reports the block length as a controller value and locks itself in idle.
+    #[derive(Debug, Default)]
+    struct Reporter;
+
+    crate::test_plugin!(
+        Reporter,
+        InfoBuilder::new("Reporter", "Reporter", 0).build(),
+        {
+            fn idle(&mut self, ctx: &mut GuiContext<'_>) {
+                let _lock = ctx.lock_plugin();
+                // the state would be swapped here
+            }
+
+            fn tick(&mut self, ctx: &mut TickContext<'_>) {
+                ctx.on_controller(0, 0);
+            }
+        }
+    );
+
+    impl Effect for Reporter {
+        fn render(
+            &mut self,
+            ctx: &mut AudioContext<'_>,
+            _input: &[[f32; 2]],
+            _output: &mut [[f32; 2]],
+        ) {
+            let value = ctx.block_len() as u16;
+            ctx.on_controller(0, value);
+        }
+    }
+
+    #[test]
+    fn contexts_by_thread() {
+        let host = MockHost::new();
+        let mut plugin = host.create_effect::<Reporter>();
+
+        plugin.tick();
+        plugin.eff_render(&[[1.0; 2]; 4], &mut [[0.0; 2]; 4]);
+        plugin.idle();
+
+        assert_eq!(
+            vec![
+                HostCall::Controller { index: 0, value: 0 },
+                HostCall::Controller { index: 0, value: 4 },
+                HostCall::LockPlugin,
+                HostCall::UnlockPlugin,
+            ],
+            host.take_calls()
+        );
+    }
+}
@@ -4,11 +4,10 @@
 //! [`PromptBuilder::default`] to build your prompt, then use its
 //! [`PromptBuilder::show`](struct.PromptBuilder.html#method.show) method to get the result
 //! ([`Prompt`](struct.Prompt.html)).
-use std::ffi::CString;
 use std::os::raw::{c_char, c_int, c_void};
 
+use crate::cstr;
 use crate::host::Host;
-use crate::AsRawPtr;
 
 /// The result returned by [`PromptBuilder::show`](../struct.PromptBuilder.html#method.show) if the
 /// user closed the window by clicking OK.
@@ -59,34 +58,29 @@ impl PromptBuilder {
     /// window by clicking OK. The method returns `None` otherwise.
     pub fn show(self, host: &mut Host, message: String) -> Option<Prompt> {
         let mut color = self.with_color as c_int - 1;
-        let value = CString::default().into_raw();
+        let caption = cstr::to_c_string(&message);
+        // the host writes up to 256 chars
+        let mut value = [0_u8; cstr::BUF_LEN];
 
         if unsafe {
             !prompt_show(
                 *host.host_ptr.get_mut(),
                 self.x.map(|v| v as c_int).unwrap_or(-1),
                 self.y.map(|v| v as c_int).unwrap_or(-1),
-                message.as_raw_ptr() as *mut c_char,
-                value,
+                caption.as_ptr() as *mut c_char,
+                value.as_mut_ptr() as *mut c_char,
                 &mut color,
             )
         } {
-            unsafe { CString::from_raw(value) };
             return None;
         }
 
         Some(Prompt {
-            value: self.value_from_raw(value),
+            value: cstr::read_from(&value),
             color: self.color_to_result(color),
         })
     }
 
-    fn value_from_raw(&self, ptr: *mut c_char) -> String {
-        unsafe { CString::from_raw(ptr) }
-            .to_string_lossy()
-            .to_string()
-    }
-
     fn color_to_result(&self, color: c_int) -> Option<i32> {
         if self.with_color {
             Some(i32::from_be(color))
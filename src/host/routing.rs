@@ -0,0 +1,367 @@
+//! Multiple inputs and outputs of a plugin.
+//!
+//! FL Studio can route several mixer tracks into an effect (sidechain) and route a plugin's
+//! output into several tracks. [`Routing`](struct.Routing.html) keeps the list of them and gives
+//! access to their buffers inside [`Effect::render`](../../plugin/trait.Effect.html#tymethod.render)
+//! or [`Generator::render`](../../plugin/trait.Generator.html#tymethod.render). It's refreshed
+//! when the routing changes, before the plugin gets
+//! [`host::Message::RoutingChanged`](../enum.Message.html#variant.RoutingChanged):
+//!
+//! ```ignore
+//! fn render(&mut self, ctx: &mut AudioContext<'_>, input: &[[f32; 2]], output: &mut [[f32; 2]]) {
+//!     let routing = ctx.routing();
+//!     let key = routing.input(1).map(<[_]>::to_vec);
+//!     // ...
+//!     if let Some(mut send) = routing.output(1) {
+//!         for (dst, src) in send.iter_mut().zip(output.iter()) {
+//!             dst[0] += src[0];
+//!             dst[1] += src[1];
+//!         }
+//!     }
+//! }
+//! ```
+use std::ops::{Deref, DerefMut};
+use std::slice;
+use std::sync::atomic::Ordering;
+
+use log::trace;
+
+use super::{host_get_input_buf, host_get_output_buf};
+use crate::host::Host;
+use crate::ownership::RustOwned;
+use crate::plugin::message::{GetInName, GetNumInOut, GetOutName};
+use crate::plugin::{self, render_block_len};
+use crate::{cstr, intptr_t, FlMessage, TNameColor};
+
+/// Input buffer flag. Tells if the buffer is filled.
+const IO_FILLED: u32 = 1;
+/// Output buffer flag. Passed before adding to the buffer.
+const IO_LOCK: u32 = 0;
+/// Output buffer flag. Passed after adding to the buffer.
+const IO_UNLOCK: u32 = 1;
+// ports kept without allocating the lists and the names
+const PORTS: usize = 16;
+
+/// A routed input or output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Port {
+    /// Index of the port, starting from 1 (0 is the buffer passed to render).
+    pub index: usize,
+    /// User-defined name (can be empty).
+    pub name: String,
+    /// Visible name (can be guessed).
+    pub vis_name: String,
+    /// Color.
+    pub color: u8,
+    /// Mixer track number.
+    pub track: usize,
+}
+
+impl Port {
+    fn new() -> Self {
+        Self {
+            index: 0,
+            name: String::with_capacity(cstr::BUF_LEN),
+            vis_name: String::with_capacity(cstr::BUF_LEN),
+            color: 0,
+            track: 0,
+        }
+    }
+
+    // Copies the names into the strings the port already has.
+    fn update(&mut self, index: usize, name_color: Option<&TNameColor>) {
+        self.index = index;
+        match name_color {
+            Some(name_color) => {
+                cstr::read_into(&name_color.name, &mut self.name);
+                cstr::read_into(&name_color.vis_name, &mut self.vis_name);
+                self.color = name_color.color as u8;
+                self.track = name_color.index as usize;
+            }
+            None => {
+                self.name.clear();
+                self.vis_name.clear();
+                self.color = 0;
+                self.track = 0;
+            }
+        }
+    }
+}
+
+// Reuses the port at `index` (starting from 1), adding one if there are more ports than before.
+fn port_at(ports: &mut Vec<Port>, index: usize) -> &mut Port {
+    if ports.len() < index {
+        ports.push(Port::new());
+    }
+    &mut ports[index - 1]
+}
+
+/// Inputs and outputs of the plugin. Get it from
+/// [`AudioContext::routing`](../context/struct.AudioContext.html#method.routing).
+#[derive(Debug)]
+pub struct Routing {
+    host: Host,
+    tag: plugin::Tag,
+    inputs: Vec<Port>,
+    num_inputs: usize,
+    outputs: Vec<Port>,
+    num_outputs: usize,
+    // lent to the host for each name
+    name_color: RustOwned<TNameColor>,
+}
+
+impl Routing {
+    // The lists are empty until the routing is refreshed.
+    pub(crate) fn new(host: &Host, tag: plugin::Tag) -> Self {
+        Self {
+            host: Host::new(host.host_ptr.load(Ordering::Relaxed)),
+            tag,
+            inputs: (0..PORTS).map(|_| Port::new()).collect(),
+            num_inputs: 0,
+            outputs: (0..PORTS).map(|_| Port::new()).collect(),
+            num_outputs: 0,
+            name_color: RustOwned::new(TNameColor {
+                name: [0; cstr::BUF_LEN],
+                vis_name: [0; cstr::BUF_LEN],
+                color: 0,
+                index: 0,
+            }),
+        }
+    }
+
+    /// Ask the host for the inputs and outputs.
+    ///
+    /// The ports and their names are reused, so this doesn't allocate unless there are more
+    /// ports than before (or more than 16 at first).
+    pub fn refresh(&mut self) {
+        let (host, tag) = (&mut self.host, self.tag);
+        self.num_inputs = host.on_message(tag, GetNumInOut::Inputs);
+        self.num_outputs = host.on_message(tag, GetNumInOut::Outputs);
+        for index in 1..=self.num_inputs {
+            let found = GetInName(index).fill(tag, host, &mut self.name_color);
+            let name_color = found.then_some(&*self.name_color.get_mut());
+            port_at(&mut self.inputs, index).update(index, name_color);
+        }
+        for index in 1..=self.num_outputs {
+            let found = GetOutName(index).fill(tag, host, &mut self.name_color);
+            let name_color = found.then_some(&*self.name_color.get_mut());
+            port_at(&mut self.outputs, index).update(index, name_color);
+        }
+        trace!(
+            "routing refreshed: {:?} -> {:?}",
+            self.inputs(),
+            self.outputs()
+        );
+    }
+
+    // Refreshes on RoutingChanged (25) and when the plugin is enabled (SetEnabled, 11). The raw
+    // message is matched, so the others aren't converted.
+    pub(crate) fn on_message(&mut self, message: &FlMessage) {
+        match message.id {
+            25 => self.refresh(),
+            11 if message.value != 0 => self.refresh(),
+            _ => {}
+        }
+    }
+
+    /// The inputs routed to the plugin.
+    pub fn inputs(&self) -> &[Port] {
+        &self.inputs[..self.num_inputs]
+    }
+
+    /// The outputs the plugin is routed to.
+    pub fn outputs(&self) -> &[Port] {
+        &self.outputs[..self.num_outputs]
+    }
+
+    /// Get the input buffer `index` (starting from 1), sized to the block being rendered.
+    ///
+    /// Returns `None` outside of render, if there's no such input or it's empty (silent).
+    pub fn input(&mut self, index: usize) -> Option<&[[f32; 2]]> {
+        let len = render_block_len()?;
+        let buf = unsafe {
+            host_get_input_buf(*self.host.host_ptr.get_mut(), self.tag.0, index as intptr_t)
+        };
+        if buf.buffer.is_null() || buf.flags & IO_FILLED == 0 {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts(buf.buffer as *const [f32; 2], len) })
+    }
+
+    /// Lock the output buffer `index` (starting from 1), sized to the block being rendered.
+    ///
+    /// The buffer is add-only: add your signal to it, don't overwrite it. It's unlocked when the
+    /// guard is dropped.
+    ///
+    /// Returns `None` outside of render or if there's no such output.
+    pub fn output(&mut self, index: usize) -> Option<OutputGuard<'_>> {
+        let len = render_block_len()?;
+        let host_ptr = *self.host.host_ptr.get_mut();
+        let buf = unsafe { host_get_output_buf(host_ptr, self.tag.0, index as intptr_t, IO_LOCK) };
+        if buf.buffer.is_null() {
+            return None;
+        }
+        Some(OutputGuard {
+            buffer: unsafe { slice::from_raw_parts_mut(buf.buffer as *mut [f32; 2], len) },
+            host: &mut self.host,
+            tag: self.tag,
+            index,
+        })
+    }
+}
+
+/// Locked output buffer. Unlocks it when dropped.
+#[derive(Debug)]
+pub struct OutputGuard<'a> {
+    buffer: &'a mut [[f32; 2]],
+    host: &'a mut Host,
+    tag: plugin::Tag,
+    index: usize,
+}
+
+impl Deref for OutputGuard<'_> {
+    type Target = [[f32; 2]];
+
+    fn deref(&self) -> &Self::Target {
+        self.buffer
+    }
+}
+
+impl DerefMut for OutputGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.buffer
+    }
+}
+
+impl Drop for OutputGuard<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            host_get_output_buf(
+                *self.host.host_ptr.get_mut(),
+                self.tag.0,
+                self.index as intptr_t,
+                IO_UNLOCK,
+            )
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::{self, context::AudioContext};
+    use crate::plugin::{Effect, InfoBuilder};
+    use crate::testing::{HostCall, MockHost, Reply};
+
+    // Ducks the input by the sidechain and sends the result to the first output.
+    #[derive(Debug, Default)]
+    struct Ducker;
+
+    crate::test_plugin!(Ducker, InfoBuilder::new("Ducker", "Ducker", 0).build());
+
+    impl Effect for Ducker {
+        fn render(
+            &mut self,
+            ctx: &mut AudioContext<'_>,
+            input: &[[f32; 2]],
+            output: &mut [[f32; 2]],
+        ) {
+            let routing = ctx.routing();
+            assert_eq!("Kick", routing.inputs()[0].name);
+            let key = routing.input(1).map(<[_]>::to_vec);
+            for (i, (o, x)) in output.iter_mut().zip(input).enumerate() {
+                let gain = key.as_ref().map_or(1.0, |key| 1.0 - key[i][0]);
+                *o = [x[0] * gain, x[1] * gain];
+            }
+            if let Some(mut send) = routing.output(1) {
+                for (s, o) in send.iter_mut().zip(output.iter()) {
+                    s[0] += o[0];
+                    s[1] += o[1];
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sidechain_and_send() {
+        let host = MockHost::new();
+        host.set_input(1, vec![[0.5; 2]; 8]);
+        host.set_output(1, 8);
+        host.queue_reply(
+            crate::testing::id::GET_IN_NAME,
+            Reply::NameColor("Kick".into(), "Kick".into(), 3, 2),
+        );
+        let mut plugin = host.create_effect::<Ducker>();
+        plugin.dispatch(host::Message::RoutingChanged);
+
+        let mut output = [[0.0; 2]; 4];
+        plugin.eff_render(&[[1.0; 2]; 4], &mut output);
+        assert_eq!([[0.5; 2]; 4], output);
+        // only the block is touched
+        let mut sent = vec![[0.5; 2]; 4];
+        sent.extend(vec![[0.0; 2]; 4]);
+        assert_eq!(Some(sent), host.output(1));
+
+        let calls = host.take_calls();
+        let locks: Vec<_> = calls
+            .iter()
+            .filter(|call| matches!(call, HostCall::LockOutput(_) | HostCall::UnlockOutput(_)))
+            .collect();
+        assert_eq!(
+            vec![&HostCall::LockOutput(1), &HostCall::UnlockOutput(1)],
+            locks
+        );
+    }
+
+    #[test]
+    fn no_buffers_outside_render() {
+        let host = MockHost::new();
+        host.set_input(1, vec![[1.0; 2]; 4]);
+        host.set_output(1, 4);
+        host.queue_reply(
+            crate::testing::id::GET_IN_NAME,
+            Reply::NameColor("Kick".into(), "Kick".into(), 3, 2),
+        );
+        let mut routing = Routing::new(&host.host(), plugin::Tag(0));
+
+        routing.refresh();
+        assert_eq!("Kick", routing.inputs()[0].name);
+        assert_eq!(2, routing.inputs()[0].track);
+        assert_eq!(1, routing.outputs()[0].index);
+        assert!(routing.input(1).is_none());
+        assert!(routing.output(1).is_none());
+        assert!(!host
+            .calls()
+            .iter()
+            .any(|call| matches!(call, HostCall::LockOutput(_))));
+    }
+
+    #[test]
+    fn refresh_reuses_names() {
+        let host = MockHost::new();
+        host.set_input(1, vec![[0.0; 2]; 4]);
+        host.set_input(2, vec![[0.0; 2]; 4]);
+        host.queue_reply(
+            crate::testing::id::GET_IN_NAME,
+            Reply::NameColor("Kick".into(), "Kick".into(), 3, 2),
+        );
+        let mut routing = Routing::new(&host.host(), plugin::Tag(0));
+        routing.refresh();
+        let names = routing.inputs()[0].name.as_ptr();
+
+        host.queue_reply(
+            crate::testing::id::GET_IN_NAME,
+            Reply::NameColor("Snare".into(), "Snare".into(), 4, 3),
+        );
+        routing.on_message(&FlMessage {
+            id: 25,
+            index: 0,
+            value: 0,
+        });
+        assert_eq!("Snare", routing.inputs()[0].name);
+        assert_eq!(names, routing.inputs()[0].name.as_ptr());
+        // the second input has no name
+        assert_eq!("", routing.inputs()[1].vis_name);
+        assert_eq!(2, routing.inputs()[1].index);
+    }
+}
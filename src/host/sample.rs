@@ -0,0 +1,414 @@
+//! Samples loaded by the host.
+//!
+//! FL Studio decodes audio files for the plugin. [`Sample`](struct.Sample.html) holds a loaded
+//! sample and closes it when dropped:
+//!
+//! ```ignore
+//! let sample = Sample::load(&host, "kick.wav", SampleLoadFlags::empty())?;
+//! let info = sample.info();
+//! for frame in sample.frames() {
+//!     // ...
+//! }
+//! for region in sample.regions() {
+//!     // ...
+//! }
+//! ```
+//!
+//! Generators that set [`InfoBuilder::get_chan_sample`](
+//! ../../plugin/struct.InfoBuilder.html#method.get_chan_sample) receive the sample loaded in their
+//! channel with [`host::Message::ChanSample`](../enum.Message.html#variant.ChanSample). Use
+//! [`Sample::from_channel`](struct.Sample.html#method.from_channel) to access it.
+use std::ffi::c_void;
+use std::mem;
+use std::os::raw::{c_char, c_int};
+use std::slice;
+use std::sync::atomic::Ordering;
+
+use log::trace;
+
+use crate::host::Host;
+use crate::{cstr, intptr_t, SampleLoadFlags};
+
+/// Sample format requested from the host (32-bit float).
+const FORMAT_32F: c_int = 1;
+
+extern "C" {
+    fn host_load_sample(
+        host: *mut c_void,
+        handle: *mut intptr_t,
+        file_name: *mut c_char,
+        flags: c_int,
+    ) -> bool;
+    fn host_close_sample(host: *mut c_void, handle: intptr_t);
+    fn host_get_sample_info(host: *mut c_void, handle: intptr_t, info: *mut TSampleInfo);
+    fn host_get_sample_region(
+        host: *mut c_void,
+        handle: intptr_t,
+        region_num: c_int,
+        region: *mut TSampleRegion,
+    );
+}
+
+#[repr(C, packed)]
+struct TSampleInfo {
+    size: c_int,
+    data: *mut c_void,
+    length: c_int,
+    solid_length: c_int,
+    loop_start: c_int,
+    loop_end: c_int,
+    smp_rate_conv: f64,
+    num_regions: c_int,
+    num_beats: f32,
+    tempo: f32,
+    num_chans: c_int,
+    format: c_int,
+    reserved: [c_int; 13],
+}
+
+#[repr(C)]
+struct TSampleRegion {
+    sample_start: c_int,
+    sample_end: c_int,
+    name: [u8; cstr::BUF_LEN],
+    info: [u8; cstr::BUF_LEN],
+    time: f32,
+    key_num: c_int,
+    reserved: [c_int; 4],
+}
+
+/// Sample handle, as the host knows it.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Handle(pub intptr_t);
+
+/// Sample info.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Info {
+    /// Length in frames.
+    pub length: usize,
+    /// Length without ending silence.
+    pub solid_length: usize,
+    /// Loop start and end, if the sample has them.
+    pub loop_points: Option<(usize, usize)>,
+    /// Sample rate relative to the host's one (see [`sample_rate`](#method.sample_rate)).
+    pub rate_conversion: f64,
+    /// Number of regions (see [`Sample::regions`](struct.Sample.html#method.regions)).
+    pub num_regions: usize,
+    /// Length in beats.
+    pub num_beats: f32,
+    /// Tempo.
+    pub tempo: f32,
+}
+
+impl Info {
+    /// Sample rate of the sample, given the host's sample rate.
+    pub fn sample_rate(&self, host_rate: f64) -> f64 {
+        host_rate * self.rate_conversion
+    }
+}
+
+impl From<TSampleInfo> for Info {
+    fn from(info: TSampleInfo) -> Self {
+        let loop_points = if info.loop_start < 0 {
+            None
+        } else {
+            Some((info.loop_start as usize, info.loop_end.max(0) as usize))
+        };
+        Self {
+            length: info.length.max(0) as usize,
+            solid_length: info.solid_length.max(0) as usize,
+            loop_points,
+            rate_conversion: info.smp_rate_conv,
+            num_regions: info.num_regions.max(0) as usize,
+            num_beats: info.num_beats,
+            tempo: info.tempo,
+        }
+    }
+}
+
+/// Sample region.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    /// First frame.
+    pub start: usize,
+    /// Last frame.
+    pub end: usize,
+    /// Name.
+    pub name: String,
+    /// Info.
+    pub info: String,
+    /// Beat position, mainly for loop dumping.
+    pub time: Option<f32>,
+    /// Linked MIDI note number.
+    pub key: Option<u8>,
+}
+
+impl From<TSampleRegion> for Region {
+    fn from(region: TSampleRegion) -> Self {
+        Self {
+            start: region.sample_start.max(0) as usize,
+            end: region.sample_end.max(0) as usize,
+            name: cstr::read_from(&region.name),
+            info: cstr::read_from(&region.info),
+            time: Some(region.time).filter(|time| *time >= 0.0),
+            key: Some(region.key_num)
+                .filter(|key| (0..128).contains(key))
+                .map(|key| key as u8),
+        }
+    }
+}
+
+/// Sample loaded by the host.
+///
+/// Frames are stereo 32-bit float. The sample is closed when dropped, unless it belongs to the
+/// channel (see [`from_channel`](#method.from_channel)).
+#[derive(Debug)]
+pub struct Sample {
+    host: Host,
+    handle: Handle,
+    file_name: String,
+    info: Info,
+    owned: bool,
+}
+
+impl Sample {
+    /// Load the sample from `file_name`.
+    ///
+    /// With [`SampleLoadFlags::SHOW_DIALOG`](../../struct.SampleLoadFlags.html), the user selects
+    /// the file, starting from `file_name` (see [`file_name`](#method.file_name)).
+    ///
+    /// Returns `None` if the host couldn't load it or the user cancelled the dialog.
+    pub fn load(host: &Host, file_name: &str, flags: SampleLoadFlags) -> Option<Self> {
+        Self::open(host, Handle(0), file_name, flags, true)
+    }
+
+    /// Access the sample loaded in the plugin's channel, received with
+    /// [`host::Message::ChanSample`](../enum.Message.html#variant.ChanSample).
+    ///
+    /// The channel owns the sample, so it isn't closed when dropped.
+    pub fn from_channel(host: &Host, handle: Handle) -> Option<Self> {
+        Self::open(host, handle, "", SampleLoadFlags::GET_NAME, false)
+    }
+
+    fn open(
+        host: &Host,
+        handle: Handle,
+        file_name: &str,
+        flags: SampleLoadFlags,
+        owned: bool,
+    ) -> Option<Self> {
+        let host_ptr = host.host_ptr.load(Ordering::Relaxed);
+        let mut raw_handle = handle.0;
+        let mut name_buf = [0_u8; cstr::BUF_LEN];
+        cstr::write_to(file_name, &mut name_buf);
+
+        let loaded = unsafe {
+            host_load_sample(
+                host_ptr,
+                &mut raw_handle,
+                name_buf.as_mut_ptr() as *mut c_char,
+                flags.bits() as c_int,
+            )
+        };
+        if !loaded || raw_handle == 0 {
+            trace!("sample {:?} is not loaded", file_name);
+            return None;
+        }
+
+        let sample = Self {
+            host: Host::new(host_ptr),
+            handle: Handle(raw_handle),
+            file_name: cstr::read_from(&name_buf),
+            info: raw_info(host_ptr, raw_handle).into(),
+            owned,
+        };
+        trace!("loaded sample {:?}: {:?}", sample.file_name, sample.info);
+        Some(sample)
+    }
+
+    /// The handle.
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    /// File name of the sample, as reported by the host.
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// Sample info, queried on load.
+    pub fn info(&self) -> &Info {
+        &self.info
+    }
+
+    /// The decoded frames, as 32-bit float stereo.
+    pub fn frames(&self) -> &[[f32; 2]] {
+        let info = raw_info(self.host.host_ptr.load(Ordering::Relaxed), self.handle.0);
+        let (data, length) = (info.data, info.length);
+        if data.is_null() || length <= 0 {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(data as *const [f32; 2], length as usize) }
+    }
+
+    /// Get the region `index`.
+    pub fn region(&self, index: usize) -> Option<Region> {
+        if index >= self.info.num_regions {
+            return None;
+        }
+        let mut region: TSampleRegion = unsafe { mem::zeroed() };
+        unsafe {
+            host_get_sample_region(
+                self.host.host_ptr.load(Ordering::Relaxed),
+                self.handle.0,
+                index as c_int,
+                &mut region,
+            )
+        };
+        Some(region.into())
+    }
+
+    /// Iterate over the regions.
+    pub fn regions(&self) -> impl Iterator<Item = Region> + '_ {
+        (0..self.info.num_regions).filter_map(move |index| self.region(index))
+    }
+}
+
+// The data is in the format asked here, which isn't the one passed to LoadSample.
+fn raw_info(host_ptr: *mut c_void, handle: intptr_t) -> TSampleInfo {
+    let mut info: TSampleInfo = unsafe { mem::zeroed() };
+    info.size = size_of::<TSampleInfo>() as c_int;
+    info.num_chans = 2;
+    info.format = FORMAT_32F;
+    unsafe { host_get_sample_info(host_ptr, handle, &mut info) };
+    info
+}
+
+impl Drop for Sample {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { host_close_sample(*self.host.host_ptr.get_mut(), self.handle.0) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host;
+    use crate::host::context::AudioContext;
+    use crate::plugin::{self, Generator, InfoBuilder};
+    use crate::testing::{HostCall, MockHost};
+    use crate::voice::{self, ReceiveVoiceHandler, SendVoiceHandler};
+    use crate::DispatchResult;
+
+    #[test]
+    fn load_and_close() {
+        let host = MockHost::new();
+        let regions = vec![Region {
+            start: 0,
+            end: 2,
+            name: "Hit".into(),
+            info: String::new(),
+            time: None,
+            key: Some(36),
+        }];
+        host.add_sample("kick.wav", vec![[0.5, -0.5]; 4], regions.clone());
+
+        assert!(Sample::load(&host.host(), "snare.wav", SampleLoadFlags::empty()).is_none());
+        let sample = Sample::load(&host.host(), "kick.wav", SampleLoadFlags::empty()).unwrap();
+        assert_eq!("kick.wav", sample.file_name());
+        assert_eq!(4, sample.info().length);
+        assert_eq!(None, sample.info().loop_points);
+        assert_eq!(44100.0, sample.info().sample_rate(44100.0));
+        assert_eq!(&[[0.5, -0.5]; 4], sample.frames());
+        assert_eq!(regions, sample.regions().collect::<Vec<_>>());
+        assert!(sample.region(1).is_none());
+
+        let handle = sample.handle();
+        drop(sample);
+        assert!(host.calls().contains(&HostCall::CloseSample(handle.0)));
+    }
+
+    #[derive(Debug)]
+    struct Sampler {
+        host: Host,
+        sample: Option<Sample>,
+    }
+
+    crate::test_plugin!(
+        Sampler,
+        InfoBuilder::new("Sampler", "Sampler", 0)
+            .get_note_input()
+            .get_chan_sample()
+            .build(),
+        {
+            fn new(host: Host, _tag: plugin::Tag) -> Self {
+                Self { host, sample: None }
+            }
+
+            fn handle_message(&mut self, message: host::Message<'_>) -> DispatchResult {
+                if let host::Message::ChanSample(handle) = message {
+                    self.sample = Sample::from_channel(&self.host, handle);
+                }
+                DispatchResult::NONE
+            }
+        }
+    );
+
+    #[derive(Debug)]
+    struct NoVoices;
+
+    impl ReceiveVoiceHandler for NoVoices {
+        fn trigger(&mut self, _params: voice::Params, _tag: voice::Tag) {
+            unreachable!("no notes are played")
+        }
+
+        fn release(&mut self, _tag: voice::Tag) {}
+
+        fn kill(&mut self, _tag: voice::Tag) {}
+
+        fn out_handler(&mut self) -> Option<&mut dyn SendVoiceHandler> {
+            None
+        }
+    }
+
+    impl Generator for Sampler {
+        type Voices = NoVoices;
+
+        fn voices(_host: &Host, _tag: plugin::Tag) -> Self::Voices {
+            NoVoices
+        }
+
+        fn render(
+            &mut self,
+            _ctx: &mut AudioContext<'_>,
+            output: &mut [[f32; 2]],
+            _voices: &mut Self::Voices,
+        ) {
+            if let Some(sample) = self.sample.as_ref() {
+                for (o, s) in output.iter_mut().zip(sample.frames()) {
+                    *o = *s;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn channel_sample() {
+        let host = MockHost::new();
+        let handle = host.add_sample("loop.wav", vec![[0.25; 2]; 8], Vec::new());
+        let mut plugin = host.create_generator::<Sampler>();
+        plugin.dispatch(host::Message::ChanSample(handle));
+
+        let mut output = [[0.0; 2]; 4];
+        plugin.gen_render(&mut output);
+        assert_eq!([[0.25; 2]; 4], output);
+
+        drop(plugin);
+        assert!(!host
+            .calls()
+            .iter()
+            .any(|call| matches!(call, HostCall::CloseSample(_))));
+    }
+}
@@ -0,0 +1,310 @@
+//! Transport and musical time.
+//!
+//! FL Studio tells the plugin about the tempo, the time signature and the playing state with
+//! separate messages and events. [`TransportState`](struct.TransportState.html) collects them
+//! and keeps track of the song position, block by block:
+//!
+//! ```ignore
+//! fn handle_message(&mut self, message: host::Message<'_>) -> DispatchResult {
+//!     self.transport.on_message(&message);
+//!     DispatchResult::NONE
+//! }
+//!
+//! fn process_event(&mut self, event: host::Event) {
+//!     self.transport.on_event(&event);
+//! }
+//!
+//! fn render(&mut self, _ctx: &mut AudioContext<'_>, input: &[[f32; 2]], output: &mut [[f32; 2]]) {
+//!     self.transport.begin_block(output.len());
+//!     for (offset, (o, i)) in output.iter_mut().zip(input).enumerate() {
+//!         // a quarter note LFO
+//!         let lfo = (self.transport.phase_at(offset, 1.0) * TAU).sin() as f32;
+//!         // ...
+//!     }
+//! }
+//! ```
+use std::sync::atomic::Ordering;
+
+use log::trace;
+
+use crate::host::{self, Host};
+use crate::plugin;
+use crate::plugin::message::{GetMixingTime, GetSelTime};
+use crate::{TimeFormat, TimeSignature};
+
+/// Transport state and song position.
+///
+/// The position is in beats (quarter notes). It's taken from the host when playback starts or
+/// the position jumps, and advanced by the rendered blocks in between.
+#[derive(Debug)]
+pub struct TransportState {
+    host: Host,
+    tag: plugin::Tag,
+    playing: bool,
+    tempo: f64,
+    sample_rate: f64,
+    samples_per_tick: Option<f64>,
+    time_signature: TimeSignature,
+    // position at the start of the current block
+    beats: f64,
+    block_len: usize,
+    selection: Option<(f64, f64)>,
+    needs_sync: bool,
+}
+
+impl TransportState {
+    /// Initializer. Starts stopped, at 120 BPM, 44100 Hz, 4/4 and 96 PPQ until the host tells
+    /// otherwise.
+    pub fn new(host: &Host, tag: plugin::Tag) -> Self {
+        Self {
+            host: Host::new(host.host_ptr.load(Ordering::Relaxed)),
+            tag,
+            playing: false,
+            tempo: 120.0,
+            sample_rate: 44100.0,
+            samples_per_tick: None,
+            time_signature: TimeSignature {
+                steps_per_bar: 16,
+                steps_per_beat: 4,
+                ppq: 96,
+            },
+            beats: 0.0,
+            block_len: 0,
+            selection: None,
+            needs_sync: true,
+        }
+    }
+
+    /// Update from a message. Call it from
+    /// [`Plugin::handle_message`](../../plugin/trait.Plugin.html#method.handle_message).
+    pub fn on_message(&mut self, message: &host::Message<'_>) {
+        match message {
+            host::Message::SetPlaying(playing) => {
+                self.playing = *playing;
+                self.needs_sync = true;
+            }
+            host::Message::SongPosChanged => self.needs_sync = true,
+            host::Message::SetTimeSig(signature) => {
+                self.time_signature = signature.clone();
+                self.needs_sync = true;
+            }
+            host::Message::SetSampleRate(rate) => self.sample_rate = *rate as f64,
+            host::Message::SetSamplesPerTick(samples) if *samples > 0.0 => {
+                self.samples_per_tick = Some(*samples as f64)
+            }
+            _ => {}
+        }
+    }
+
+    /// Update from an event. Call it from
+    /// [`Plugin::process_event`](../../plugin/trait.Plugin.html#method.process_event).
+    pub fn on_event(&mut self, event: &host::Event) {
+        if let host::Event::Tempo(tempo, samples_per_tick) = event {
+            self.tempo = *tempo as f64;
+            if *samples_per_tick > 0 {
+                self.samples_per_tick = Some(*samples_per_tick as f64);
+            }
+        }
+    }
+
+    /// Start a new block of `len` samples. Call it at the start of render.
+    ///
+    /// While playing, the position moves by the previous block. After a jump or a change of the
+    /// playing state, the position and the selection are asked from the host.
+    pub fn begin_block(&mut self, len: usize) {
+        if self.needs_sync {
+            self.sync();
+        } else if self.playing {
+            self.beats += self.block_len as f64 / self.samples_per_beat();
+        }
+        self.block_len = len;
+    }
+
+    /// Ask the host for the position and the selection.
+    pub fn sync(&mut self) {
+        self.beats = self
+            .host
+            .on_message(self.tag, GetMixingTime(TimeFormat::Beats, 0))
+            .0;
+        let selection = self
+            .host
+            .on_message(self.tag, GetSelTime(TimeFormat::Beats));
+        self.selection = if selection.1 > selection.0 {
+            Some((selection.0, selection.1))
+        } else {
+            None
+        };
+        self.needs_sync = false;
+        trace!("transport synced at {} beats", self.beats);
+    }
+
+    /// Whether the host is playing.
+    pub fn playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Tempo in BPM.
+    pub fn tempo(&self) -> f64 {
+        self.tempo
+    }
+
+    /// Sample rate.
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Pulses per quarter note.
+    pub fn ppq(&self) -> u32 {
+        self.time_signature.ppq
+    }
+
+    /// Time signature.
+    pub fn time_signature(&self) -> &TimeSignature {
+        &self.time_signature
+    }
+
+    /// Beats in a bar.
+    pub fn beats_per_bar(&self) -> f64 {
+        self.time_signature.steps_per_bar as f64 / self.time_signature.steps_per_beat.max(1) as f64
+    }
+
+    /// Length of a beat in samples.
+    pub fn samples_per_beat(&self) -> f64 {
+        match self.samples_per_tick {
+            Some(samples) => samples * self.ppq() as f64,
+            None => self.sample_rate * 60.0 / self.tempo,
+        }
+    }
+
+    /// Length of the current block.
+    pub fn block_len(&self) -> usize {
+        self.block_len
+    }
+
+    /// Song position at the start of the block, in beats.
+    pub fn beats(&self) -> f64 {
+        self.beats
+    }
+
+    /// Song position at the start of the block, in ticks.
+    pub fn ticks(&self) -> f64 {
+        self.beats * self.ppq() as f64
+    }
+
+    /// Song position at the start of the block, in samples.
+    pub fn samples(&self) -> f64 {
+        self.beats * self.samples_per_beat()
+    }
+
+    /// Bar (starting from 0) and beat in the bar at the start of the block.
+    pub fn bar_beat(&self) -> (u64, f64) {
+        let per_bar = self.beats_per_bar();
+        let bar = (self.beats / per_bar).floor();
+        (bar as u64, self.beats - bar * per_bar)
+    }
+
+    /// Selection range in beats (the whole song if nothing is selected). `None` if the host
+    /// didn't report one.
+    pub fn selection(&self) -> Option<(f64, f64)> {
+        self.selection
+    }
+
+    /// Song position in beats at `offset` samples into the block.
+    ///
+    /// The position doesn't move when stopped.
+    pub fn beat_at(&self, offset: usize) -> f64 {
+        if self.playing {
+            self.beats + offset as f64 / self.samples_per_beat()
+        } else {
+            self.beats
+        }
+    }
+
+    /// Phase (0..1) of a cycle lasting `period` beats, at `offset` samples into the block.
+    pub fn phase_at(&self, offset: usize, period: f64) -> f64 {
+        (self.beat_at(offset) / period).rem_euclid(1.0)
+    }
+
+    /// Offset in the block of the next multiple of `period` beats, if it falls into the block.
+    ///
+    /// Useful to start things in sync, e.g. `next_beat(0.25)` for sixteenth notes.
+    pub fn next_beat(&self, period: f64) -> Option<usize> {
+        if !self.playing || period <= 0.0 {
+            return None;
+        }
+        let next = (self.beats / period).ceil() * period;
+        let offset = ((next - self.beats) * self.samples_per_beat()).round() as usize;
+        if offset < self.block_len {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    /// Convert a duration in beats to samples, e.g. for tempo-synced delays.
+    pub fn beats_to_samples(&self, beats: f64) -> f64 {
+        beats * self.samples_per_beat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{id, MockHost, Reply};
+
+    #[test]
+    fn follows_host() {
+        let host = MockHost::new();
+        host.queue_reply(id::GET_MIXING_TIME, Reply::Time(8.0, 0.0));
+        host.queue_reply(id::GET_SEL_TIME, Reply::Time(4.0, 12.0));
+        let mut transport = TransportState::new(&host.host(), plugin::Tag(0));
+
+        transport.on_message(&host::Message::SetSampleRate(48000));
+        transport.on_event(&host::Event::Tempo(150.0, 0));
+        transport.on_message(&host::Message::SetTimeSig(TimeSignature {
+            steps_per_bar: 12,
+            steps_per_beat: 4,
+            ppq: 96,
+        }));
+        transport.on_message(&host::Message::SetPlaying(true));
+        // a beat is 19200 samples
+        transport.begin_block(4800);
+        assert!(transport.playing());
+        assert_eq!(8.0, transport.beats());
+        assert_eq!(768.0, transport.ticks());
+        assert_eq!((2, 2.0), transport.bar_beat());
+        assert_eq!(Some((4.0, 12.0)), transport.selection());
+        assert_eq!(8.125, transport.beat_at(2400));
+        assert_eq!(0.5, transport.phase_at(9600, 1.0));
+        assert_eq!(Some(0), transport.next_beat(1.0));
+
+        transport.begin_block(9600);
+        assert_eq!(8.25, transport.beats());
+        assert_eq!(Some(4800), transport.next_beat(0.5));
+        assert_eq!(None, transport.next_beat(1.0));
+
+        // SetSamplesPerTick is more precise than the tempo
+        transport.on_message(&host::Message::SetSamplesPerTick(100.0));
+        assert_eq!(9600.0, transport.samples_per_beat());
+        assert_eq!(4800.0, transport.beats_to_samples(0.5));
+    }
+
+    #[test]
+    fn stopped_and_jumps() {
+        let host = MockHost::new();
+        host.queue_reply(id::GET_MIXING_TIME, Reply::Time(0.0, 0.0));
+        host.queue_reply(id::GET_MIXING_TIME, Reply::Time(16.0, 0.0));
+        let mut transport = TransportState::new(&host.host(), plugin::Tag(0));
+
+        transport.begin_block(44100);
+        transport.begin_block(44100);
+        assert_eq!(0.0, transport.beats());
+        assert_eq!(0.0, transport.beat_at(100));
+        assert_eq!(None, transport.selection());
+
+        transport.on_message(&host::Message::SongPosChanged);
+        transport.begin_block(64);
+        assert_eq!(16.0, transport.beats());
+        assert_eq!(2, host.messages(id::GET_MIXING_TIME).len());
+    }
+}
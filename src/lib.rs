@@ -5,14 +5,31 @@
 //!
 //! ## How to use this library
 //!
-//! You should implement [`Plugin`](plugin/trait.Plugin.html) and export it with
-//! [`create_plugin!`](macro.create_plugin.html).
+//! You should implement [`Plugin`](plugin/trait.Plugin.html), together with
+//! [`Effect`](plugin/trait.Effect.html) or [`Generator`](plugin/trait.Generator.html), and export
+//! it with [`create_plugin!`](macro.create_plugin.html).
 //!
 //! To talk to the host use [`Host`](host/struct.Host.html), which is passed to the plugin's
 //! constructor.
 //!
+//! Parameters can be declared with [`params`](params/index.html), which then answers the host's
+//! parameter requests for you.
+//!
+//! Presets can be kept with [`presets`](presets/index.html).
+//!
+//! Parameter changes and other events can be applied at their sample with
+//! [`render`](render/index.html).
+//!
 //! `examples/simple.rs` in the code repo provides you with more details.
 //!
+//! Who frees the memory exchanged with the host is described in
+//! [`ownership`](ownership/index.html).
+//!
+//! ## Testing
+//!
+//! With the `testing` feature, the [`testing`](testing/index.html) module provides a mock host,
+//! so you can run your plugin in `cargo test` without FL Studio.
+//!
 //! ## Types of plugins
 //!
 //! There are two kinds of Fruity plugins: effects and generators. Effects are plugins that receive
@@ -21,6 +38,13 @@
 //! user (like the SimSynth and Sytrus). The main reason to make something a generator is that it
 //! needs input from the FL Studio pianoroll (although there are other reasons possible).
 //!
+//! Generators can use [`voice::engine`](voice/engine/index.html) to manage their voices.
+//!
+//! MIDI messages can be decoded and built with [`midi`](midi/index.html).
+//!
+//! Notes for the piano roll can be made with [`notes`](notes/index.html), in bars, beats and
+//! steps. With the `smf` feature, they can also be imported from MIDI files.
+//!
 //! ## Installation
 //!
 //! Plugins are installed in FL Studio in subfolders of the `FL Studio\Plugins\Fruity` folder on
@@ -48,16 +72,26 @@
     unreachable_pub
 )]
 
+pub mod cstr;
 pub mod host;
+pub mod midi;
+pub mod notes;
+pub mod ownership;
+pub mod params;
 pub mod plugin;
+pub mod presets;
+pub mod render;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod voice;
 
 use std::ffi::{CStr, CString};
-use std::mem;
 use std::os::raw::{c_char, c_int, c_void};
 
 use bitflags::bitflags;
-use log::{debug, error};
+use log::debug;
+
+use ownership::{Borrowed, HostOwned};
 
 /// Current FL SDK version.
 pub const CURRENT_SDK_VERSION: u32 = 1;
@@ -84,7 +118,7 @@ macro_rules! implement_tag {
 
         /// Identifier.
         #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-        pub struct Tag(pub crate::Tag);
+        pub struct Tag(pub $crate::Tag);
 
         impl fmt::Display for Tag {
             fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -130,6 +164,12 @@ primitive_as_raw_ptr!(usize);
 primitive_as_raw_ptr!(*mut c_void);
 primitive_as_raw_ptr!(*const c_void);
 
+impl AsRawPtr for intptr_t {
+    fn as_raw_ptr(&self) -> intptr_t {
+        *self
+    }
+}
+
 impl AsRawPtr for bool {
     fn as_raw_ptr(&self) -> intptr_t {
         (self.to_owned() as u8).into()
@@ -150,23 +190,81 @@ impl AsRawPtr for f64 {
 
 impl AsRawPtr for String {
     fn as_raw_ptr(&self) -> intptr_t {
-        let value = CString::new(self.clone()).unwrap_or_else(|e| {
-            error!("{}", e);
-            panic!();
-        });
+        let value = cstr::to_c_string(self);
         // alloc_real_cstr prevents memory leak caused by CString::into_raw
         unsafe { alloc_real_cstr(value.into_raw()) as intptr_t }
     }
 }
 
-/// FFI to make C string (`char *`) managed by C side. Because `char *` produced by
-/// `CString::into_raw` leads to memory leak:
+/// Result of [`Plugin::handle_message`](plugin/trait.Plugin.html#method.handle_message) and
+/// [`Plugin::handle_param`](plugin/trait.Plugin.html#method.handle_param).
 ///
-/// > The pointer which this function returns must be returned to Rust and reconstituted using
-/// > from_raw to be properly deallocated. Specifically, one should not use the standard C free()
-/// > function to deallocate this string.
-#[no_mangle]
+/// It's the raw value passed to the host, so returning it doesn't allocate:
+///
+/// ```ignore
+/// DispatchResult::NONE
+/// DispatchResult::from(true)
+/// DispatchResult::from(0.5_f32)
+/// DispatchResult::string("Name") // the host frees it
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct DispatchResult(intptr_t);
+
+impl DispatchResult {
+    /// Nothing to return (0).
+    pub const NONE: DispatchResult = DispatchResult(0);
+
+    /// Raw value.
+    pub fn raw(value: intptr_t) -> Self {
+        DispatchResult(value)
+    }
+
+    /// A C string owned by the host. This allocates, don't use it from the mixer thread.
+    pub fn string(value: &str) -> Self {
+        DispatchResult(value.to_string().as_raw_ptr())
+    }
+}
+
+impl AsRawPtr for DispatchResult {
+    fn as_raw_ptr(&self) -> intptr_t {
+        self.0
+    }
+}
+
+macro_rules! dispatch_result_from {
+    ($($type:ty),*) => {
+        $(
+            impl From<$type> for DispatchResult {
+                fn from(value: $type) -> Self {
+                    DispatchResult(value.as_raw_ptr())
+                }
+            }
+        )*
+    };
+}
+
+dispatch_result_from!(bool, i8, u8, i16, u16, i32, u32, i64, u64, isize, usize, f32, f64);
+
+impl From<ParameterFlags> for DispatchResult {
+    fn from(value: ParameterFlags) -> Self {
+        DispatchResult(value.as_raw_ptr())
+    }
+}
+
+/// Migration from the boxed results.
+impl From<Box<dyn AsRawPtr>> for DispatchResult {
+    fn from(value: Box<dyn AsRawPtr>) -> Self {
+        DispatchResult(value.as_raw_ptr())
+    }
+}
+
 extern "C" {
+    /// FFI to make C string (`char *`) managed by C side. Because `char *` produced by
+    /// `CString::into_raw` leads to memory leak:
+    ///
+    /// > The pointer which this function returns must be returned to Rust and reconstituted using
+    /// > from_raw to be properly deallocated. Specifically, one should not use the standard C
+    /// > free() function to deallocate this string.
     fn alloc_real_cstr(raw_str: *mut c_char) -> *mut c_char;
 }
 
@@ -200,6 +298,12 @@ primitive_from_raw_ptr!(usize);
 primitive_from_raw_ptr!(*mut c_void);
 primitive_from_raw_ptr!(*const c_void);
 
+impl FromRawPtr for intptr_t {
+    fn from_raw_ptr(value: intptr_t) -> Self {
+        value
+    }
+}
+
 impl FromRawPtr for f32 {
     fn from_raw_ptr(value: intptr_t) -> Self {
         f32::from_bits(value as i32 as u32)
@@ -212,10 +316,11 @@ impl FromRawPtr for f64 {
     }
 }
 
+/// The string is copied, the host keeps its memory (see
+/// [`ownership::copy_str`](ownership/fn.copy_str.html)).
 impl FromRawPtr for String {
     fn from_raw_ptr(value: intptr_t) -> Self {
-        let cstr = unsafe { CStr::from_ptr(value as *const c_char) };
-        cstr.to_string_lossy().to_string()
+        unsafe { ownership::copy_str(value) }
     }
 }
 
@@ -265,6 +370,7 @@ impl From<TTimeSigInfo> for TimeSignature {
     }
 }
 
+#[derive(Clone, Copy)]
 #[repr(C)]
 struct TTimeSigInfo {
     steps_per_bar: c_int,
@@ -274,14 +380,13 @@ struct TTimeSigInfo {
 
 impl FromRawPtr for TTimeSigInfo {
     fn from_raw_ptr(raw_ptr: intptr_t) -> Self {
-        let sig_ptr = raw_ptr as *mut c_void as *mut TTimeSigInfo;
-        unsafe {
-            Self {
-                steps_per_beat: (*sig_ptr).steps_per_beat,
-                steps_per_bar: (*sig_ptr).steps_per_bar,
-                ppq: (*sig_ptr).ppq,
-            }
-        }
+        unsafe { Borrowed::<TTimeSigInfo>::from_raw(raw_ptr) }
+            .map(|sig| sig.copy())
+            .unwrap_or(TTimeSigInfo {
+                steps_per_bar: 16,
+                steps_per_beat: 4,
+                ppq: 96,
+            })
     }
 }
 
@@ -314,19 +419,22 @@ impl From<TimeFormat> for u8 {
 /// The first value is mixing time.
 ///
 /// The second value is offset in samples.
-#[derive(Debug, Default)]
+#[derive(Clone, Copy, Debug, Default)]
 #[repr(C)]
 pub struct Time(pub f64, pub f64);
 
+/// The time is copied, the memory isn't freed.
 impl FromRawPtr for Time {
     fn from_raw_ptr(value: intptr_t) -> Self {
-        unsafe { *Box::from_raw(value as *mut c_void as *mut Time) }
+        unsafe { Borrowed::<Time>::from_raw(value) }
+            .map(|time| time.copy())
+            .unwrap_or_default()
     }
 }
 
 /// Song time in **bar:step:tick** format.
 #[allow(missing_docs)]
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default)]
 #[repr(C)]
 pub struct SongTime {
     pub bar: i32,
@@ -334,9 +442,12 @@ pub struct SongTime {
     pub tick: i32,
 }
 
+/// The time is copied, the memory isn't freed.
 impl FromRawPtr for SongTime {
     fn from_raw_ptr(value: intptr_t) -> Self {
-        unsafe { *Box::from_raw(value as *mut c_void as *mut Self) }
+        unsafe { Borrowed::<SongTime>::from_raw(value) }
+            .map(|time| time.copy())
+            .unwrap_or_default()
     }
 }
 
@@ -355,10 +466,11 @@ pub struct NameColor {
 }
 
 // Type used in FFI for [`NameColor`](struct.NameColor.html).
+#[derive(Debug)]
 #[repr(C)]
 struct TNameColor {
-    name: [u8; 256],
-    vis_name: [u8; 256],
+    name: [u8; cstr::BUF_LEN],
+    vis_name: [u8; cstr::BUF_LEN],
     color: c_int,
     index: c_int,
 }
@@ -366,8 +478,8 @@ struct TNameColor {
 impl From<TNameColor> for NameColor {
     fn from(name_color: TNameColor) -> Self {
         Self {
-            name: String::from_utf8_lossy(&name_color.name[..]).to_string(),
-            vis_name: String::from_utf8_lossy(&name_color.vis_name[..]).to_string(),
+            name: cstr::read_from(&name_color.name),
+            vis_name: cstr::read_from(&name_color.vis_name),
             color: name_color.color as u8,
             index: name_color.index as usize,
         }
@@ -376,10 +488,10 @@ impl From<TNameColor> for NameColor {
 
 impl From<NameColor> for TNameColor {
     fn from(name_color: NameColor) -> Self {
-        let mut name = [0_u8; 256];
-        name.copy_from_slice(name_color.name.as_bytes());
-        let mut vis_name = [0_u8; 256];
-        vis_name.copy_from_slice(name_color.vis_name.as_bytes());
+        let mut name = [0_u8; cstr::BUF_LEN];
+        cstr::write_to(&name_color.name, &mut name);
+        let mut vis_name = [0_u8; cstr::BUF_LEN];
+        cstr::write_to(&name_color.vis_name, &mut vis_name);
         Self {
             name,
             vis_name,
@@ -389,14 +501,10 @@ impl From<NameColor> for TNameColor {
     }
 }
 
-impl FromRawPtr for TNameColor {
-    fn from_raw_ptr(value: intptr_t) -> Self {
-        unsafe { *Box::from_raw(value as *mut Self) }
-    }
-}
-
 /// MIDI message.
-#[derive(Debug)]
+///
+/// Use [`midi::MidiEvent`](midi/enum.MidiEvent.html) to decode or build it.
+#[derive(Clone, Debug, PartialEq)]
 pub struct MidiMessage {
     /// Status byte.
     pub status: u8,
@@ -430,13 +538,22 @@ impl From<c_int> for MidiMessage {
     }
 }
 
-/// Collection of notes, which you can add to the piano roll using
+impl From<MidiMessage> for c_int {
+    fn from(message: MidiMessage) -> Self {
+        message.status as c_int
+            | (message.data1 as c_int) << 8
+            | (message.data2 as c_int) << 16
+            | (message.port as c_int) << 24
+    }
+}
+
+/// Collection of notes, which you can add to the piano roll or the step sequencer using
 /// [`Host::on_message`](host/struct.Host.html#on_message.new) with message
 /// [`plugin::message::AddToPianoRoll`](./plugin/message/struct.AddToPianoRoll.html).
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Notes {
-    // 0=step seq (not supported yet), 1=piano roll
-    //target: i32,
+    /// Where to add the notes.
+    pub target: NotesTarget,
     /// Notes.
     pub notes: Vec<Note>,
     /// See [`NotesFlags`](struct.NotesFlags.html).
@@ -447,8 +564,18 @@ pub struct Notes {
     pub channel: Option<u32>,
 }
 
+/// Where [`Notes`](struct.Notes.html) are added.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NotesTarget {
+    /// Step sequencer. Each note is a step, positioned at its start.
+    StepSequencer = 0,
+    /// Piano roll.
+    #[default]
+    PianoRoll = 1,
+}
+
 /// This type represents a note in [`Notes`](struct.Notes.html).
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 #[repr(C)]
 pub struct Note {
     /// Position in PPQ.
@@ -481,35 +608,6 @@ bitflags! {
     }
 }
 
-// This type in FL SDK is what we represent as Notes. Here we use it for FFI, to send it to C++.
-#[repr(C)]
-struct TNotesParams {
-    target: c_int,
-    flags: c_int,
-    pat_num: c_int,
-    chan_num: c_int,
-    count: c_int,
-    notes: *mut Note,
-}
-
-impl From<Notes> for TNotesParams {
-    fn from(mut notes: Notes) -> Self {
-        notes.notes.shrink_to_fit();
-        let notes_ptr = notes.notes.as_mut_ptr();
-        let len = notes.notes.len();
-        mem::forget(notes.notes);
-
-        Self {
-            target: 1,
-            flags: notes.flags.bits() as c_int,
-            pat_num: notes.pattern.map(|v| v as c_int).unwrap_or(-1),
-            chan_num: notes.channel.map(|v| v as c_int).unwrap_or(-1),
-            count: len as c_int,
-            notes: notes_ptr,
-        }
-    }
-}
-
 /// Describes an item that should be added to a control's right-click popup menu.
 #[derive(Debug)]
 pub struct ParamMenuEntry {
@@ -530,12 +628,12 @@ bitflags! {
 }
 
 impl ParamMenuEntry {
-    fn from_ffi(ffi_t: *mut TParamMenuEntry) -> Self {
+    // the host keeps the entry and its name
+    fn from_ffi(entry: HostOwned<TParamMenuEntry>) -> Self {
+        let entry = entry.borrow();
         Self {
-            name: unsafe { CString::from_raw((*ffi_t).name) }
-                .to_string_lossy()
-                .to_string(),
-            flags: ParamMenuItemFlags::from_bits(unsafe { (*ffi_t).flags })
+            name: unsafe { ownership::copy_str(entry.get().name as intptr_t) },
+            flags: ParamMenuItemFlags::from_bits(entry.get().flags)
                 .unwrap_or_else(ParamMenuItemFlags::empty),
         }
     }
@@ -629,11 +727,11 @@ bitflags! {
         const FORCE_RELOAD = 2;
         /// Don't load the sample, instead get its filename & make sure that the format is correct
         ///
-        /// (useful after [host::HostMessage::ChanSampleChanged](
-        /// enum.HostMessage.html#variant.ChanSampleChanged))
+        /// (useful after [host::Message::ChanSample](
+        /// host/enum.Message.html#variant.ChanSample))
         const GET_NAME = 4;
         /// Don't resample to the host sample rate
-        const NO_RESAMPLING = 5;
+        const NO_RESAMPLING = 8;
     }
 }
 
@@ -1002,18 +1100,6 @@ unsafe extern "C" fn fplog(message: *const c_char) {
     debug!("{}", CStr::from_ptr(message).to_string_lossy());
 }
 
-/// FFI to free rust's Box::into_raw pointer.
-///
-/// It supposed to be used internally. Don't use it.
-///
-/// # Safety
-///
-/// Unsafe
-#[no_mangle]
-unsafe extern "C" fn free_rbox_raw(raw_ptr: *mut c_void) {
-    let _ = Box::from_raw(raw_ptr);
-}
-
 /// FFI to free rust's CString pointer.
 ///
 /// It supposed to be used internally. Don't use it.
@@ -0,0 +1,680 @@
+//! Typed MIDI messages.
+//!
+//! [`MidiMessage`](../struct.MidiMessage.html) is what the host sends and receives: raw status
+//! and data bytes. [`MidiEvent`](enum.MidiEvent.html) is the same message decoded:
+//!
+//! ```ignore
+//! fn midi_in(&mut self, message: MidiMessage) -> MidiAction {
+//!     match MidiEvent::from(&message) {
+//!         MidiEvent::NoteOn { channel, note, velocity } => {
+//!             self.host.midi_out(self.tag, MidiEvent::note_on(channel, note + 12, velocity));
+//!         }
+//!         MidiEvent::PitchBend { value, .. } => {
+//!             self.bend = (value as f32 - PITCH_BEND_CENTER as f32) / PITCH_BEND_CENTER as f32;
+//!         }
+//!         _ => {}
+//!     }
+//!     MidiAction::Pass
+//! }
+//! ```
+//!
+//! RPN and NRPN messages are sent as a series of control changes.
+//! [`ParameterAssembler`](struct.ParameterAssembler.html) puts them back together.
+//!
+//! [`Plugin::midi_in`](../plugin/trait.Plugin.html#method.midi_in) returns a
+//! [`MidiAction`](enum.MidiAction.html), so the plugin can filter or remap the incoming MIDI:
+//!
+//! ```ignore
+//! fn midi_in(&mut self, message: MidiMessage) -> MidiAction {
+//!     match MidiEvent::from(&message) {
+//!         MidiEvent::NoteOn { channel, note, velocity } if note < 36 => MidiAction::Drop,
+//!         MidiEvent::NoteOn { channel, note, velocity } => {
+//!             MidiAction::Replace(MidiEvent::note_on(channel, note, 100).to_message(message.port))
+//!         }
+//!         _ => MidiAction::Pass,
+//!     }
+//! }
+//! ```
+//!
+//! Output can be timed with [`scheduler`](scheduler/index.html).
+pub mod scheduler;
+
+use std::os::raw::c_int;
+
+use crate::{intptr_t, AsRawPtr, DispatchResult, MidiMessage};
+
+/// Center of the pitch bend range.
+pub const PITCH_BEND_CENTER: u16 = 0x2000;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const POLY_PRESSURE: u8 = 0xa0;
+const CONTROL_CHANGE: u8 = 0xb0;
+const PROGRAM_CHANGE: u8 = 0xc0;
+const CHANNEL_PRESSURE: u8 = 0xd0;
+const PITCH_BEND: u8 = 0xe0;
+
+const CC_DATA_ENTRY_MSB: u8 = 6;
+const CC_DATA_ENTRY_LSB: u8 = 38;
+const CC_DATA_INCREMENT: u8 = 96;
+const CC_DATA_DECREMENT: u8 = 97;
+const CC_NRPN_LSB: u8 = 98;
+const CC_NRPN_MSB: u8 = 99;
+const CC_RPN_LSB: u8 = 100;
+const CC_RPN_MSB: u8 = 101;
+
+/// Parameter number which deselects the current RPN or NRPN.
+pub const NULL_PARAMETER: u16 = 0x3fff;
+
+/// Decoded MIDI message.
+///
+/// Channels are 0..=15. Data bytes are 7-bit, the higher bit is dropped when converting to a
+/// [`MidiMessage`](../struct.MidiMessage.html).
+///
+/// Conversions are lossless with one exception: a note on with velocity 0 is a note off (as
+/// MIDI defines it), so it's decoded as [`NoteOff`](#variant.NoteOff) with velocity 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MidiEvent {
+    /// Note off.
+    NoteOff {
+        /// Channel.
+        channel: u8,
+        /// Note number.
+        note: u8,
+        /// Release velocity.
+        velocity: u8,
+    },
+    /// Note on. The velocity is never 0.
+    NoteOn {
+        /// Channel.
+        channel: u8,
+        /// Note number.
+        note: u8,
+        /// Velocity.
+        velocity: u8,
+    },
+    /// Polyphonic key pressure (aftertouch).
+    PolyPressure {
+        /// Channel.
+        channel: u8,
+        /// Note number.
+        note: u8,
+        /// Pressure.
+        pressure: u8,
+    },
+    /// Control change.
+    ControlChange {
+        /// Channel.
+        channel: u8,
+        /// Controller number.
+        controller: u8,
+        /// Value.
+        value: u8,
+    },
+    /// Program change.
+    ProgramChange {
+        /// Channel.
+        channel: u8,
+        /// Program number.
+        program: u8,
+    },
+    /// Channel pressure (aftertouch).
+    ChannelPressure {
+        /// Channel.
+        channel: u8,
+        /// Pressure.
+        pressure: u8,
+    },
+    /// Pitch bend.
+    PitchBend {
+        /// Channel.
+        channel: u8,
+        /// 14-bit value, [`PITCH_BEND_CENTER`](constant.PITCH_BEND_CENTER.html) is no bend.
+        value: u16,
+    },
+    /// System realtime message.
+    Realtime(Realtime),
+    /// Any other message (system common, malformed), kept as is.
+    Other {
+        /// Status byte.
+        status: u8,
+        /// First data byte.
+        data1: u8,
+        /// Second data byte.
+        data2: u8,
+    },
+}
+
+/// System realtime messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Realtime {
+    /// Timing clock, 24 per quarter note.
+    Clock,
+    /// Start.
+    Start,
+    /// Continue.
+    Continue,
+    /// Stop.
+    Stop,
+    /// Active sensing.
+    ActiveSensing,
+    /// System reset.
+    Reset,
+}
+
+impl Realtime {
+    fn from_status(status: u8) -> Option<Self> {
+        match status {
+            0xf8 => Some(Self::Clock),
+            0xfa => Some(Self::Start),
+            0xfb => Some(Self::Continue),
+            0xfc => Some(Self::Stop),
+            0xfe => Some(Self::ActiveSensing),
+            0xff => Some(Self::Reset),
+            _ => None,
+        }
+    }
+
+    fn status(self) -> u8 {
+        match self {
+            Self::Clock => 0xf8,
+            Self::Start => 0xfa,
+            Self::Continue => 0xfb,
+            Self::Stop => 0xfc,
+            Self::ActiveSensing => 0xfe,
+            Self::Reset => 0xff,
+        }
+    }
+}
+
+impl MidiEvent {
+    /// Note on. Velocity 0 makes a note off.
+    pub fn note_on(channel: u8, note: u8, velocity: u8) -> Self {
+        if velocity & 0x7f == 0 {
+            Self::note_off(channel, note, 0)
+        } else {
+            Self::NoteOn {
+                channel,
+                note,
+                velocity,
+            }
+        }
+    }
+
+    /// Note off.
+    pub fn note_off(channel: u8, note: u8, velocity: u8) -> Self {
+        Self::NoteOff {
+            channel,
+            note,
+            velocity,
+        }
+    }
+
+    /// Control change.
+    pub fn control_change(channel: u8, controller: u8, value: u8) -> Self {
+        Self::ControlChange {
+            channel,
+            controller,
+            value,
+        }
+    }
+
+    /// Program change.
+    pub fn program_change(channel: u8, program: u8) -> Self {
+        Self::ProgramChange { channel, program }
+    }
+
+    /// Pitch bend from -1.0 (full down) to 1.0 (full up).
+    pub fn pitch_bend(channel: u8, bend: f32) -> Self {
+        let bend = bend.clamp(-1.0, 1.0);
+        // the range is asymmetric: 8192 steps down, 8191 up
+        let steps = if bend < 0.0 { 8192.0 } else { 8191.0 };
+        Self::PitchBend {
+            channel,
+            value: (PITCH_BEND_CENTER as f32 + bend * steps).round() as u16,
+        }
+    }
+
+    /// The control changes which set RPN `parameter` to `value` (both 14-bit).
+    pub fn rpn(channel: u8, parameter: u16, value: u16) -> [Self; 4] {
+        Self::parameter_ccs(channel, CC_RPN_MSB, CC_RPN_LSB, parameter, value)
+    }
+
+    /// The control changes which set NRPN `parameter` to `value` (both 14-bit).
+    pub fn nrpn(channel: u8, parameter: u16, value: u16) -> [Self; 4] {
+        Self::parameter_ccs(channel, CC_NRPN_MSB, CC_NRPN_LSB, parameter, value)
+    }
+
+    fn parameter_ccs(channel: u8, msb: u8, lsb: u8, parameter: u16, value: u16) -> [Self; 4] {
+        [
+            Self::control_change(channel, msb, (parameter >> 7) as u8 & 0x7f),
+            Self::control_change(channel, lsb, parameter as u8 & 0x7f),
+            Self::control_change(channel, CC_DATA_ENTRY_MSB, (value >> 7) as u8 & 0x7f),
+            Self::control_change(channel, CC_DATA_ENTRY_LSB, value as u8 & 0x7f),
+        ]
+    }
+
+    /// Channel of a channel message.
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            Self::NoteOff { channel, .. }
+            | Self::NoteOn { channel, .. }
+            | Self::PolyPressure { channel, .. }
+            | Self::ControlChange { channel, .. }
+            | Self::ProgramChange { channel, .. }
+            | Self::ChannelPressure { channel, .. }
+            | Self::PitchBend { channel, .. } => Some(channel),
+            Self::Realtime(_) | Self::Other { .. } => None,
+        }
+    }
+
+    /// Make a message for `port`.
+    pub fn to_message(self, port: u8) -> MidiMessage {
+        let (status, data1, data2) = match self {
+            Self::NoteOff {
+                channel,
+                note,
+                velocity,
+            } => (NOTE_OFF | channel & 0xf, note, velocity),
+            Self::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => (NOTE_ON | channel & 0xf, note, velocity),
+            Self::PolyPressure {
+                channel,
+                note,
+                pressure,
+            } => (POLY_PRESSURE | channel & 0xf, note, pressure),
+            Self::ControlChange {
+                channel,
+                controller,
+                value,
+            } => (CONTROL_CHANGE | channel & 0xf, controller, value),
+            Self::ProgramChange { channel, program } => {
+                (PROGRAM_CHANGE | channel & 0xf, program, 0)
+            }
+            Self::ChannelPressure { channel, pressure } => {
+                (CHANNEL_PRESSURE | channel & 0xf, pressure, 0)
+            }
+            Self::PitchBend { channel, value } => {
+                (PITCH_BEND | channel & 0xf, value as u8, (value >> 7) as u8)
+            }
+            Self::Realtime(message) => (message.status(), 0, 0),
+            Self::Other {
+                status,
+                data1,
+                data2,
+            } => {
+                return MidiMessage {
+                    status,
+                    data1,
+                    data2,
+                    port,
+                }
+            }
+        };
+        MidiMessage {
+            status,
+            data1: data1 & 0x7f,
+            data2: data2 & 0x7f,
+            port,
+        }
+    }
+}
+
+impl From<MidiMessage> for MidiEvent {
+    fn from(message: MidiMessage) -> Self {
+        MidiEvent::from(&message)
+    }
+}
+
+impl From<&MidiMessage> for MidiEvent {
+    fn from(message: &MidiMessage) -> Self {
+        let channel = message.status & 0xf;
+        let (data1, data2) = (message.data1, message.data2);
+        if data1 > 0x7f || data2 > 0x7f {
+            return Self::Other {
+                status: message.status,
+                data1,
+                data2,
+            };
+        }
+        match message.status & 0xf0 {
+            NOTE_OFF => Self::note_off(channel, data1, data2),
+            NOTE_ON => Self::note_on(channel, data1, data2),
+            POLY_PRESSURE => Self::PolyPressure {
+                channel,
+                note: data1,
+                pressure: data2,
+            },
+            CONTROL_CHANGE => Self::control_change(channel, data1, data2),
+            PROGRAM_CHANGE if data2 == 0 => Self::program_change(channel, data1),
+            CHANNEL_PRESSURE if data2 == 0 => Self::ChannelPressure {
+                channel,
+                pressure: data1,
+            },
+            PITCH_BEND => Self::PitchBend {
+                channel,
+                value: (data2 as u16) << 7 | data1 as u16,
+            },
+            _ => match Realtime::from_status(message.status) {
+                Some(realtime) if data1 == 0 && data2 == 0 => Self::Realtime(realtime),
+                _ => Self::Other {
+                    status: message.status,
+                    data1,
+                    data2,
+                },
+            },
+        }
+    }
+}
+
+/// Makes a message for port 0.
+impl From<MidiEvent> for MidiMessage {
+    fn from(event: MidiEvent) -> Self {
+        event.to_message(0)
+    }
+}
+
+/// Packed message which tells the host to kill the message (`MIDIMsg_Null`).
+const NULL_MESSAGE: c_int = -1;
+
+/// What to do with a message received by
+/// [`Plugin::midi_in`](../plugin/trait.Plugin.html#method.midi_in).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum MidiAction {
+    /// Let the message through unchanged.
+    #[default]
+    Pass,
+    /// Replace the message with another one.
+    Replace(MidiMessage),
+    /// Kill the message.
+    Drop,
+}
+
+impl MidiAction {
+    /// Apply the action to the packed message the host passed.
+    pub(crate) fn apply(self, message: &mut c_int) {
+        match self {
+            Self::Pass => {}
+            Self::Replace(replacement) => *message = replacement.into(),
+            Self::Drop => *message = NULL_MESSAGE,
+        }
+    }
+}
+
+/// Result for [`host::Message::MidiIn`](../host/enum.Message.html#variant.MidiIn), the live MIDI
+/// input preview.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreviewAction {
+    /// The plugin didn't use the message.
+    Pass,
+    /// The plugin used the message (stole it from the host).
+    Handled,
+}
+
+impl AsRawPtr for PreviewAction {
+    fn as_raw_ptr(&self) -> intptr_t {
+        (*self == Self::Handled) as intptr_t
+    }
+}
+
+impl From<PreviewAction> for DispatchResult {
+    fn from(action: PreviewAction) -> Self {
+        DispatchResult::raw(action.as_raw_ptr())
+    }
+}
+
+/// Kind of a [`Parameter`](struct.Parameter.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ParameterKind {
+    /// Registered parameter number.
+    Rpn,
+    /// Non-registered parameter number.
+    Nrpn,
+}
+
+/// RPN or NRPN change, assembled by [`ParameterAssembler`](struct.ParameterAssembler.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Parameter {
+    /// Channel.
+    pub channel: u8,
+    /// RPN or NRPN.
+    pub kind: ParameterKind,
+    /// 14-bit parameter number.
+    pub parameter: u16,
+    /// 14-bit value.
+    pub value: u16,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct ChannelParameter {
+    kind: Option<ParameterKind>,
+    msb: u8,
+    lsb: u8,
+    value: u16,
+}
+
+impl ChannelParameter {
+    fn number(&self) -> u16 {
+        (self.msb as u16) << 7 | self.lsb as u16
+    }
+}
+
+/// Assembles RPN and NRPN changes from control changes, for each channel.
+///
+/// A change is reported on each data entry, increment and decrement. Data entry MSB resets the
+/// LSB of the value, like most devices expect.
+#[derive(Clone, Debug, Default)]
+pub struct ParameterAssembler {
+    channels: [ChannelParameter; 16],
+}
+
+impl ParameterAssembler {
+    /// Initializer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed an event. Returns the changed parameter, if any.
+    ///
+    /// Control changes which select a parameter or enter data are consumed here. Pass the others
+    /// on as usual.
+    pub fn push(&mut self, event: &MidiEvent) -> Option<Parameter> {
+        let (channel, controller, value) = match *event {
+            MidiEvent::ControlChange {
+                channel,
+                controller,
+                value,
+            } => (channel & 0xf, controller, value),
+            _ => return None,
+        };
+        let state = &mut self.channels[channel as usize];
+        match controller {
+            CC_RPN_MSB | CC_NRPN_MSB => {
+                state.kind = Some(Self::kind_of(controller));
+                state.msb = value;
+                state.value = 0;
+            }
+            CC_RPN_LSB | CC_NRPN_LSB => {
+                state.kind = Some(Self::kind_of(controller));
+                state.lsb = value;
+                state.value = 0;
+            }
+            CC_DATA_ENTRY_MSB => state.value = (value as u16) << 7,
+            CC_DATA_ENTRY_LSB => state.value = state.value & !0x7f | value as u16,
+            CC_DATA_INCREMENT => state.value = (state.value + 1).min(0x3fff),
+            CC_DATA_DECREMENT => state.value = state.value.saturating_sub(1),
+            _ => return None,
+        }
+        if !Self::is_data(controller) || state.number() == NULL_PARAMETER {
+            return None;
+        }
+        state.kind.map(|kind| Parameter {
+            channel,
+            kind,
+            parameter: state.number(),
+            value: state.value,
+        })
+    }
+
+    fn kind_of(controller: u8) -> ParameterKind {
+        if controller == CC_RPN_MSB || controller == CC_RPN_LSB {
+            ParameterKind::Rpn
+        } else {
+            ParameterKind::Nrpn
+        }
+    }
+
+    fn is_data(controller: u8) -> bool {
+        matches!(
+            controller,
+            CC_DATA_ENTRY_MSB | CC_DATA_ENTRY_LSB | CC_DATA_INCREMENT | CC_DATA_DECREMENT
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host;
+    use crate::host::context::AudioContext;
+    use crate::plugin::{Effect, InfoBuilder};
+    use crate::testing::MockHost;
+
+    #[test]
+    fn round_trip() {
+        let events = [
+            MidiEvent::note_on(3, 60, 100),
+            MidiEvent::note_off(3, 60, 40),
+            MidiEvent::PolyPressure {
+                channel: 15,
+                note: 1,
+                pressure: 2,
+            },
+            MidiEvent::control_change(0, 7, 127),
+            MidiEvent::program_change(9, 5),
+            MidiEvent::ChannelPressure {
+                channel: 1,
+                pressure: 64,
+            },
+            MidiEvent::PitchBend {
+                channel: 2,
+                value: 0x3fff,
+            },
+            MidiEvent::Realtime(Realtime::Clock),
+            MidiEvent::Other {
+                status: 0xf2,
+                data1: 1,
+                data2: 2,
+            },
+        ];
+        for event in events.iter() {
+            let message = event.to_message(4);
+            let packed = c_int::from(message.clone());
+            assert_eq!(message, MidiMessage::from(packed));
+            assert_eq!(*event, MidiEvent::from(MidiMessage::from(packed)));
+        }
+
+        // velocity 0 is a note off
+        let message = MidiMessage::from(0x00_00_40_93);
+        assert_eq!(MidiEvent::note_off(3, 64, 0), MidiEvent::from(message));
+        assert_eq!(MidiEvent::note_off(3, 64, 0), MidiEvent::note_on(3, 64, 0));
+
+        let bend = MidiEvent::pitch_bend(0, 0.0).to_message(0);
+        assert_eq!((0xe0, 0x00, 0x40), (bend.status, bend.data1, bend.data2));
+        assert_eq!(
+            MidiEvent::PitchBend {
+                channel: 0,
+                value: 0
+            },
+            MidiEvent::pitch_bend(0, -1.0)
+        );
+    }
+
+    #[test]
+    fn assembles_parameters() {
+        let mut assembler = ParameterAssembler::new();
+        // pitch bend range of 12 semitones and 50 cents
+        let changes: Vec<_> = MidiEvent::rpn(1, 0, 12 << 7 | 50)
+            .iter()
+            .filter_map(|event| assembler.push(event))
+            .collect();
+        let range = |value| Parameter {
+            channel: 1,
+            kind: ParameterKind::Rpn,
+            parameter: 0,
+            value,
+        };
+        assert_eq!(vec![range(12 << 7), range(12 << 7 | 50)], changes);
+        assert_eq!(
+            Some(range(12 << 7 | 51)),
+            assembler.push(&MidiEvent::control_change(1, 96, 0))
+        );
+
+        let changes: Vec<_> = MidiEvent::nrpn(2, 1000, 3)
+            .iter()
+            .filter_map(|event| assembler.push(event))
+            .collect();
+        assert_eq!(ParameterKind::Nrpn, changes[1].kind);
+        assert_eq!((1000, 3), (changes[1].parameter, changes[1].value));
+
+        // null parameter
+        for event in MidiEvent::rpn(2, NULL_PARAMETER, 0).iter() {
+            assert_eq!(None, assembler.push(event));
+        }
+        assert_eq!(None, assembler.push(&MidiEvent::note_on(2, 60, 1)));
+    }
+
+    // Drops low notes, fixes the velocity of the others and steals notes from the preview.
+    #[derive(Debug, Default)]
+    struct Filter;
+
+    crate::test_plugin!(Filter, InfoBuilder::new("Filter", "Filter", 0).build(), {
+        fn handle_message(&mut self, message: host::Message<'_>) -> DispatchResult {
+            match message {
+                host::Message::MidiIn(_) => PreviewAction::Handled.into(),
+                _ => DispatchResult::NONE,
+            }
+        }
+
+        fn midi_in(&mut self, message: MidiMessage) -> MidiAction {
+            match MidiEvent::from(&message) {
+                MidiEvent::NoteOn { note, .. } if note < 36 => MidiAction::Drop,
+                MidiEvent::NoteOn { channel, note, .. } => {
+                    MidiAction::Replace(MidiEvent::note_on(channel, note, 100).to_message(1))
+                }
+                _ => MidiAction::Pass,
+            }
+        }
+    });
+
+    impl Effect for Filter {
+        fn render(
+            &mut self,
+            _ctx: &mut AudioContext<'_>,
+            _input: &[[f32; 2]],
+            _output: &mut [[f32; 2]],
+        ) {
+        }
+    }
+
+    #[test]
+    fn filters_midi_in() {
+        let host = MockHost::new();
+        let mut plugin = host.create_effect::<Filter>();
+
+        let low = MidiEvent::note_on(0, 24, 64).to_message(0);
+        assert_eq!(NULL_MESSAGE, plugin.midi_in(low.into()));
+        let high = MidiEvent::note_on(0, 60, 64).to_message(0);
+        assert_eq!(
+            MidiEvent::note_on(0, 60, 100).to_message(1),
+            plugin.midi_in(high.clone().into()).into()
+        );
+        let cc = c_int::from(MidiEvent::control_change(0, 1, 2).to_message(0));
+        assert_eq!(cc, plugin.midi_in(cc));
+
+        assert_eq!(1, plugin.dispatch(host::Message::MidiIn(high)));
+    }
+}
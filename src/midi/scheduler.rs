@@ -0,0 +1,321 @@
+//! Scheduled MIDI output.
+//!
+//! FL Studio sends delayed MIDI out messages when the MIDI tick reaches the mixer tick they were
+//! sent in, so the output can be timed to a tick. [`Scheduler`](struct.Scheduler.html) queues
+//! messages with a time inside the render block or a number of ticks ahead, and hands them to
+//! the host in the tick they fall into:
+//!
+//! ```ignore
+//! fn handle_message(&mut self, message: host::Message<'_>) -> DispatchResult {
+//!     self.midi_out.on_message(&message);
+//!     DispatchResult::NONE
+//! }
+//!
+//! fn process_event(&mut self, event: host::Event) {
+//!     self.midi_out.on_event(&event);
+//! }
+//!
+//! fn tick(&mut self, _ctx: &mut TickContext<'_>) {
+//!     self.midi_out.tick();
+//! }
+//!
+//! fn render(
+//!     &mut self,
+//!     _ctx: &mut AudioContext<'_>,
+//!     output: &mut [[f32; 2]],
+//!     voices: &mut Self::Voices,
+//! ) {
+//!     self.midi_out.begin_block(output.len());
+//!     // an eighth note (48 ticks at 96 PPQ) starting 100 samples into the block
+//!     self.midi_out.note(Time::Sample(100), 48.0, 0, 60, 100, 0);
+//! }
+//! ```
+//!
+//! The plugin must ask for [`Plugin::tick`](../../plugin/trait.Plugin.html#method.tick) with
+//! [`InfoBuilder::want_new_tick`](../../plugin/struct.InfoBuilder.html#method.want_new_tick).
+//!
+//! Queued messages are dropped when the playback stops or the plugin is flushed, and the notes
+//! which are still on get their note offs right away.
+use std::sync::atomic::Ordering;
+
+use log::trace;
+
+use super::MidiEvent;
+use crate::host::{self, Host};
+use crate::plugin;
+use crate::MidiMessage;
+
+/// When a message should be sent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Time {
+    /// Sample offset in the current render block.
+    Sample(usize),
+    /// Ticks from the start of the current render block (or the current tick outside of
+    /// render).
+    Ticks(f64),
+}
+
+#[derive(Clone, Debug)]
+struct Scheduled {
+    // in ticks since the scheduler was created
+    time: f64,
+    message: MidiMessage,
+}
+
+// a note which has been sent to the host and not released yet
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Sounding {
+    port: u8,
+    channel: u8,
+    note: u8,
+}
+
+/// MIDI output queue.
+#[derive(Debug)]
+pub struct Scheduler {
+    host: Host,
+    tag: plugin::Tag,
+    samples_per_tick: f64,
+    // index of the tick being mixed, -1 before the first one
+    tick: i64,
+    // samples rendered in the current tick before the current block
+    block_start: usize,
+    block_len: usize,
+    queue: Vec<Scheduled>,
+    sounding: Vec<Sounding>,
+}
+
+impl Scheduler {
+    /// Initializer. `capacity` is the number of messages which can be queued without
+    /// allocating.
+    pub fn new(host: &Host, tag: plugin::Tag, capacity: usize) -> Self {
+        Self {
+            host: Host::new(host.host_ptr.load(Ordering::Relaxed)),
+            tag,
+            // 120 BPM, 96 PPQ and 44100 Hz until the host tells otherwise
+            samples_per_tick: 44100.0 * 60.0 / 120.0 / 96.0,
+            tick: -1,
+            block_start: 0,
+            block_len: 0,
+            queue: Vec::with_capacity(capacity),
+            sounding: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Update from a message. Call it from
+    /// [`Plugin::handle_message`](../../plugin/trait.Plugin.html#method.handle_message).
+    ///
+    /// Cancels the queue on [`SetPlaying(false)`](../../host/enum.Message.html#variant.SetPlaying)
+    /// and [`Flush`](../../host/enum.Message.html#variant.Flush).
+    pub fn on_message(&mut self, message: &host::Message<'_>) {
+        match message {
+            host::Message::SetPlaying(false) | host::Message::Flush => self.cancel(),
+            host::Message::SetSamplesPerTick(samples) if *samples > 0.0 => {
+                self.samples_per_tick = *samples as f64
+            }
+            _ => {}
+        }
+    }
+
+    /// Update from an event. Call it from
+    /// [`Plugin::process_event`](../../plugin/trait.Plugin.html#method.process_event).
+    pub fn on_event(&mut self, event: &host::Event) {
+        if let host::Event::Tempo(_, samples_per_tick) = event {
+            if *samples_per_tick > 0 {
+                self.samples_per_tick = *samples_per_tick as f64;
+            }
+        }
+    }
+
+    /// Start a new tick. Call it from [`Plugin::tick`](../../plugin/trait.Plugin.html#method.tick).
+    ///
+    /// Sends the messages which fall into the tick.
+    pub fn tick(&mut self) {
+        self.tick += 1;
+        self.block_start = 0;
+        self.block_len = 0;
+        self.flush_until(self.tick as f64 + 1.0);
+    }
+
+    /// Start a new block of `len` samples. Call it at the start of render.
+    pub fn begin_block(&mut self, len: usize) {
+        self.block_start += self.block_len;
+        self.block_len = len;
+    }
+
+    /// Queue a message.
+    ///
+    /// A message which falls into the current tick is sent right away.
+    pub fn send(&mut self, time: Time, message: impl Into<MidiMessage>) {
+        let time = self.time_of(time);
+        let message = message.into();
+        if time < self.tick as f64 + 1.0 {
+            self.send_now(message);
+            return;
+        }
+        // after the messages with the same time, so they keep their order
+        let index = self
+            .queue
+            .partition_point(|scheduled| scheduled.time <= time);
+        self.queue.insert(index, Scheduled { time, message });
+    }
+
+    /// Queue a note on and its note off `length` ticks later.
+    pub fn note(&mut self, time: Time, length: f64, channel: u8, note: u8, velocity: u8, port: u8) {
+        let start = self.time_of(time) - self.block_time();
+        self.send(
+            Time::Ticks(start),
+            MidiEvent::note_on(channel, note, velocity).to_message(port),
+        );
+        self.send(
+            Time::Ticks(start + length.max(0.0)),
+            MidiEvent::note_off(channel, note, 0).to_message(port),
+        );
+    }
+
+    /// Number of queued messages.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Drop the queued messages and release the notes which are on.
+    pub fn cancel(&mut self) {
+        trace!(
+            "cancel {} MIDI messages, release {} notes",
+            self.queue.len(),
+            self.sounding.len()
+        );
+        self.queue.clear();
+        for sounding in self.sounding.drain(..) {
+            let note_off = MidiEvent::note_off(sounding.channel, sounding.note, 0);
+            self.host
+                .midi_out(self.tag, note_off.to_message(sounding.port));
+        }
+    }
+
+    fn block_time(&self) -> f64 {
+        self.tick.max(0) as f64 + self.block_start as f64 / self.samples_per_tick
+    }
+
+    fn time_of(&self, time: Time) -> f64 {
+        match time {
+            Time::Sample(offset) => self.block_time() + offset as f64 / self.samples_per_tick,
+            Time::Ticks(ticks) => self.block_time() + ticks,
+        }
+    }
+
+    fn flush_until(&mut self, end: f64) {
+        let due = self.queue.partition_point(|scheduled| scheduled.time < end);
+        for index in 0..due {
+            let message = self.queue[index].message.clone();
+            self.send_now(message);
+        }
+        self.queue.drain(..due);
+    }
+
+    fn send_now(&mut self, message: MidiMessage) {
+        let port = message.port;
+        match MidiEvent::from(&message) {
+            MidiEvent::NoteOn { channel, note, .. } => {
+                let sounding = Sounding {
+                    port,
+                    channel,
+                    note,
+                };
+                if !self.sounding.contains(&sounding) {
+                    self.sounding.push(sounding);
+                }
+            }
+            MidiEvent::NoteOff { channel, note, .. } => self.sounding.retain(|sounding| {
+                *sounding
+                    != Sounding {
+                        port,
+                        channel,
+                        note,
+                    }
+            }),
+            _ => {}
+        }
+        self.host.midi_out_del(self.tag, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{HostCall, MockHost};
+
+    fn sent(host: &MockHost) -> Vec<HostCall> {
+        host.take_calls()
+            .into_iter()
+            .filter(|call| matches!(call, HostCall::MidiOut(_) | HostCall::MidiOutDelayed(_)))
+            .collect()
+    }
+
+    #[test]
+    fn sends_in_the_right_tick() {
+        let host = MockHost::new();
+        let mut scheduler = Scheduler::new(&host.host(), plugin::Tag(0), 8);
+        scheduler.on_message(&host::Message::SetSamplesPerTick(100.0));
+
+        scheduler.tick();
+        scheduler.begin_block(100);
+        // in this tick
+        scheduler.send(Time::Sample(50), MidiEvent::program_change(0, 1));
+        // 1.5 and 2.5 ticks later
+        scheduler.note(Time::Sample(150), 1.0, 0, 60, 100, 2);
+        scheduler.send(Time::Ticks(2.0), MidiEvent::control_change(0, 1, 2));
+        assert_eq!(
+            vec![HostCall::MidiOutDelayed(
+                MidiEvent::program_change(0, 1).into()
+            )],
+            sent(&host)
+        );
+        assert_eq!(3, scheduler.pending());
+
+        scheduler.tick();
+        scheduler.begin_block(60);
+        scheduler.begin_block(40);
+        // at 1.6 ticks
+        scheduler.send(Time::Sample(0), MidiEvent::control_change(0, 7, 0));
+        assert_eq!(
+            vec![
+                HostCall::MidiOutDelayed(MidiEvent::note_on(0, 60, 100).to_message(2)),
+                HostCall::MidiOutDelayed(MidiEvent::control_change(0, 7, 0).into()),
+            ],
+            sent(&host)
+        );
+
+        scheduler.tick();
+        assert_eq!(
+            vec![
+                HostCall::MidiOutDelayed(MidiEvent::control_change(0, 1, 2).into()),
+                HostCall::MidiOutDelayed(MidiEvent::note_off(0, 60, 0).to_message(2)),
+            ],
+            sent(&host)
+        );
+        assert_eq!(0, scheduler.pending());
+    }
+
+    #[test]
+    fn releases_notes_on_stop() {
+        let host = MockHost::new();
+        let mut scheduler = Scheduler::new(&host.host(), plugin::Tag(0), 8);
+        scheduler.on_event(&host::Event::Tempo(120.0, 100));
+
+        scheduler.tick();
+        scheduler.note(Time::Ticks(0.0), 4.0, 1, 64, 90, 0);
+        scheduler.note(Time::Ticks(2.0), 4.0, 1, 67, 90, 0);
+        sent(&host);
+
+        scheduler.on_message(&host::Message::SetPlaying(false));
+        assert_eq!(0, scheduler.pending());
+        assert_eq!(
+            vec![HostCall::MidiOut(MidiEvent::note_off(1, 64, 0).into())],
+            sent(&host)
+        );
+
+        scheduler.on_message(&host::Message::Flush);
+        assert!(sent(&host).is_empty());
+    }
+}
@@ -0,0 +1,756 @@
+//! Piano roll notes in musical units.
+//!
+//! [`NotesBuilder`](struct.NotesBuilder.html) makes [`Notes`](../struct.Notes.html) for
+//! [`AddToPianoRoll`](../plugin/message/struct.AddToPianoRoll.html) from bars, beats and steps of
+//! the project:
+//!
+//! ```ignore
+//! let notes = NotesBuilder::new(self.transport.time_signature())
+//!     .scale(Scale::minor(Pitch::from_str("A5")?))
+//!     .velocity(0.8)
+//!     .chord(&["A5".parse()?, "C6".parse()?, "E6".parse()?], Time::Beats(2.0))
+//!     .advance(Time::Beats(2.0))
+//!     .note("G5".parse()?, Time::Steps(2.0))
+//!     .humanize(Time::Ticks(4.0), 0.1, 42)
+//!     .build();
+//! host.on_message(tag, message::AddToPianoRoll(notes));
+//! ```
+//!
+//! With the `smf` feature, Standard MIDI Files can be added too (see
+//! [`NotesBuilder::smf`](struct.NotesBuilder.html#method.smf)).
+//!
+//! [`StepGrid`](struct.StepGrid.html) makes a step sequencer pattern:
+//!
+//! ```ignore
+//! let notes = StepGrid::new(self.transport.time_signature(), 16)
+//!     .hits("x...x...x...x.x.")
+//!     .step(14, Step { velocity: 0.5, ..Step::default() })
+//!     .swing(0.3)
+//!     .build();
+//! host.on_message(tag, message::AddToPianoRoll(notes));
+//! ```
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Note, Notes, NotesFlags, NotesTarget, TimeSignature};
+
+/// Default velocity of FL Studio (100 of 128).
+pub const DEFAULT_VELOCITY: f32 = 100.0 / 128.0;
+
+/// Note number.
+///
+/// Names follow FL Studio, where C5 is 60. Sharps are `#` and flats are `b`: `"F#4"`, `"Bb3"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pitch(pub u8);
+
+/// Error parsing a [`Pitch`](struct.Pitch.html) name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsePitchError(String);
+
+impl fmt::Display for ParsePitchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid note name {:?}", self.0)
+    }
+}
+
+impl error::Error for ParsePitchError {}
+
+impl FromStr for Pitch {
+    type Err = ParsePitchError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let error = || ParsePitchError(name.to_string());
+        let mut chars = name.trim().chars().peekable();
+        let class: i32 = match chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some('C') => 0,
+            Some('D') => 2,
+            Some('E') => 4,
+            Some('F') => 5,
+            Some('G') => 7,
+            Some('A') => 9,
+            Some('B') => 11,
+            _ => return Err(error()),
+        };
+        let mut accidental = 0;
+        while let Some(c) = chars.next_if(|c| *c == '#' || *c == 'b') {
+            accidental += if c == '#' { 1 } else { -1 };
+        }
+        let octave: i32 = chars.collect::<String>().parse().map_err(|_| error())?;
+        let note = octave * 12 + class + accidental;
+        if (0..=127).contains(&note) {
+            Ok(Pitch(note as u8))
+        } else {
+            Err(error())
+        }
+    }
+}
+
+impl fmt::Display for Pitch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NAMES: [&str; 12] = [
+            "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+        ];
+        write!(f, "{}{}", NAMES[self.0 as usize % 12], self.0 / 12)
+    }
+}
+
+impl From<u8> for Pitch {
+    fn from(note: u8) -> Self {
+        Pitch(note)
+    }
+}
+
+/// Scale to quantize the notes to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scale {
+    root: u8,
+    // semitones from the root, sorted, starting with 0
+    intervals: Vec<u8>,
+}
+
+impl Scale {
+    /// Scale of `intervals` (semitones from the root, less than 12) starting at `root`.
+    pub fn new(root: Pitch, intervals: &[u8]) -> Self {
+        let mut intervals: Vec<u8> = intervals.iter().map(|i| i % 12).collect();
+        intervals.push(0);
+        intervals.sort_unstable();
+        intervals.dedup();
+        Self {
+            root: root.0 % 12,
+            intervals,
+        }
+    }
+
+    /// Major scale.
+    pub fn major(root: Pitch) -> Self {
+        Self::new(root, &[0, 2, 4, 5, 7, 9, 11])
+    }
+
+    /// Natural minor scale.
+    pub fn minor(root: Pitch) -> Self {
+        Self::new(root, &[0, 2, 3, 5, 7, 8, 10])
+    }
+
+    /// Major pentatonic scale.
+    pub fn pentatonic(root: Pitch) -> Self {
+        Self::new(root, &[0, 2, 4, 7, 9])
+    }
+
+    /// Whether the pitch is in the scale.
+    pub fn contains(&self, pitch: Pitch) -> bool {
+        let degree = (pitch.0 + 12 - self.root) % 12;
+        self.intervals.contains(&degree)
+    }
+
+    /// The nearest pitch in the scale. Goes down when both neighbours are as near.
+    pub fn quantize(&self, pitch: Pitch) -> Pitch {
+        (0..12)
+            .flat_map(|distance| [pitch.0 as i32 - distance, pitch.0 as i32 + distance])
+            .filter(|note| (0..=127).contains(note))
+            .map(|note| Pitch(note as u8))
+            .find(|pitch| self.contains(*pitch))
+            .unwrap_or(pitch)
+    }
+}
+
+/// Position or length in musical units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Time {
+    /// Bars of the project's time signature.
+    Bars(f64),
+    /// Beats (quarter notes).
+    Beats(f64),
+    /// Steps of the project's time signature.
+    Steps(f64),
+    /// Ticks (PPQ).
+    Ticks(f64),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Humanize {
+    timing: f64,
+    velocity: f32,
+    seed: u64,
+}
+
+/// Builder for [`Notes`](../struct.Notes.html).
+///
+/// Notes are added at the cursor, which starts at 0 and only moves with
+/// [`at`](#method.at) and [`advance`](#method.advance).
+#[derive(Clone, Debug)]
+pub struct NotesBuilder {
+    time_sig: TimeSignature,
+    cursor: f64,
+    velocity: f32,
+    pan: f32,
+    color: u8,
+    scale: Option<Scale>,
+    humanize: Option<Humanize>,
+    notes: Notes,
+}
+
+impl NotesBuilder {
+    /// Initializer. The time signature is the project's one (see
+    /// [`host::Message::SetTimeSig`](../host/enum.Message.html#variant.SetTimeSig)).
+    pub fn new(time_sig: &TimeSignature) -> Self {
+        Self {
+            time_sig: time_sig.clone(),
+            cursor: 0.0,
+            velocity: DEFAULT_VELOCITY,
+            pan: 0.0,
+            color: 0,
+            scale: None,
+            humanize: None,
+            notes: Notes {
+                target: NotesTarget::PianoRoll,
+                notes: Vec::new(),
+                flags: NotesFlags::empty(),
+                pattern: None,
+                channel: None,
+            },
+        }
+    }
+
+    /// Convert time to ticks.
+    pub fn ticks(&self, time: Time) -> f64 {
+        let ppq = self.time_sig.ppq as f64;
+        let step = ppq / self.time_sig.steps_per_beat.max(1) as f64;
+        match time {
+            Time::Bars(bars) => bars * step * self.time_sig.steps_per_bar as f64,
+            Time::Beats(beats) => beats * ppq,
+            Time::Steps(steps) => steps * step,
+            Time::Ticks(ticks) => ticks,
+        }
+    }
+
+    /// Move the cursor to `time`.
+    pub fn at(mut self, time: Time) -> Self {
+        self.cursor = self.ticks(time).max(0.0);
+        self
+    }
+
+    /// Move the cursor by `time`.
+    pub fn advance(mut self, time: Time) -> Self {
+        self.cursor = (self.cursor + self.ticks(time)).max(0.0);
+        self
+    }
+
+    /// Velocity (0..1) of the following notes. [`DEFAULT_VELOCITY`](constant.DEFAULT_VELOCITY.html)
+    /// by default.
+    pub fn velocity(mut self, velocity: f32) -> Self {
+        self.velocity = velocity.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Pan (-1..1) of the following notes.
+    pub fn pan(mut self, pan: f32) -> Self {
+        self.pan = pan.clamp(-1.0, 1.0);
+        self
+    }
+
+    /// Color (MIDI channel, 0..15) of the following notes.
+    pub fn color(mut self, color: u8) -> Self {
+        self.color = color & 0xf;
+        self
+    }
+
+    /// Quantize the following notes to `scale`.
+    pub fn scale(mut self, scale: Scale) -> Self {
+        self.scale = Some(scale);
+        self
+    }
+
+    /// Randomize the notes when building: move them by up to `timing` and change their velocity
+    /// by up to `velocity` (0..1, relative). The same `seed` gives the same result.
+    pub fn humanize(mut self, timing: Time, velocity: f32, seed: u64) -> Self {
+        self.humanize = Some(Humanize {
+            timing: self.ticks(timing).abs(),
+            velocity: velocity.abs(),
+            seed,
+        });
+        self
+    }
+
+    /// See [`NotesFlags`](../struct.NotesFlags.html).
+    pub fn flags(mut self, flags: NotesFlags) -> Self {
+        self.notes.flags = flags;
+        self
+    }
+
+    /// Pattern number. The current one by default.
+    pub fn pattern(mut self, pattern: u32) -> Self {
+        self.notes.pattern = Some(pattern);
+        self
+    }
+
+    /// Channel number. The plugin's channel by default.
+    pub fn channel(mut self, channel: u32) -> Self {
+        self.notes.channel = Some(channel);
+        self
+    }
+
+    /// Add a note at the cursor.
+    pub fn note(self, pitch: Pitch, length: Time) -> Self {
+        let (position, length) = (self.cursor, self.ticks(length));
+        let (velocity, color) = (self.velocity, self.color);
+        self.push(pitch, position, length, velocity, color)
+    }
+
+    /// Add notes at the cursor, all of the same length.
+    pub fn chord(mut self, pitches: &[Pitch], length: Time) -> Self {
+        for pitch in pitches {
+            self = self.note(*pitch, length);
+        }
+        self
+    }
+
+    /// Finish building.
+    pub fn build(mut self) -> Notes {
+        if let Some(humanize) = self.humanize {
+            let mut random = Random(humanize.seed);
+            for note in self.notes.notes.iter_mut() {
+                let shift = (random.next() * humanize.timing).round() as i32;
+                note.position = (note.position + shift).max(0);
+                let vol = note.vol as f32 * (1.0 + random.next() as f32 * humanize.velocity);
+                note.vol = (vol.round() as i32).clamp(1, 128);
+            }
+        }
+        self.notes
+            .notes
+            .sort_by_key(|note| (note.position, note.note));
+        self.notes
+    }
+
+    fn push(mut self, pitch: Pitch, position: f64, length: f64, velocity: f32, color: u8) -> Self {
+        let pitch = match self.scale {
+            Some(ref scale) => scale.quantize(pitch),
+            None => pitch,
+        };
+        self.notes.notes.push(Note {
+            position: position.round() as i32,
+            length: length.round().max(1.0) as i32,
+            pan: (self.pan * 100.0).round() as i32,
+            vol: (velocity * 128.0).round() as i32,
+            note: pitch.0 as i16,
+            color: color as i16,
+            pitch: 0,
+            mod_x: 0.0,
+            mod_y: 0.0,
+        });
+        self
+    }
+}
+
+/// A step of [`StepGrid`](struct.StepGrid.html).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step {
+    /// Velocity (0..1).
+    pub velocity: f32,
+    /// Pan (-1..1).
+    pub pan: f32,
+    /// Fine pitch in cents (-1200..1200).
+    pub pitch: i32,
+    /// Note. The note of the grid if `None`.
+    pub note: Option<Pitch>,
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self {
+            velocity: DEFAULT_VELOCITY,
+            pan: 0.0,
+            pitch: 0,
+            note: None,
+        }
+    }
+}
+
+/// Step sequencer pattern. Builds [`Notes`](../struct.Notes.html) for the step sequencer.
+#[derive(Clone, Debug)]
+pub struct StepGrid {
+    step_ticks: f64,
+    steps: Vec<Option<Step>>,
+    note: Pitch,
+    swing: f64,
+    pattern: Option<u32>,
+    channel: Option<u32>,
+}
+
+impl StepGrid {
+    /// Initializer. `len` steps, all off. The step length comes from the project's time
+    /// signature.
+    pub fn new(time_sig: &TimeSignature, len: usize) -> Self {
+        Self {
+            step_ticks: time_sig.ppq as f64 / time_sig.steps_per_beat.max(1) as f64,
+            steps: vec![None; len],
+            note: Pitch(60),
+            swing: 0.0,
+            pattern: None,
+            channel: None,
+        }
+    }
+
+    /// Turn step `index` on with the `step` parameters. Out of range steps are ignored.
+    pub fn step(mut self, index: usize, step: Step) -> Self {
+        if let Some(slot) = self.steps.get_mut(index) {
+            *slot = Some(step);
+        }
+        self
+    }
+
+    /// Turn step `index` off.
+    pub fn off(mut self, index: usize) -> Self {
+        if let Some(slot) = self.steps.get_mut(index) {
+            *slot = None;
+        }
+        self
+    }
+
+    /// Turn the steps on and off from a string, one character per step from the first one: `.`,
+    /// `-` or space are off, anything else is on with the default parameters.
+    pub fn hits(mut self, hits: &str) -> Self {
+        for (index, hit) in hits.chars().enumerate() {
+            self = match hit {
+                '.' | '-' | ' ' => self.off(index),
+                _ => self.step(index, Step::default()),
+            };
+        }
+        self
+    }
+
+    /// Note of the steps which don't set their own. C5 by default.
+    pub fn note(mut self, note: Pitch) -> Self {
+        self.note = note;
+        self
+    }
+
+    /// Swing (0..1). Delays every second step by up to half a step.
+    pub fn swing(mut self, swing: f32) -> Self {
+        self.swing = swing.clamp(0.0, 1.0) as f64;
+        self
+    }
+
+    /// Pattern number. The current one by default.
+    pub fn pattern(mut self, pattern: u32) -> Self {
+        self.pattern = Some(pattern);
+        self
+    }
+
+    /// Channel number. The plugin's channel by default.
+    pub fn channel(mut self, channel: u32) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// Number of steps.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Whether the grid has no steps.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Finish building. Replaces the steps of the pattern.
+    pub fn build(self) -> Notes {
+        let notes = self
+            .steps
+            .iter()
+            .enumerate()
+            .filter_map(|(index, step)| step.map(|step| (index, step)))
+            .map(|(index, step)| {
+                let swing = if index % 2 == 1 {
+                    self.swing * self.step_ticks / 2.0
+                } else {
+                    0.0
+                };
+                Note {
+                    position: (index as f64 * self.step_ticks + swing).round() as i32,
+                    length: self.step_ticks.round() as i32,
+                    pan: (step.pan.clamp(-1.0, 1.0) * 100.0).round() as i32,
+                    vol: (step.velocity.clamp(0.0, 1.0) * 128.0).round() as i32,
+                    note: step.note.unwrap_or(self.note).0 as i16,
+                    color: 0,
+                    pitch: step.pitch.clamp(-1200, 1200),
+                    mod_x: 0.0,
+                    mod_y: 0.0,
+                }
+            })
+            .collect();
+        Notes {
+            target: NotesTarget::StepSequencer,
+            notes,
+            flags: NotesFlags::EMPTY_FIRST,
+            pattern: self.pattern,
+            channel: self.channel,
+        }
+    }
+}
+
+// xorshift64*, gives -1..1
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> f64 {
+        // zero is a fixed point
+        let mut x = self.0.max(1);
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        let value = x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+        value as f64 / (1_u64 << 52) as f64 - 1.0
+    }
+}
+
+/// Error reading a Standard MIDI File.
+#[cfg(feature = "smf")]
+#[derive(Debug)]
+pub enum SmfError {
+    /// The file is malformed.
+    Parse(midly::Error),
+    /// The file is timed in SMPTE frames, not beats.
+    Timecode,
+}
+
+#[cfg(feature = "smf")]
+impl fmt::Display for SmfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmfError::Parse(e) => write!(f, "can't parse MIDI file: {}", e),
+            SmfError::Timecode => write!(f, "SMPTE timing is not supported"),
+        }
+    }
+}
+
+#[cfg(feature = "smf")]
+impl error::Error for SmfError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SmfError::Parse(e) => Some(e),
+            SmfError::Timecode => None,
+        }
+    }
+}
+
+#[cfg(feature = "smf")]
+impl NotesBuilder {
+    /// Add the notes of a Standard MIDI File at the cursor. All tracks are merged, the MIDI
+    /// channel becomes the color. The scale applies, the velocity and the color set on the
+    /// builder don't.
+    pub fn smf(mut self, data: &[u8]) -> Result<Self, SmfError> {
+        use midly::{MidiMessage, Smf, Timing, TrackEventKind};
+
+        let smf = Smf::parse(data).map_err(SmfError::Parse)?;
+        let ticks_per_beat = match smf.header.timing {
+            Timing::Metrical(ticks) => ticks.as_int() as f64,
+            Timing::Timecode(..) => return Err(SmfError::Timecode),
+        };
+        let scale = self.time_sig.ppq as f64 / ticks_per_beat;
+        let start = self.cursor;
+
+        for track in &smf.tracks {
+            let mut ticks = 0_u64;
+            // (channel, key, start, velocity) of the notes which are on
+            let mut on: Vec<(u8, u8, u64, u8)> = Vec::new();
+            for event in track {
+                ticks += event.delta.as_int() as u64;
+                let (channel, message) = match event.kind {
+                    TrackEventKind::Midi { channel, message } => (channel.as_int(), message),
+                    _ => continue,
+                };
+                match message {
+                    MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                        on.push((channel, key.as_int(), ticks, vel.as_int()))
+                    }
+                    MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                        // the earliest of the notes with this key
+                        if let Some(index) = on
+                            .iter()
+                            .position(|n| n.0 == channel && n.1 == key.as_int())
+                        {
+                            let (channel, key, from, vel) = on.remove(index);
+                            self = self.push(
+                                Pitch(key),
+                                start + from as f64 * scale,
+                                (ticks - from) as f64 * scale,
+                                vel as f32 / 127.0,
+                                channel,
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            // the notes without note off end with the track
+            for (channel, key, from, vel) in on {
+                self = self.push(
+                    Pitch(key),
+                    start + from as f64 * scale,
+                    (ticks - from) as f64 * scale,
+                    vel as f32 / 127.0,
+                    channel,
+                );
+            }
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin;
+    use crate::plugin::message::AddToPianoRoll;
+    use crate::testing::{id, HostCall, MockHost, Payload};
+
+    fn pitch(name: &str) -> Pitch {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn builds_notes() {
+        assert_eq!(Pitch(60), pitch("C5"));
+        assert_eq!(Pitch(66), pitch("f#5"));
+        assert_eq!(Pitch(46), pitch("Bb3"));
+        assert_eq!("A#3", Pitch(46).to_string());
+        assert!("H2".parse::<Pitch>().is_err());
+        assert!("A10".parse::<Pitch>().is_err());
+
+        // 3/4 with 4 steps per beat
+        let time_sig = TimeSignature {
+            steps_per_bar: 12,
+            steps_per_beat: 4,
+            ppq: 96,
+        };
+        let notes = NotesBuilder::new(&time_sig)
+            .flags(NotesFlags::EMPTY_FIRST)
+            .scale(Scale::major(pitch("C5")))
+            .at(Time::Bars(1.0))
+            .velocity(0.5)
+            .chord(&[pitch("C5"), pitch("D#5"), pitch("G5")], Time::Beats(1.0))
+            .advance(Time::Steps(6.0))
+            .pan(-0.5)
+            .note(pitch("F#5"), Time::Ticks(12.0))
+            .build();
+
+        assert_eq!(NotesFlags::EMPTY_FIRST, notes.flags);
+        let summary: Vec<_> = notes
+            .notes
+            .iter()
+            .map(|n| (n.position, n.length, n.note, n.vol, n.pan))
+            .collect();
+        assert_eq!(
+            vec![
+                (288, 96, 60, 64, 0),
+                // D#5 goes down to D5
+                (288, 96, 62, 64, 0),
+                (288, 96, 67, 64, 0),
+                (432, 12, 65, 64, -50),
+            ],
+            summary
+        );
+
+        let humanized = NotesBuilder::new(&time_sig)
+            .note(pitch("C5"), Time::Beats(1.0))
+            .advance(Time::Beats(1.0))
+            .note(pitch("C5"), Time::Beats(1.0))
+            .humanize(Time::Ticks(4.0), 0.2, 7);
+        let first = humanized.clone().build();
+        assert_eq!(first, humanized.build());
+        for (note, position) in first.notes.iter().zip([0, 96].iter()) {
+            assert!((note.position - position).abs() <= 4);
+            assert!((note.vol - 100).abs() <= 20);
+        }
+    }
+
+    #[test]
+    fn step_grid_goes_to_step_sequencer() {
+        let time_sig = TimeSignature {
+            steps_per_bar: 16,
+            steps_per_beat: 4,
+            ppq: 96,
+        };
+        let grid = StepGrid::new(&time_sig, 8)
+            .note(pitch("D5"))
+            .hits("x.x-xx  x")
+            .step(
+                5,
+                Step {
+                    velocity: 0.25,
+                    pan: 1.0,
+                    pitch: 50,
+                    note: Some(pitch("E5")),
+                },
+            )
+            .off(0)
+            .swing(0.5)
+            .pattern(3);
+        assert_eq!(8, grid.len());
+
+        let host = MockHost::new();
+        host.host()
+            .on_message(plugin::Tag(0), AddToPianoRoll(grid.build()));
+        let notes = match host.messages(id::ADD_TO_PIANO_ROLL).pop() {
+            Some(HostCall::Message {
+                payload: Payload::Notes(notes),
+                ..
+            }) => notes,
+            call => panic!("unexpected {:?}", call),
+        };
+        assert_eq!(NotesTarget::StepSequencer, notes.target);
+        assert_eq!(Some(3), notes.pattern);
+        let summary: Vec<_> = notes
+            .notes
+            .iter()
+            .map(|n| (n.position, n.length, n.note, n.vol, n.pan, n.pitch))
+            .collect();
+        // odd steps are 6 ticks late
+        assert_eq!(
+            vec![
+                (48, 24, 62, 100, 0, 0),
+                (96, 24, 62, 100, 0, 0),
+                (126, 24, 64, 32, 100, 50),
+            ],
+            summary
+        );
+    }
+
+    #[cfg(feature = "smf")]
+    #[test]
+    fn imports_smf() {
+        // format 0, 48 ticks per beat; E5 for a beat, then an overlapping G5 without a note off
+        let track: &[u8] = &[
+            0x00, 0x90, 64, 127, // E5 on
+            0x18, 0x91, 67, 64, // G5 on, channel 1
+            0x18, 0x90, 64, 0, // E5 off
+            0x30, 0xff, 0x2f, 0x00, // end of track
+        ];
+        let mut data = b"MThd\x00\x00\x00\x06\x00\x00\x00\x01\x00\x30MTrk".to_vec();
+        data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        data.extend_from_slice(track);
+
+        let time_sig = TimeSignature {
+            steps_per_bar: 16,
+            steps_per_beat: 4,
+            ppq: 96,
+        };
+        let notes = NotesBuilder::new(&time_sig)
+            .at(Time::Bars(1.0))
+            .smf(&data)
+            .unwrap()
+            .build();
+        let summary: Vec<_> = notes
+            .notes
+            .iter()
+            .map(|n| (n.position, n.length, n.note, n.vol, n.color))
+            .collect();
+        assert_eq!(vec![(384, 96, 64, 128, 0), (432, 144, 67, 65, 1)], summary);
+        assert!(matches!(
+            NotesBuilder::new(&time_sig).smf(b"RIFF"),
+            Err(SmfError::Parse(_))
+        ));
+    }
+}
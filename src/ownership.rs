@@ -0,0 +1,137 @@
+//! Ownership of the memory exchanged with the host.
+//!
+//! A pointer the host gives or takes is one of:
+//!
+//! - [`RustOwned`](struct.RustOwned.html) — allocated by the plugin and lent to the host for the
+//!   duration of a call, for example the [`Time`](../struct.Time.html) the host fills for
+//!   [`GetMixingTime`](../plugin/message/struct.GetMixingTime.html). The plugin frees it.
+//! - [`HostOwned`](struct.HostOwned.html) — allocated and freed by the host, for example the
+//!   entry returned for [`GetParamMenuEntry`](../plugin/message/struct.GetParamMenuEntry.html).
+//!   The plugin only reads it.
+//! - [`Borrowed`](struct.Borrowed.html) — host memory valid only until the host is called
+//!   again, for example the path returned for
+//!   [`LocateDataFile`](../plugin/message/struct.LocateDataFile.html). The plugin copies what it
+//!   needs right away (see [`copy_str`](fn.copy_str.html)).
+//!
+//! Host memory is never turned into a `Box` or a `CString`, which would free it with the Rust
+//! allocator.
+use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::os::raw::c_char;
+use std::ptr::NonNull;
+
+use crate::intptr_t;
+
+/// Plugin memory lent to the host for the duration of a call.
+#[derive(Debug)]
+pub struct RustOwned<T>(Box<T>);
+
+impl<T> RustOwned<T> {
+    /// Initializer.
+    pub fn new(value: T) -> Self {
+        Self(Box::new(value))
+    }
+
+    /// The pointer to give to the host. It stays valid until this is dropped.
+    pub fn as_mut_ptr(&mut self) -> intptr_t {
+        let ptr: *mut T = &mut *self.0;
+        ptr as intptr_t
+    }
+
+    /// The value, to read it or reuse it for another call.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+
+    /// Take the value back after the host is done with it.
+    pub fn into_inner(self) -> T {
+        *self.0
+    }
+}
+
+/// Memory which the host allocated and frees itself. It's only read.
+#[derive(Debug)]
+pub struct HostOwned<T>(NonNull<T>);
+
+impl<T> HostOwned<T> {
+    /// Wrap a pointer from the host. Returns `None` if it's null.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must point to a valid `T` which the host doesn't free while this is used.
+    pub unsafe fn from_raw(value: intptr_t) -> Option<Self> {
+        NonNull::new(value as *mut T).map(Self)
+    }
+
+    /// Read the value.
+    pub fn borrow(&self) -> Borrowed<'_, T> {
+        Borrowed(self.0, PhantomData)
+    }
+}
+
+/// Host memory valid only for `'a`.
+#[derive(Debug)]
+pub struct Borrowed<'a, T>(NonNull<T>, PhantomData<&'a T>);
+
+impl<'a, T> Borrowed<'a, T> {
+    /// Wrap a pointer from the host. Returns `None` if it's null.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must point to a valid `T` for `'a`.
+    pub unsafe fn from_raw(value: intptr_t) -> Option<Self> {
+        NonNull::new(value as *mut T).map(|ptr| Self(ptr, PhantomData))
+    }
+
+    /// Get the value.
+    pub fn get(&self) -> &'a T {
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl<T: Copy> Borrowed<'_, T> {
+    /// Copy the value out.
+    pub fn copy(&self) -> T {
+        *self.get()
+    }
+}
+
+/// Copy a NUL-terminated string the host returned. A null pointer gives an empty string and
+/// invalid UTF-8 is replaced.
+///
+/// # Safety
+///
+/// The pointer must be null or point to a NUL-terminated string which is valid during this call.
+pub unsafe fn copy_str(value: intptr_t) -> String {
+    if value == 0 {
+        return String::new();
+    }
+    CStr::from_ptr(value as *const c_char)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+
+    #[test]
+    fn copies_and_lends() {
+        let host_string = CString::new("C:\\data\\file.wav").unwrap();
+        let copy = unsafe { copy_str(host_string.as_ptr() as intptr_t) };
+        drop(host_string);
+        assert_eq!("C:\\data\\file.wav", copy);
+        assert_eq!("", unsafe { copy_str(0) });
+
+        let mut lent = RustOwned::new([0_i32; 3]);
+        let ptr = lent.as_mut_ptr();
+        // the host fills it
+        unsafe { *(ptr as *mut [i32; 3]) = [1, 2, 3] };
+        let host = unsafe { HostOwned::<[i32; 3]>::from_raw(ptr) }.unwrap();
+        assert_eq!([1, 2, 3], host.borrow().copy());
+        assert_eq!([1, 2, 3], lent.into_inner());
+        assert!(unsafe { Borrowed::<i32>::from_raw(0) }.is_none());
+    }
+}
@@ -0,0 +1,639 @@
+//! Declarative plugin parameters.
+//!
+//! Describe the parameters once with [`Param`](struct.Param.html), collect them in
+//! [`Params`](struct.Params.html) and forward the host's parameter requests to it:
+//!
+//! ```ignore
+//! const GAIN: usize = 0;
+//! const MODE: usize = 1;
+//!
+//! fn new(host: Host, tag: Tag) -> Self {
+//!     let params = Params::builder()
+//!         .param(Param::float("gain", "Gain", 0.0, 2.0, 1.0).unit("x"))
+//!         .param(Param::choice("mode", "Mode", &["Clean", "Warm"], 0))
+//!         .build(&host, tag);
+//!     Self { host, tag, params }
+//! }
+//!
+//! fn info(&self) -> Info {
+//!     InfoBuilder::new("Gain", "Gain", 0)
+//!         .with_params(&self.params)
+//!         .build()
+//! }
+//!
+//! fn handle_message(&mut self, message: host::Message<'_>) -> DispatchResult {
+//!     if let Some(result) = self.params.on_message(&message) {
+//!         return result;
+//!     }
+//!     DispatchResult::NONE
+//! }
+//!
+//! fn name_of(&self, value: GetName) -> String {
+//!     self.params.name_of(&value).unwrap_or_default()
+//! }
+//!
+//! fn handle_param(
+//!     &mut self,
+//!     index: usize,
+//!     value: ValuePtr,
+//!     flags: ProcessParamFlags,
+//! ) -> DispatchResult {
+//!     self.params.process_param(index, value, flags)
+//! }
+//! ```
+//!
+//! The values are kept in the parameter's own units. `Params::get(GAIN)` is `1.0` by default,
+//! `Params::get(MODE)` is `0.0` or `1.0`.
+//!
+//! Values used in render can be smoothed with [`smooth`](smooth/index.html).
+pub mod smooth;
+
+use std::sync::atomic::Ordering;
+
+use log::{debug, trace};
+
+use self::smooth::{Smoother, Smoothing};
+use crate::host::{self, GetName, Host};
+use crate::plugin::Tag;
+use crate::{
+    intptr_t, AsRawPtr, DispatchResult, FromRawPtr, ParameterFlags, ProcessModeFlags,
+    ProcessParamFlags, ValuePtr,
+};
+
+/// Values coming with [`ProcessParamFlags::FROM_MIDI`](
+/// ../struct.ProcessParamFlags.html#associatedconstant.FROM_MIDI) are in `0..=MIDI_RANGE`.
+pub const MIDI_RANGE: f64 = 65536.0;
+
+/// How the value of a parameter is sent to and from the host.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParamKind {
+    /// The host sees a normalized (0..1) single float
+    /// ([`ParameterFlags::FLOAT`](../struct.ParameterFlags.html#associatedconstant.FLOAT)).
+    Float,
+    /// The host sees the integer value.
+    Integer,
+}
+
+/// Description of one parameter.
+#[derive(Clone, Debug)]
+pub struct Param {
+    id: &'static str,
+    name: String,
+    kind: ParamKind,
+    min: f64,
+    max: f64,
+    default: f64,
+    unit: String,
+    flags: ParameterFlags,
+    labels: Vec<String>,
+    display: Option<fn(f64) -> String>,
+    smoothing: Smoothing,
+}
+
+impl Param {
+    /// A float parameter in `min..=max` range.
+    ///
+    /// `id` is a stable identifier of the parameter, `name` is shown to the user.
+    pub fn float(id: &'static str, name: &str, min: f64, max: f64, default: f64) -> Self {
+        Self::new(id, name, ParamKind::Float, min, max, default)
+    }
+
+    /// An integer parameter in `min..=max` range.
+    pub fn integer(id: &'static str, name: &str, min: i32, max: i32, default: i32) -> Self {
+        Self::new(
+            id,
+            name,
+            ParamKind::Integer,
+            min.into(),
+            max.into(),
+            default.into(),
+        )
+        .flags(ParameterFlags::CANT_INTERPOLATE)
+    }
+
+    /// An integer parameter choosing one of `labels`. The value is the index of the label.
+    pub fn choice(id: &'static str, name: &str, labels: &[&str], default: usize) -> Self {
+        let mut param = Self::integer(
+            id,
+            name,
+            0,
+            labels.len().saturating_sub(1) as i32,
+            default as i32,
+        );
+        param.labels = labels.iter().map(|label| label.to_string()).collect();
+        param
+    }
+
+    fn new(
+        id: &'static str,
+        name: &str,
+        kind: ParamKind,
+        min: f64,
+        max: f64,
+        default: f64,
+    ) -> Self {
+        let (min, max) = if min <= max { (min, max) } else { (max, min) };
+        Self {
+            id,
+            name: name.to_string(),
+            kind,
+            min,
+            max,
+            default: default.clamp(min, max),
+            unit: String::new(),
+            flags: ParameterFlags::empty(),
+            labels: Vec::new(),
+            display: None,
+            smoothing: Smoothing::None,
+        }
+    }
+
+    /// Set the unit shown after the value, like `dB` or `Hz`.
+    pub fn unit(mut self, unit: &str) -> Self {
+        self.unit = unit.to_string();
+        self
+    }
+
+    /// Add [`ParameterFlags`](../struct.ParameterFlags.html).
+    ///
+    /// [`ParameterFlags::FLOAT`](../struct.ParameterFlags.html#associatedconstant.FLOAT) follows
+    /// from the parameter's kind and is ignored here.
+    pub fn flags(mut self, flags: ParameterFlags) -> Self {
+        self.flags |= flags - ParameterFlags::FLOAT;
+        self
+    }
+
+    /// Use a custom function to show the value.
+    pub fn display(mut self, display: fn(f64) -> String) -> Self {
+        self.display = Some(display);
+        self
+    }
+
+    /// Smooth the value read with
+    /// [`Params::next_sample`](struct.Params.html#method.next_sample) and
+    /// [`Params::next_block`](struct.Params.html#method.next_block).
+    pub fn smoothing(mut self, smoothing: Smoothing) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    /// The identifier.
+    pub fn id(&self) -> &'static str {
+        self.id
+    }
+
+    /// The name shown to the user.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The kind.
+    pub fn kind(&self) -> ParamKind {
+        self.kind
+    }
+
+    /// The minimum value.
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+
+    /// The maximum value.
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    /// The default value.
+    pub fn default_value(&self) -> f64 {
+        self.default
+    }
+
+    /// The smoothing.
+    pub fn smoothing_kind(&self) -> Smoothing {
+        self.smoothing
+    }
+
+    /// The flags reported to the host.
+    pub fn parameter_flags(&self) -> ParameterFlags {
+        match self.kind {
+            ParamKind::Float => self.flags | ParameterFlags::FLOAT,
+            ParamKind::Integer => self.flags,
+        }
+    }
+
+    /// Clamp the value to the range (and round it for integer parameters).
+    pub fn constrain(&self, value: f64) -> f64 {
+        let value = if value.is_nan() { self.default } else { value };
+        let value = value.clamp(self.min, self.max);
+        match self.kind {
+            ParamKind::Float => value,
+            ParamKind::Integer => value.round(),
+        }
+    }
+
+    /// Map the value to `0..=1`.
+    pub fn normalize(&self, value: f64) -> f64 {
+        if self.max > self.min {
+            ((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Map a `0..=1` value to the range.
+    pub fn denormalize(&self, value: f64) -> f64 {
+        self.constrain(self.min + value.clamp(0.0, 1.0) * (self.max - self.min))
+    }
+
+    /// Convert the value the way the host sends it.
+    pub fn from_raw(&self, value: intptr_t) -> f64 {
+        match self.kind {
+            ParamKind::Float => self.denormalize(f32::from_raw_ptr(value).into()),
+            ParamKind::Integer => self.constrain(value as f64),
+        }
+    }
+
+    /// Convert the value the way the host expects it.
+    pub fn to_raw(&self, value: f64) -> intptr_t {
+        match self.kind {
+            ParamKind::Float => (self.normalize(value) as f32).as_raw_ptr(),
+            ParamKind::Integer => self.constrain(value) as intptr_t,
+        }
+    }
+
+    /// Text representation of the value.
+    pub fn format(&self, value: f64) -> String {
+        if let Some(display) = self.display {
+            return display(value);
+        }
+
+        let text = match self.kind {
+            ParamKind::Integer => {
+                let value = self.constrain(value);
+                match self.labels.get(value as usize) {
+                    Some(label) if value >= 0.0 => return label.clone(),
+                    _ => format!("{}", value as i64),
+                }
+            }
+            ParamKind::Float => format!("{:.2}", value),
+        };
+
+        if self.unit.is_empty() {
+            text
+        } else {
+            format!("{} {}", text, self.unit)
+        }
+    }
+}
+
+/// Use this to instantiate [`Params`](struct.Params.html).
+#[derive(Clone, Debug, Default)]
+pub struct ParamsBuilder {
+    params: Vec<Param>,
+}
+
+impl ParamsBuilder {
+    /// Add a parameter. Parameters get their indexes in the order they're added.
+    pub fn param(mut self, param: Param) -> Self {
+        debug_assert!(
+            self.params.iter().all(|p| p.id != param.id),
+            "duplicate parameter id {}",
+            param.id
+        );
+        self.params.push(param);
+        self
+    }
+
+    /// Finish the parameter set for the plugin with `tag`.
+    pub fn build(self, host: &Host, tag: Tag) -> Params {
+        let values = self.params.iter().map(|param| param.default).collect();
+        let smoothers = self
+            .params
+            .iter()
+            .map(|param| Smoother::new(param.smoothing, param.default))
+            .collect();
+        Params {
+            host: Host::new(host.host_ptr.load(Ordering::Relaxed)),
+            tag,
+            params: self.params,
+            values,
+            smoothers,
+        }
+    }
+}
+
+/// The parameters of a plugin together with their current values.
+///
+/// It answers the host's parameter requests (see the [module docs](index.html)) and notifies the
+/// host when the plugin changes a value itself.
+#[derive(Debug)]
+pub struct Params {
+    host: Host,
+    tag: Tag,
+    params: Vec<Param>,
+    values: Vec<f64>,
+    smoothers: Vec<Smoother>,
+}
+
+impl Params {
+    /// Start describing parameters.
+    pub fn builder() -> ParamsBuilder {
+        ParamsBuilder::default()
+    }
+
+    /// The number of parameters.
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Whether there are no parameters.
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// Description of the parameter.
+    pub fn param(&self, index: usize) -> Option<&Param> {
+        self.params.get(index)
+    }
+
+    /// Iterate over the descriptions.
+    pub fn iter(&self) -> impl Iterator<Item = &Param> {
+        self.params.iter()
+    }
+
+    /// Index of the parameter with `id`.
+    pub fn index_of(&self, id: &str) -> Option<usize> {
+        self.params.iter().position(|param| param.id == id)
+    }
+
+    /// The current value of the parameter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of range.
+    pub fn get(&self, index: usize) -> f64 {
+        self.values[index]
+    }
+
+    /// The current values of all parameters.
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    /// The smoothed value for the next sample. See [`smooth`](smooth/index.html).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of range.
+    pub fn next_sample(&mut self, index: usize) -> f64 {
+        self.smoothers[index].next_sample()
+    }
+
+    /// Skip `len` samples and get the smoothed value at the end.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of range.
+    pub fn next_block(&mut self, index: usize, len: usize) -> f64 {
+        self.smoothers[index].next_block(len)
+    }
+
+    /// The smoother of the parameter.
+    pub fn smoother(&self, index: usize) -> Option<&Smoother> {
+        self.smoothers.get(index)
+    }
+
+    /// Change the value from within the plugin and let the host know, so it can record it.
+    ///
+    /// Does nothing if `index` is out of range.
+    pub fn set(&mut self, index: usize, value: f64) {
+        if let Some(raw) = self.update(index, value) {
+            self.host
+                .on_parameter(self.tag, index, ValuePtr::from_raw_ptr(raw));
+        }
+    }
+
+    /// Change the value without notifying the host, like when loading the state. The smoothed
+    /// value jumps to it.
+    ///
+    /// Does nothing if `index` is out of range.
+    pub fn set_silently(&mut self, index: usize, value: f64) {
+        self.update(index, value);
+        if let Some(smoother) = self.smoothers.get_mut(index) {
+            smoother.reset(self.values[index]);
+        }
+    }
+
+    /// Set all values to their defaults without notifying the host.
+    pub fn reset(&mut self) {
+        for ((value, smoother), param) in self
+            .values
+            .iter_mut()
+            .zip(&mut self.smoothers)
+            .zip(&self.params)
+        {
+            *value = param.default;
+            smoother.reset(param.default);
+        }
+    }
+
+    // Returns the raw value if it has changed.
+    fn update(&mut self, index: usize, value: f64) -> Option<intptr_t> {
+        let param = self.params.get(index)?;
+        let value = param.constrain(value);
+        if self.values[index] == value {
+            return None;
+        }
+        self.values[index] = value;
+        self.smoothers[index].set_target(value);
+        Some(param.to_raw(value))
+    }
+
+    /// Handle [`Plugin::handle_param`](../plugin/trait.Plugin.html#method.handle_param).
+    pub fn process_param(
+        &mut self,
+        index: usize,
+        value: ValuePtr,
+        flags: ProcessParamFlags,
+    ) -> DispatchResult {
+        trace!("process param {} {:?} {:?}", index, value, flags);
+
+        let param = match self.params.get(index) {
+            Some(param) => param,
+            None => {
+                debug!("unknown parameter {}", index);
+                return DispatchResult::NONE;
+            }
+        };
+
+        let new_value = if flags.contains(ProcessParamFlags::FROM_MIDI) {
+            param.denormalize(value.get::<intptr_t>() as f64 / MIDI_RANGE)
+        } else {
+            param.from_raw(value.get())
+        };
+
+        if flags.contains(ProcessParamFlags::UPDATE_VALUE) {
+            self.values[index] = new_value;
+            self.smoothers[index].set_target(new_value);
+        }
+
+        if flags.contains(ProcessParamFlags::SHOW_HINT) {
+            let hint = format!("{}: {}", param.name, param.format(self.values[index]));
+            self.host.on_hint(self.tag, hint);
+        }
+
+        if flags.contains(ProcessParamFlags::FROM_MIDI) {
+            DispatchResult::raw(param.to_raw(new_value))
+        } else if flags.contains(ProcessParamFlags::GET_VALUE) {
+            DispatchResult::raw(param.to_raw(self.values[index]))
+        } else {
+            DispatchResult::NONE
+        }
+    }
+
+    /// Handle [`Plugin::name_of`](../plugin/trait.Plugin.html#tymethod.name_of) for parameters.
+    ///
+    /// Returns `None` if it's not about a parameter of this set.
+    pub fn name_of(&self, value: &GetName) -> Option<String> {
+        match *value {
+            GetName::Param(index) => self.params.get(index).map(|param| param.name.clone()),
+            GetName::ParamValue(index, value) => self
+                .params
+                .get(index)
+                .map(|param| param.format(param.from_raw(value))),
+            _ => None,
+        }
+    }
+
+    /// Handle [`host::Message::GetParamInfo`](../host/enum.Message.html#variant.GetParamInfo).
+    ///
+    /// Returns `None` for other messages. It also updates the smoothers on
+    /// [`Flush`](../host/enum.Message.html#variant.Flush),
+    /// [`SetSampleRate`](../host/enum.Message.html#variant.SetSampleRate) and
+    /// [`ProcessMode`](../host/enum.Message.html#variant.ProcessMode).
+    pub fn on_message(&mut self, message: &host::Message<'_>) -> Option<DispatchResult> {
+        match *message {
+            host::Message::Flush => {
+                for smoother in &mut self.smoothers {
+                    smoother.reset(smoother.target());
+                }
+                None
+            }
+            host::Message::SetSampleRate(rate) => {
+                for smoother in &mut self.smoothers {
+                    smoother.set_sample_rate(rate as f64);
+                }
+                None
+            }
+            host::Message::ProcessMode(flags) => {
+                let high_quality = flags.contains(ProcessModeFlags::IS_RENDERING);
+                for smoother in &mut self.smoothers {
+                    smoother.set_high_quality(high_quality);
+                }
+                None
+            }
+            host::Message::GetParamInfo(index) => Some(
+                self.params
+                    .get(index)
+                    .map(Param::parameter_flags)
+                    .unwrap_or_else(ParameterFlags::empty)
+                    .into(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{HostCall, MockHost};
+
+    fn params(host: &MockHost) -> Params {
+        Params::builder()
+            .param(Param::float("gain", "Gain", -12.0, 12.0, 0.0).unit("dB"))
+            .param(Param::choice("mode", "Mode", &["Clean", "Warm", "Hot"], 1))
+            .build(&host.host(), Tag(0))
+    }
+
+    #[test]
+    fn speaks_host_protocol() {
+        let host = MockHost::new();
+        let mut params = params(&host);
+
+        let raw = params.process_param(
+            0,
+            ValuePtr::from_raw_ptr(0.75_f32.as_raw_ptr()),
+            ProcessParamFlags::UPDATE_VALUE | ProcessParamFlags::GET_VALUE,
+        );
+        assert_eq!(0.75_f32.as_raw_ptr(), raw.as_raw_ptr());
+        assert_eq!(6.0, params.get(0));
+
+        let raw = params.process_param(
+            1,
+            ValuePtr::from_raw_ptr(65536),
+            ProcessParamFlags::FROM_MIDI,
+        );
+        assert_eq!(2, raw.as_raw_ptr());
+        assert_eq!(1.0, params.get(1));
+
+        assert_eq!(Some("Gain".to_string()), params.name_of(&GetName::Param(0)));
+        assert_eq!(
+            Some("-6.00 dB".to_string()),
+            params.name_of(&GetName::ParamValue(0, 0.25_f32.as_raw_ptr()))
+        );
+        assert_eq!(
+            Some("Hot".to_string()),
+            params.name_of(&GetName::ParamValue(1, 2))
+        );
+        assert_eq!(None, params.name_of(&GetName::Param(2)));
+
+        let flags = params
+            .on_message(&host::Message::GetParamInfo(1))
+            .map(|flags| flags.as_raw_ptr());
+        assert_eq!(Some(ParameterFlags::CANT_INTERPOLATE.bits()), flags);
+    }
+
+    #[test]
+    fn notifies_host_on_change() {
+        let host = MockHost::new();
+        let mut params = params(&host);
+
+        params.set(1, 2.0);
+        params.set(1, 2.0);
+        params.set_silently(0, 12.0);
+
+        assert_eq!(
+            vec![HostCall::Parameter { index: 1, value: 2 }],
+            host.calls()
+        );
+        assert_eq!(&[12.0, 2.0], params.values());
+    }
+
+    #[test]
+    fn smooths_changes() {
+        let host = MockHost::new();
+        let mut params = Params::builder()
+            .param(Param::float("gain", "Gain", 0.0, 1.0, 0.0).smoothing(Smoothing::Linear(1.0)))
+            .build(&host.host(), Tag(0));
+        params.on_message(&host::Message::SetSampleRate(10000));
+        params.on_message(&host::Message::ProcessMode(
+            ProcessModeFlags::HQ_NON_REALTIME | ProcessModeFlags::IS_RENDERING,
+        ));
+
+        params.process_param(
+            0,
+            ValuePtr::from_raw_ptr(1.0_f32.as_raw_ptr()),
+            ProcessParamFlags::UPDATE_VALUE,
+        );
+        assert_eq!(1.0, params.get(0));
+        assert_eq!(0.0, params.next_sample(0));
+        assert!((params.next_block(0, 4) - 0.5).abs() < 1e-9);
+
+        params.on_message(&host::Message::Flush);
+        assert_eq!(1.0, params.next_sample(0));
+
+        params.set(0, 0.0);
+        params.set_silently(0, 0.5);
+        assert_eq!(0.5, params.next_sample(0));
+    }
+}
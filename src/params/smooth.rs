@@ -0,0 +1,274 @@
+//! Parameter smoothing.
+//!
+//! Automation comes once per tick, so a value applied as is makes steps which can be heard
+//! (zipper noise). A [`Smoother`](struct.Smoother.html) moves to the new value over time. It's
+//! attached to a parameter with [`Param::smoothing`](../struct.Param.html#method.smoothing) and
+//! read in render:
+//!
+//! ```ignore
+//! let params = Params::builder()
+//!     .param(Param::float("gain", "Gain", 0.0, 2.0, 1.0).smoothing(Smoothing::Linear(20.0)))
+//!     .param(Param::float("cutoff", "Cutoff", 20.0, 20000.0, 1000.0)
+//!         .smoothing(Smoothing::Multiplicative(50.0)))
+//!     .build(&host, tag);
+//!
+//! fn render(&mut self, ctx: &mut AudioContext<'_>, input: &[[f32; 2]], output: &mut [[f32; 2]]) {
+//!     // per block
+//!     let cutoff = self.params.next_block(CUTOFF, output.len());
+//!     self.filter.set_cutoff(cutoff);
+//!     for (input, output) in input.iter().zip(output) {
+//!         // per sample
+//!         let gain = self.params.next_sample(GAIN) as f32;
+//!         output[0] = self.filter.process(input[0]) * gain;
+//!         output[1] = self.filter.process(input[1]) * gain;
+//!     }
+//! }
+//! ```
+//!
+//! In realtime, the value moves every 16 samples, so expensive updates (like filter
+//! coefficients) can be done only when it changes. When FL renders to file (the process mode has
+//! [`IS_RENDERING`](../../struct.ProcessModeFlags.html#associatedconstant.IS_RENDERING)), it
+//! moves every sample.
+//!
+//! The smoothers jump to their values on
+//! [`host::Message::Flush`](../../host/enum.Message.html#variant.Flush) and
+//! [`host::Message::SetSampleRate`](../../host/enum.Message.html#variant.SetSampleRate) (see
+//! [`Params::on_message`](../struct.Params.html#method.on_message)).
+
+/// Samples between the value updates in realtime.
+pub const CONTROL_INTERVAL: usize = 16;
+
+// The one-pole smoother stops when it's this close.
+const EPSILON: f64 = 1e-9;
+
+/// How the value moves to its target. The times are in ms.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Smoothing {
+    /// The value jumps.
+    #[default]
+    None,
+    /// Straight line, reaching the target in time.
+    Linear(f64),
+    /// Exponential approach with the time constant (63% of the way).
+    OnePole(f64),
+    /// Constant ratio per sample, reaching the target in time. It sounds even for frequencies
+    /// and gains. Both values must be positive, otherwise the value jumps.
+    Multiplicative(f64),
+}
+
+/// Moves a value to its target.
+#[derive(Clone, Debug)]
+pub struct Smoother {
+    smoothing: Smoothing,
+    sample_rate: f64,
+    interval: usize,
+    current: f64,
+    target: f64,
+    // samples returned by next_sample() since the last update
+    pending: usize,
+    // samples until the target is reached (linear and multiplicative)
+    remaining: usize,
+    // added per sample (linear) or multiplied by (multiplicative)
+    step: f64,
+    // per sample (one-pole)
+    coef: f64,
+}
+
+impl Smoother {
+    /// Initializer. The sample rate is 44100 Hz until
+    /// [`set_sample_rate`](#method.set_sample_rate) is called.
+    pub fn new(smoothing: Smoothing, value: f64) -> Self {
+        let mut smoother = Self {
+            smoothing,
+            sample_rate: 44100.0,
+            interval: CONTROL_INTERVAL,
+            current: value,
+            target: value,
+            pending: 0,
+            remaining: 0,
+            step: 0.0,
+            coef: 0.0,
+        };
+        smoother.update_coef();
+        smoother
+    }
+
+    /// The smoothing.
+    pub fn smoothing(&self) -> Smoothing {
+        self.smoothing
+    }
+
+    /// The current value.
+    pub fn current(&self) -> f64 {
+        self.current
+    }
+
+    /// The value it moves to.
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    /// Whether the value is still moving.
+    pub fn is_smoothing(&self) -> bool {
+        self.current != self.target
+    }
+
+    /// Move to `target`.
+    pub fn set_target(&mut self, target: f64) {
+        self.target = target;
+        let samples = match self.smoothing {
+            Smoothing::None => 0,
+            Smoothing::Linear(ms) | Smoothing::Multiplicative(ms) => self.samples_of(ms),
+            Smoothing::OnePole(_) => return,
+        };
+        if samples == 0 {
+            self.reset(target);
+            return;
+        }
+        match self.smoothing {
+            Smoothing::Linear(_) => self.step = (target - self.current) / samples as f64,
+            Smoothing::Multiplicative(_) if self.current > 0.0 && target > 0.0 => {
+                self.step = (target / self.current).powf(1.0 / samples as f64)
+            }
+            _ => {
+                self.reset(target);
+                return;
+            }
+        }
+        self.remaining = samples;
+        self.pending = 0;
+    }
+
+    /// Jump to `value`.
+    pub fn reset(&mut self, value: f64) {
+        self.current = value;
+        self.target = value;
+        self.remaining = 0;
+        self.pending = 0;
+    }
+
+    /// Change the sample rate. The value jumps to the target.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        if sample_rate > 0.0 {
+            self.sample_rate = sample_rate;
+            self.update_coef();
+        }
+        self.reset(self.target);
+    }
+
+    /// Move the value every sample (`true`) or every
+    /// [`CONTROL_INTERVAL`](constant.CONTROL_INTERVAL.html) samples.
+    pub fn set_high_quality(&mut self, high_quality: bool) {
+        self.interval = if high_quality { 1 } else { CONTROL_INTERVAL };
+        self.pending = 0;
+    }
+
+    /// The value for the next sample. It moves at the end of each interval and on the sample the
+    /// target is reached, so both modes get there at the same time.
+    pub fn next_sample(&mut self) -> f64 {
+        let value = self.current;
+        self.pending += 1;
+        if self.pending == self.interval || self.pending == self.remaining {
+            self.advance(self.pending);
+            self.pending = 0;
+        }
+        value
+    }
+
+    /// Skip `len` samples and get the value at the end.
+    pub fn next_block(&mut self, len: usize) -> f64 {
+        self.advance(self.pending + len);
+        self.pending = 0;
+        self.current
+    }
+
+    fn advance(&mut self, samples: usize) {
+        if !self.is_smoothing() {
+            return;
+        }
+        match self.smoothing {
+            Smoothing::None => self.current = self.target,
+            Smoothing::Linear(_) | Smoothing::Multiplicative(_) => {
+                let samples = samples.min(self.remaining);
+                self.remaining -= samples;
+                if self.remaining == 0 {
+                    self.current = self.target;
+                } else if let Smoothing::Linear(_) = self.smoothing {
+                    self.current += self.step * samples as f64;
+                } else {
+                    self.current *= self.step.powi(samples as i32);
+                }
+            }
+            Smoothing::OnePole(_) => {
+                let distance = (self.current - self.target) * self.coef.powi(samples as i32);
+                self.current = if distance.abs() < EPSILON {
+                    self.target
+                } else {
+                    self.target + distance
+                };
+            }
+        }
+    }
+
+    fn samples_of(&self, ms: f64) -> usize {
+        (ms.max(0.0) * self.sample_rate / 1000.0).round() as usize
+    }
+
+    fn update_coef(&mut self) {
+        self.coef = match self.smoothing {
+            Smoothing::OnePole(ms) if ms > 0.0 => (-1000.0 / (ms * self.sample_rate)).exp(),
+            _ => 0.0,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reaches_targets() {
+        // 10 ms are 441 samples
+        let mut linear = Smoother::new(Smoothing::Linear(10.0), 0.0);
+        linear.set_high_quality(true);
+        linear.set_target(441.0);
+        assert_eq!(0.0, linear.next_sample());
+        assert_eq!(1.0, linear.next_sample());
+        assert_eq!(102.0, linear.next_block(100));
+        assert_eq!(441.0, linear.next_block(1000));
+        assert!(!linear.is_smoothing());
+
+        // in realtime it moves every 16 samples
+        let mut ratio = Smoother::new(Smoothing::Multiplicative(10.0), 100.0);
+        ratio.set_target(400.0);
+        assert!((0..16).all(|_| ratio.next_sample() == 100.0));
+        assert!((ratio.next_sample() - 100.0 * 4_f64.powf(16.0 / 441.0)).abs() < 1e-9);
+        let expected = 100.0 * 4_f64.powf(221.0 / 441.0);
+        assert!((ratio.next_block(204) - expected).abs() < 1e-9);
+
+        let mut pole = Smoother::new(Smoothing::OnePole(10.0), 1.0);
+        pole.set_target(0.0);
+        assert!((pole.next_block(441) - (-1.0_f64).exp()).abs() < 1e-9);
+        pole.set_sample_rate(48000.0);
+        assert_eq!(0.0, pole.current());
+
+        // a negative value can't be multiplied
+        ratio.set_target(-1.0);
+        assert_eq!(-1.0, ratio.current());
+    }
+
+    #[test]
+    fn modes_reach_target_together() {
+        // 441 samples aren't a whole number of intervals
+        for smoothing in &[Smoothing::Linear(10.0), Smoothing::Multiplicative(10.0)] {
+            let reached_at = |high_quality| {
+                let mut smoother = Smoother::new(*smoothing, 1.0);
+                smoother.set_high_quality(high_quality);
+                smoother.set_target(2.0);
+                (0..1000).position(|_| smoother.next_sample() == 2.0)
+            };
+            assert_eq!(Some(441), reached_at(true));
+            assert_eq!(Some(441), reached_at(false));
+        }
+    }
+}
@@ -1,29 +1,50 @@
 //! Plugin related stuff.
 
 pub mod message;
+pub mod state;
 
-use std::ffi::CString;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
 use std::io::{self, Read, Write};
 use std::os::raw::{c_char, c_int, c_void};
-use std::panic::RefUnwindSafe;
+use std::panic::{self, AssertUnwindSafe, RefUnwindSafe};
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::thread;
 
 use hresult::HRESULT;
 use log::{debug, error};
 
+use crate::host::context::{AudioContext, GuiContext, TickContext};
+use crate::host::routing::Routing;
 use crate::host::{self, Event, GetName, Host};
-use crate::voice::ReceiveVoiceHandler;
+use crate::midi::MidiAction;
+use crate::params::Params;
+use crate::voice::{ReceiveVoiceHandler, VoiceSlots};
 use crate::{
-    alloc_real_cstr, intptr_t, AsRawPtr, FlMessage, MidiMessage, ProcessParamFlags, ValuePtr,
-    CURRENT_SDK_VERSION,
+    alloc_real_cstr, cstr, intptr_t, AsRawPtr, DispatchResult, FlMessage, MidiMessage,
+    ProcessParamFlags, ValuePtr, CURRENT_SDK_VERSION,
 };
 
 crate::implement_tag!();
 
-/// Exposes your plugin from DLL. Accepts type name as input. The type should implement
-/// [`Plugin`](plugin/trait.Plugin.html) trait.
+/// Exposes your plugin from DLL. Accepts the kind of the plugin and type name as input:
+///
+/// - `create_plugin!(effect MyEffect)` for a type implementing
+///   [`Effect`](plugin/trait.Effect.html);
+/// - `create_plugin!(generator MySynth)` for a type implementing
+///   [`Generator`](plugin/trait.Generator.html).
 #[macro_export]
 macro_rules! create_plugin {
-    ($pl:ty) => {
+    (effect $pl:ty) => {
+        $crate::create_plugin!(@export effect, $pl);
+    };
+    (generator $pl:ty) => {
+        $crate::create_plugin!(@export generator, $pl);
+    };
+    (@export $kind:ident, $pl:ty) => {
         use std::os::raw::c_void;
 
         extern "C" {
@@ -40,20 +61,26 @@ macro_rules! create_plugin {
             host: *mut c_void,
             tag: $crate::intptr_t,
         ) -> *mut c_void {
-            let ho = $crate::host::Host::new(host);
-            let plugin = <$pl as $crate::plugin::Plugin>::new(
-                ho,
-                $crate::plugin::Tag(tag as $crate::intptr_t),
-            );
-            let adapter = $crate::plugin::PluginAdapter(Box::new(plugin));
-            create_plug_instance_c(host, tag, Box::into_raw(Box::new(adapter)) as *mut c_void)
+            match $crate::plugin::PluginAdapter::$kind::<$pl>(host, tag) {
+                Some(adapter) => create_plug_instance_c(
+                    host,
+                    tag,
+                    Box::into_raw(Box::new(adapter)) as *mut c_void,
+                ),
+                None => std::ptr::null_mut(),
+            }
         }
     };
 }
 
-/// This trait must be implemented for your plugin.
-pub trait Plugin: std::fmt::Debug + RefUnwindSafe + Send + Sync + 'static {
+/// This trait must be implemented for your plugin, together with either
+/// [`Effect`](trait.Effect.html) or [`Generator`](trait.Generator.html).
+pub trait Plugin: fmt::Debug + RefUnwindSafe + Send + Sync + 'static {
     /// Initializer.
+    ///
+    /// `host` doesn't check which thread it's called from. Prefer the contexts the callbacks get
+    /// (see [`host::context`](../host/context/index.html)), which only allow the calls meant for
+    /// their thread.
     fn new(host: Host, tag: Tag) -> Self
     where
         Self: Sized;
@@ -68,10 +95,24 @@ pub trait Plugin: std::fmt::Debug + RefUnwindSafe + Send + Sync + 'static {
     ///
     /// See [`host::Message`](../host/enum.Message.html) for possible messages.
     ///
+    /// The result is boxed, so this allocates on every call. Implement
+    /// [`handle_message`](#method.handle_message) instead, it's called by the host and by
+    /// default forwards to this method.
+    ///
+    /// Can be called from GUI or mixer threads.
+    fn on_message(&mut self, _message: host::Message<'_>) -> Box<dyn AsRawPtr> {
+        Box::new(DispatchResult::NONE)
+    }
+    /// Like [`on_message`](#method.on_message), but doesn't allocate for the result.
+    ///
     /// Can be called from GUI or mixer threads.
-    fn on_message(&mut self, message: host::Message<'_>) -> Box<dyn AsRawPtr>;
+    fn handle_message(&mut self, message: host::Message<'_>) -> DispatchResult {
+        self.on_message(message).into()
+    }
     /// This is called when the host wants to know a text representation of some value.
     ///
+    /// The host has room for 255 bytes, a longer name is truncated. NULs are removed.
+    ///
     /// Can be called from GUI or mixer threads.
     fn name_of(&self, value: GetName) -> String;
     /// Process an event sent by the host.
@@ -91,6 +132,10 @@ pub trait Plugin: std::fmt::Debug + RefUnwindSafe + Send + Sync + 'static {
     /// ../struct.ProcessParamFlags.html#associatedconstant.GET_VALUE) is specified in `flags`, the
     /// result has to be the value of the parameter.
     ///
+    /// The result is boxed, so this allocates on every call. Implement
+    /// [`handle_param`](#method.handle_param) instead, it's called by the host and by default
+    /// forwards to this method.
+    ///
     /// Can be called from GUI or mixer threads.
     fn process_param(
         &mut self,
@@ -98,52 +143,141 @@ pub trait Plugin: std::fmt::Debug + RefUnwindSafe + Send + Sync + 'static {
         _value: ValuePtr,
         _flags: ProcessParamFlags,
     ) -> Box<dyn AsRawPtr> {
-        Box::new(0)
+        Box::new(DispatchResult::NONE)
+    }
+    /// Like [`process_param`](#method.process_param), but doesn't allocate for the result.
+    ///
+    /// Can be called from GUI or mixer threads.
+    fn handle_param(
+        &mut self,
+        index: usize,
+        value: ValuePtr,
+        flags: ProcessParamFlags,
+    ) -> DispatchResult {
+        self.process_param(index, value, flags).into()
     }
     /// This function is called continuously. It allows the plugin to perform certain tasks that
     /// are not time-critical and which do not take up a lot of time either. For example, in this
     /// function you may show a hint message when the mouse moves over a control in the editor.
     ///
     /// Called from GUI thread.
-    fn idle(&mut self) {}
+    fn idle(&mut self, _ctx: &mut GuiContext<'_>) {}
+    /// The host asks to show the editor, in the `parent` window if there is one.
+    ///
+    /// By default it passes
+    /// [`host::Message::ShowEditor`](../host/enum.Message.html#variant.ShowEditor) to
+    /// [`handle_message`](#method.handle_message).
+    ///
+    /// Called from GUI thread.
+    fn show_editor(
+        &mut self,
+        _ctx: &mut GuiContext<'_>,
+        parent: Option<*mut c_void>,
+    ) -> DispatchResult {
+        self.handle_message(host::Message::ShowEditor(parent))
+    }
     /// Gets called before a new tick is mixed (not played), if the plugin added
     /// [`InfoBuilder::want_new_tick`](../plugin/struct.InfoBuilder.html#method.want_new_tick) into
     /// [`Info`](../struct.Info.html).
     ///
     /// Internal controller plugins should call
-    /// [`host::Host::on_controller`](../host/struct.Host.html#method.on_controller) from
-    /// here.
+    /// [`TickContext::on_controller`](../host/context/struct.TickContext.html#method.on_controller)
+    /// from here.
     ///
     /// Called from mixer thread.
-    fn tick(&mut self) {}
+    fn tick(&mut self, _ctx: &mut TickContext<'_>) {}
     /// This is called before a new midi tick is played (not mixed).
     ///
     /// Can be called from GUI or mixer threads.
     fn midi_tick(&mut self) {}
-    /// The processing function. The input buffer is empty for generator plugins.
-    ///
-    /// The buffers are in interlaced 32Bit float stereo format.
-    ///
-    /// Called from mixer thread.
-    fn render(&mut self, _input: &[[f32; 2]], _output: &mut [[f32; 2]]) {}
-    /// Get [`ReceiveVoiceHandler`](../voice/trait.ReceiveVoiceHandler.html).
-    ///
-    /// Implement this method if you make a generator plugin.
-    fn voice_handler(&mut self) -> Option<&mut dyn ReceiveVoiceHandler> {
-        None
-    }
     /// The host will call this when there's new MIDI data available. This function is only called
     /// when the plugin has called the
     /// [`host::Host::on_message`](../host/struct.Host.html#method.on_message) with
     /// [`plugin::message::WantMidiInput`](../plugin/message/struct.WantMidiInput.html) and
     /// value set to `true`.
     ///
+    /// Use [`midi::MidiEvent`](../midi/enum.MidiEvent.html) to decode the message.
+    ///
+    /// The result tells the host to pass the message on, replace it or kill it (see
+    /// [`MidiAction`](../midi/enum.MidiAction.html)).
+    ///
     /// Can be called from GUI or mixer threads.
-    fn midi_in(&mut self, _message: MidiMessage) {}
+    fn midi_in(&mut self, _message: MidiMessage) -> MidiAction {
+        MidiAction::Pass
+    }
     /// **MAY NOT WORK**
     ///
     /// This gets called with a new buffered message to the plugin itself.
     fn loop_in(&mut self, _message: ValuePtr) {}
+    /// What to do when a method of the plugin panics. See
+    /// [`PanicPolicy`](enum.PanicPolicy.html).
+    ///
+    /// The host asks it once, right after the plugin has been created.
+    fn panic_policy(&self) -> PanicPolicy {
+        PanicPolicy::Fault
+    }
+    /// Called when the host destroys the plugin, right before it's dropped.
+    ///
+    /// Called from GUI thread.
+    fn on_destroy(&mut self) {}
+}
+
+/// An effect receives audio data from FL Studio and does something to it.
+///
+/// Export it with `create_plugin!(effect MyEffect)`, which tells the host it's an effect.
+pub trait Effect: Plugin {
+    /// The processing function.
+    ///
+    /// The buffers are in interlaced 32Bit float stereo format.
+    ///
+    /// `ctx` gives the host's buffers for the block.
+    ///
+    /// Called from mixer thread.
+    fn render(&mut self, ctx: &mut AudioContext<'_>, input: &[[f32; 2]], output: &mut [[f32; 2]]);
+}
+
+/// A generator creates sounds from the notes it gets from FL Studio. The user sees it as a
+/// channel.
+///
+/// Export it with `create_plugin!(generator MySynth)`, which tells the host it's a generator.
+pub trait Generator: Plugin {
+    /// The voice set, which the host triggers, releases and kills voices in.
+    type Voices: ReceiveVoiceHandler + 'static;
+
+    /// How many voices the host can play at once. The handles for them are allocated when the
+    /// plugin is created. Voices the host triggers over the limit are ignored, so raise it if you
+    /// need more polyphony than the default 256.
+    const MAX_VOICES: usize = 256;
+
+    /// Create the voice set. It's called right before [`Plugin::new`](trait.Plugin.html#tymethod.new)
+    /// with the same host.
+    fn voices(host: &Host, tag: Tag) -> Self::Voices;
+
+    /// The processing function. It mixes `voices` into `output`.
+    ///
+    /// The buffer is in interlaced 32Bit float stereo format.
+    ///
+    /// `ctx` gives the host's buffers for the block.
+    ///
+    /// Called from mixer thread.
+    fn render(
+        &mut self,
+        ctx: &mut AudioContext<'_>,
+        output: &mut [[f32; 2]],
+        voices: &mut Self::Voices,
+    );
+}
+
+/// Panics never reach the host. When a method of [`Plugin`](trait.Plugin.html) panics, the output
+/// buffer is silenced, the host gets a safe default result, and the panic message with a
+/// backtrace is reported to the log and to the host (see
+/// [`DebugLogMsg`](message/struct.DebugLogMsg.html)). The policy decides what happens next.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PanicPolicy {
+    /// Mark the plugin as faulted. The host's later calls are ignored and the output stays silent.
+    Fault,
+    /// Only drop the call that panicked and keep calling the plugin.
+    Recover,
 }
 
 /// This structure holds some information about the plugin that is used by the host. It is the
@@ -187,10 +321,9 @@ pub struct InfoBuilder {
 }
 
 impl InfoBuilder {
-    /// Initializer for an effect.
-    ///
-    /// This is the most basic type.
-    pub fn new_effect(long_name: &str, short_name: &str, num_params: u32) -> Self {
+    /// Initializer for an effect or a generator. The kind is set by
+    /// [`create_plugin`](../macro.create_plugin.html).
+    pub fn new(long_name: &str, short_name: &str, num_params: u32) -> Self {
         Self {
             sdk_version: CURRENT_SDK_VERSION,
             long_name: long_name.to_string(),
@@ -204,21 +337,20 @@ impl InfoBuilder {
         .new_voice_params()
     }
 
-    /// Initializer for a full standalone generator.
-    ///
-    /// This is a combination of [`generator`](struct.InfoBuilder.html#method.generator) and
-    /// [`note_input`](struct.InfoBuilder.html#method.get_note_input).
-    pub fn new_full_gen(long_name: &str, short_name: &str, num_params: u32) -> Self {
-        InfoBuilder::new_effect(long_name, short_name, num_params)
-            .generator()
-            .get_note_input()
+    /// Initializer for an effect.
+    #[deprecated(
+        since = "2.0.0",
+        note = "use `InfoBuilder::new`, the kind is set on export"
+    )]
+    pub fn new_effect(long_name: &str, short_name: &str, num_params: u32) -> Self {
+        InfoBuilder::new(long_name, short_name, num_params)
     }
 
     /// Initializer for a purely visual plugin, that doesn't process any audio data.
     ///
     /// It's a basic plugin with [`no_process`](struct.InfoBuilder.html#method.no_process) enabled.
     pub fn new_visual(long_name: &str, short_name: &str, num_params: u32) -> Self {
-        InfoBuilder::new_effect(long_name, short_name, num_params).no_process()
+        InfoBuilder::new(long_name, short_name, num_params).no_process()
     }
 
     /// Set prefered (default) maximum polyphony.
@@ -227,6 +359,12 @@ impl InfoBuilder {
         self
     }
 
+    /// Set number of parameters from [`Params`](../params/struct.Params.html).
+    pub fn with_params(mut self, params: &Params) -> Self {
+        self.num_params = params.len() as u32;
+        self
+    }
+
     /// Set number of internal output controllers.
     pub fn with_out_ctrls(mut self, out_ctrls: u32) -> Self {
         self.num_out_ctrls = out_ctrls;
@@ -239,12 +377,6 @@ impl InfoBuilder {
         self
     }
 
-    /// The plugin is a generator (as opposed to an effect).
-    pub fn generator(mut self) -> Self {
-        self.flags |= 1;
-        self
-    }
-
     /// The plugin will use a sample that the user loads into the plugin's channel.
     pub fn get_chan_custom_shape(mut self) -> Self {
         self.flags |= 1 << 3;
@@ -355,16 +487,8 @@ impl InfoBuilder {
 
     /// Finish builder and init [`Info`](struct.Info.html)
     pub fn build(self) -> Info {
-        let log_err = |e| {
-            error!("{}", e);
-            panic!();
-        };
-        let long_name = CString::new(self.long_name)
-            .unwrap_or_else(log_err)
-            .into_raw();
-        let short_name = CString::new(self.short_name)
-            .unwrap_or_else(log_err)
-            .into_raw();
+        let long_name = cstr::to_c_string(&self.long_name).into_raw();
+        let short_name = cstr::to_c_string(&self.short_name).into_raw();
 
         Info {
             sdk_version: self.sdk_version,
@@ -428,18 +552,369 @@ extern "C" {
 
 fn check_hresult(result: HRESULT, read: usize, error_msg: &str) -> io::Result<usize> {
     if !result.is_success() {
-        return Err(io::Error::new(io::ErrorKind::Other, error_msg));
+        return Err(io::Error::other(error_msg));
     }
 
     Ok(read)
 }
 
+// The plugin with its kind, erased.
+pub(crate) trait Instance: fmt::Debug + Send + Sync {
+    fn plugin(&mut self) -> &mut dyn Plugin;
+
+    fn is_generator(&self) -> bool;
+
+    // returns false if the plugin can't render this way
+    fn eff_render(
+        &mut self,
+        ctx: &mut AudioContext<'_>,
+        input: &[[f32; 2]],
+        output: &mut [[f32; 2]],
+    ) -> bool;
+
+    // returns false if the plugin can't render this way
+    fn gen_render(&mut self, ctx: &mut AudioContext<'_>, output: &mut [[f32; 2]]) -> bool;
+
+    fn voice_handler(&mut self) -> Option<&mut dyn ReceiveVoiceHandler>;
+
+    fn on_message(&mut self, host: &mut Host, tag: Tag, message: FlMessage) -> intptr_t {
+        dispatch(self.plugin(), host, tag, message.into()).as_raw_ptr()
+    }
+
+    fn process_event(&mut self, event: FlMessage) {
+        self.plugin().process_event(event.into());
+    }
+}
+
+const FLAG_GENERATOR: u32 = 1;
+
+// ShowEditor goes to its own method, with the GUI context
+fn dispatch(
+    plugin: &mut dyn Plugin,
+    host: &mut Host,
+    tag: Tag,
+    message: host::Message<'_>,
+) -> DispatchResult {
+    match message {
+        host::Message::ShowEditor(parent) => {
+            plugin.show_editor(&mut GuiContext::new(host, tag), parent)
+        }
+        message => plugin.handle_message(message),
+    }
+}
+
+#[derive(Debug)]
+struct EffectInstance<E>(E);
+
+impl<E: Effect> Instance for EffectInstance<E> {
+    fn plugin(&mut self) -> &mut dyn Plugin {
+        &mut self.0
+    }
+
+    fn is_generator(&self) -> bool {
+        false
+    }
+
+    fn eff_render(
+        &mut self,
+        ctx: &mut AudioContext<'_>,
+        input: &[[f32; 2]],
+        output: &mut [[f32; 2]],
+    ) -> bool {
+        self.0.render(ctx, input, output);
+        true
+    }
+
+    fn gen_render(&mut self, _ctx: &mut AudioContext<'_>, _output: &mut [[f32; 2]]) -> bool {
+        false
+    }
+
+    fn voice_handler(&mut self) -> Option<&mut dyn ReceiveVoiceHandler> {
+        None
+    }
+}
+
+struct GeneratorInstance<G: Generator> {
+    plugin: G,
+    voices: G::Voices,
+}
+
+impl<G: Generator> fmt::Debug for GeneratorInstance<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeneratorInstance")
+            .field("plugin", &self.plugin)
+            .finish()
+    }
+}
+
+impl<G: Generator> Instance for GeneratorInstance<G> {
+    fn plugin(&mut self) -> &mut dyn Plugin {
+        &mut self.plugin
+    }
+
+    fn is_generator(&self) -> bool {
+        true
+    }
+
+    fn eff_render(
+        &mut self,
+        _ctx: &mut AudioContext<'_>,
+        _input: &[[f32; 2]],
+        _output: &mut [[f32; 2]],
+    ) -> bool {
+        false
+    }
+
+    fn gen_render(&mut self, ctx: &mut AudioContext<'_>, output: &mut [[f32; 2]]) -> bool {
+        self.plugin.render(ctx, output, &mut self.voices);
+        true
+    }
+
+    fn voice_handler(&mut self) -> Option<&mut dyn ReceiveVoiceHandler> {
+        Some(&mut self.voices)
+    }
+
+    // the voices get a chance to answer KillVoice if the plugin didn't
+    fn on_message(&mut self, host: &mut Host, tag: Tag, message: FlMessage) -> intptr_t {
+        let message: host::Message<'_> = message.into();
+        let kill_voice = matches!(message, host::Message::KillVoice);
+        let result = dispatch(&mut self.plugin, host, tag, message).as_raw_ptr();
+        if kill_voice && result == 0 {
+            return self.voices.kill_weakest() as intptr_t;
+        }
+        result
+    }
+
+    fn process_event(&mut self, event: FlMessage) {
+        let event: Event = event.into();
+        if let Event::MaxPoly(max_poly) = event {
+            self.voices.set_max_poly(max_poly);
+        }
+        self.plugin.process_event(event);
+    }
+}
+
 /// Type wraps `Plugin` trait object to simplify sharing with C/C++.
 ///
 /// This is for internal usage only and shouldn't be used directly.
 #[doc(hidden)]
 #[derive(Debug)]
-pub struct PluginAdapter(pub Box<dyn Plugin>);
+pub struct PluginAdapter {
+    instance: Box<dyn Instance>,
+    host: Host,
+    tag: Tag,
+    policy: PanicPolicy,
+    faulted: AtomicBool,
+    // refreshed before the plugin hears about the changes
+    routing: Routing,
+    // handles of the voices the host triggered and didn't kill yet
+    pub(crate) voices: VoiceSlots,
+}
+
+impl PluginAdapter {
+    /// Create an effect of type `E`.
+    ///
+    /// Returns `None` if the plugin panicked while being created.
+    pub fn effect<E: Effect>(host_ptr: *mut c_void, tag: intptr_t) -> Option<Self> {
+        Self::new(host_ptr, tag, 0, |host, tag| {
+            Box::new(EffectInstance(E::new(host, tag)))
+        })
+    }
+
+    /// Create a generator of type `G`.
+    ///
+    /// Returns `None` if the plugin panicked while being created.
+    pub fn generator<G: Generator>(host_ptr: *mut c_void, tag: intptr_t) -> Option<Self> {
+        Self::new(host_ptr, tag, G::MAX_VOICES, |host, tag| {
+            let voices = G::voices(&host, tag);
+            Box::new(GeneratorInstance {
+                plugin: G::new(host, tag),
+                voices,
+            })
+        })
+    }
+
+    fn new<F>(host_ptr: *mut c_void, tag: intptr_t, max_voices: usize, create: F) -> Option<Self>
+    where
+        F: FnOnce(Host, Tag) -> Box<dyn Instance>,
+    {
+        install_panic_hook();
+
+        let created = catch_guarded(|| {
+            let mut instance = create(Host::new(host_ptr), Tag(tag));
+            let policy = instance.plugin().panic_policy();
+            (instance, policy)
+        });
+        let mut host = Host::new(host_ptr);
+
+        match created {
+            Ok((instance, policy)) => Some(Self {
+                instance,
+                routing: Routing::new(&host, Tag(tag)),
+                host,
+                tag: Tag(tag),
+                policy,
+                faulted: AtomicBool::new(false),
+                voices: VoiceSlots::with_capacity(max_voices),
+            }),
+            Err(payload) => {
+                report_panic(&mut host, Tag(tag), payload);
+                None
+            }
+        }
+    }
+
+    // Tells the plugin it's being destroyed and drops it with everything the host didn't free.
+    fn destroy(mut self) {
+        self.guard(|plugin| plugin.on_destroy());
+        for tag in self.voices.drain() {
+            debug!("voice {} wasn't killed by the host", tag);
+        }
+
+        let Self {
+            instance,
+            mut host,
+            tag,
+            ..
+        } = self;
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| drop(instance))) {
+            report_panic(&mut host, tag, payload);
+        }
+    }
+
+    /// Whether the plugin has been stopped after a panic.
+    pub fn is_faulted(&self) -> bool {
+        self.faulted.load(Ordering::Acquire)
+    }
+
+    /// Call the plugin, unless it's faulted.
+    ///
+    /// Returns `None` if the plugin is faulted or it has panicked.
+    pub(crate) fn guard<R>(&mut self, f: impl FnOnce(&mut dyn Plugin) -> R) -> Option<R> {
+        self.guard_instance(|instance| f(instance.plugin()))
+    }
+
+    /// Like [`guard`](#method.guard), but gives the plugin with its kind.
+    pub(crate) fn guard_instance<R>(
+        &mut self,
+        f: impl FnOnce(&mut dyn Instance) -> R,
+    ) -> Option<R> {
+        self.guard_with_host(|instance, _, _, _| f(instance))
+    }
+
+    /// Like [`guard_instance`](#method.guard_instance), but also gives the host, the routing and
+    /// the tag to build a context with.
+    pub(crate) fn guard_with_host<R>(
+        &mut self,
+        f: impl FnOnce(&mut dyn Instance, &mut Host, &mut Routing, Tag) -> R,
+    ) -> Option<R> {
+        if self.is_faulted() {
+            return None;
+        }
+
+        let instance = &mut *self.instance;
+        let host = &mut self.host;
+        let routing = &mut self.routing;
+        let tag = self.tag;
+        match catch_guarded(|| f(instance, host, routing, tag)) {
+            Ok(result) => Some(result),
+            Err(payload) => {
+                if self.policy == PanicPolicy::Fault {
+                    self.faulted.store(true, Ordering::Release);
+                }
+                report_panic(&mut self.host, self.tag, payload);
+                None
+            }
+        }
+    }
+}
+
+thread_local! {
+    // message and backtrace of the last panic on this thread, filled by the panic hook
+    static LAST_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+    // number of guarded calls running on this thread, they can nest
+    static GUARD_DEPTH: Cell<usize> = const { Cell::new(0) };
+    // length of the block being rendered on this thread
+    static RENDER_BLOCK: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Length of the block being rendered, or `None` outside of render.
+pub(crate) fn render_block_len() -> Option<usize> {
+    RENDER_BLOCK.with(Cell::get)
+}
+
+// Sets the block length for the duration of a render call.
+struct RenderBlock;
+
+impl RenderBlock {
+    fn enter(len: usize) -> Self {
+        RENDER_BLOCK.with(|block| block.set(Some(len)));
+        RenderBlock
+    }
+}
+
+impl Drop for RenderBlock {
+    fn drop(&mut self) {
+        RENDER_BLOCK.with(|block| block.set(None));
+    }
+}
+
+// Marks the thread as running a guarded call for the duration of `f` and catches its panic.
+fn catch_guarded<R>(f: impl FnOnce() -> R) -> thread::Result<R> {
+    let _guarded = Guarded::enter();
+    panic::catch_unwind(AssertUnwindSafe(f))
+}
+
+struct Guarded;
+
+impl Guarded {
+    fn enter() -> Self {
+        GUARD_DEPTH.with(|depth| depth.set(depth.get() + 1));
+        Guarded
+    }
+}
+
+impl Drop for Guarded {
+    fn drop(&mut self) {
+        GUARD_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+// A payload caught by catch_unwind doesn't hold the location and the backtrace, so we take them
+// in the hook, only for panics in guarded calls. The previous hook still runs.
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if GUARD_DEPTH.try_with(Cell::get).unwrap_or(0) > 0 {
+                let report = format!("{}\n{}", info, Backtrace::force_capture());
+                let _ = LAST_PANIC.try_with(|last| *last.borrow_mut() = Some(report));
+            }
+            previous(info);
+        }));
+    });
+}
+
+fn report_panic(host: &mut Host, tag: Tag, payload: Box<dyn Any + Send>) {
+    let report = LAST_PANIC
+        .try_with(|last| last.borrow_mut().take())
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| {
+            payload
+                .downcast_ref::<&str>()
+                .map(|msg| msg.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string())
+        });
+    error!("plugin panicked: {}", report);
+    host.on_message(
+        tag,
+        message::DebugLogMsg(format!("plugin panicked: {}", report.replace('\0', ""))),
+    );
+}
 
 /// [`Plugin::info`](trait.Plugin.html#tymethod.info) FFI.
 ///
@@ -451,10 +926,45 @@ pub struct PluginAdapter(pub Box<dyn Plugin>);
 #[doc(hidden)]
 #[no_mangle]
 unsafe extern "C" fn plugin_info(adapter: *mut PluginAdapter) -> *mut Info {
-    Box::into_raw(Box::new((*adapter).0.info()))
+    let generator = (*adapter).instance.is_generator();
+    let mut info = (*adapter)
+        .guard(|plugin| plugin.info())
+        .unwrap_or_else(|| InfoBuilder::new("", "", 0).build());
+    // the kind comes from the trait the plugin is exported with, the builder can't set it
+    if generator {
+        info.flags |= FLAG_GENERATOR;
+    }
+    Box::into_raw(Box::new(info))
+}
+
+/// Free [`Info`](struct.Info.html) returned by `plugin_info`. The names are owned by the C++ side.
+///
+/// It supposed to be used internally. Don't use it.
+///
+/// # Safety
+///
+/// Unsafe
+#[doc(hidden)]
+#[no_mangle]
+unsafe extern "C" fn free_info(info: *mut Info) {
+    drop(Box::from_raw(info));
+}
+
+/// Destroy the plugin. Calls [`Plugin::on_destroy`](trait.Plugin.html#method.on_destroy) and
+/// drops it.
+///
+/// It supposed to be used internally. Don't use it.
+///
+/// # Safety
+///
+/// Unsafe
+#[doc(hidden)]
+#[no_mangle]
+unsafe extern "C" fn plugin_destroy(adapter: *mut PluginAdapter) {
+    Box::from_raw(adapter).destroy();
 }
 
-/// [`Plugin::on_message`](trait.Plugin.html#tymethod.on_message) FFI.
+/// [`Plugin::handle_message`](trait.Plugin.html#method.handle_message) FFI.
 ///
 /// It supposed to be used internally. Don't use it.
 ///
@@ -467,7 +977,12 @@ unsafe extern "C" fn plugin_dispatcher(
     adapter: *mut PluginAdapter,
     message: FlMessage,
 ) -> intptr_t {
-    (*adapter).0.on_message(message.into()).as_raw_ptr()
+    (*adapter)
+        .guard_with_host(|instance, host, routing, tag| {
+            routing.on_message(&message);
+            instance.on_message(host, tag, message)
+        })
+        .unwrap_or(0)
 }
 
 /// [`Plugin::name_of`](trait.Plugin.html#tymethod.name_of) FFI.
@@ -480,14 +995,18 @@ unsafe extern "C" fn plugin_dispatcher(
 #[doc(hidden)]
 #[no_mangle]
 unsafe extern "C" fn plugin_name_of(
-    adapter: *const PluginAdapter,
+    adapter: *mut PluginAdapter,
     message: FlMessage,
-) -> *mut c_char {
-    let name = CString::new((*adapter).0.name_of(message.into())).unwrap_or_else(|e| {
-        error!("{}", e);
-        panic!();
-    });
-    name.into_raw()
+    name: *mut c_char,
+) {
+    let value = (*adapter)
+        .guard(|plugin| plugin.name_of(message.into()))
+        .unwrap_or_default();
+    // the host's buffer has room for BUF_LEN bytes
+    cstr::write_to(
+        &value,
+        slice::from_raw_parts_mut(name as *mut u8, cstr::BUF_LEN),
+    );
 }
 
 /// [`Plugin::process_event`](trait.Plugin.html#tymethod.process_event) FFI.
@@ -500,11 +1019,11 @@ unsafe extern "C" fn plugin_name_of(
 #[doc(hidden)]
 #[no_mangle]
 unsafe extern "C" fn plugin_process_event(adapter: *mut PluginAdapter, event: FlMessage) -> c_int {
-    (*adapter).0.process_event(event.into());
+    (*adapter).guard_instance(|instance| instance.process_event(event));
     0
 }
 
-/// [`Plugin::process_param`](trait.Plugin.html#tymethod.process_param) FFI.
+/// [`Plugin::handle_param`](trait.Plugin.html#method.handle_param) FFI.
 ///
 /// It supposed to be used internally. Don't use it.
 ///
@@ -518,13 +1037,16 @@ unsafe extern "C" fn plugin_process_param(
     message: FlMessage,
 ) -> intptr_t {
     (*adapter)
-        .0
-        .process_param(
-            message.id as usize,
-            ValuePtr(message.index),
-            ProcessParamFlags::from_bits_truncate(message.value),
-        )
-        .as_raw_ptr()
+        .guard(|plugin| {
+            plugin
+                .handle_param(
+                    message.id as usize,
+                    ValuePtr(message.index),
+                    ProcessParamFlags::from_bits_truncate(message.value),
+                )
+                .as_raw_ptr()
+        })
+        .unwrap_or(0)
 }
 
 /// [`Plugin::idle`](trait.Plugin.html#method.idle) FFI.
@@ -537,7 +1059,9 @@ unsafe extern "C" fn plugin_process_param(
 #[doc(hidden)]
 #[no_mangle]
 unsafe extern "C" fn plugin_idle(adapter: *mut PluginAdapter) {
-    (*adapter).0.idle();
+    (*adapter).guard_with_host(|instance, host, _, tag| {
+        instance.plugin().idle(&mut GuiContext::new(host, tag))
+    });
 }
 
 /// [`Plugin::tick`](trait.Plugin.html#tymethod.tick) FFI.
@@ -550,7 +1074,9 @@ unsafe extern "C" fn plugin_idle(adapter: *mut PluginAdapter) {
 #[doc(hidden)]
 #[no_mangle]
 unsafe extern "C" fn plugin_tick(adapter: *mut PluginAdapter) {
-    (*adapter).0.tick();
+    (*adapter).guard_with_host(|instance, host, _routing, tag| {
+        instance.plugin().tick(&mut TickContext::new(host, tag))
+    });
 }
 
 /// [`Plugin::midi_tick`](trait.Plugin.html#tymethod.midi_tick) FFI.
@@ -563,10 +1089,10 @@ unsafe extern "C" fn plugin_tick(adapter: *mut PluginAdapter) {
 #[doc(hidden)]
 #[no_mangle]
 unsafe extern "C" fn plugin_midi_tick(adapter: *mut PluginAdapter) {
-    (*adapter).0.midi_tick();
+    (*adapter).guard(|plugin| plugin.midi_tick());
 }
 
-/// [`Plugin::render`](trait.Plugin.html#tymethod.render) FFI for effects.
+/// [`Effect::render`](trait.Effect.html#tymethod.render) FFI.
 ///
 /// It supposed to be used internally. Don't use it.
 ///
@@ -581,12 +1107,19 @@ unsafe extern "C" fn plugin_eff_render(
     dest: *mut [f32; 2],
     length: i32,
 ) {
-    let input = std::slice::from_raw_parts(source, length as usize);
-    let mut output = std::slice::from_raw_parts_mut(dest, length as usize);
-    (*adapter).0.render(input, &mut output);
+    let input = slice::from_raw_parts(source, length as usize);
+    let output = slice::from_raw_parts_mut(dest, length as usize);
+    let _block = RenderBlock::enter(output.len());
+    let rendered = (*adapter).guard_with_host(|instance, host, routing, tag| {
+        let mut ctx = AudioContext::new(host, routing, tag, output.len());
+        instance.eff_render(&mut ctx, input, output)
+    });
+    if rendered != Some(true) {
+        silence(output);
+    }
 }
 
-/// [`Plugin::render`](trait.Plugin.html#tymethod.render) FFI for generators.
+/// [`Generator::render`](trait.Generator.html#tymethod.render) FFI.
 ///
 /// It supposed to be used internally. Don't use it.
 ///
@@ -600,8 +1133,15 @@ unsafe extern "C" fn plugin_gen_render(
     dest: *mut [f32; 2],
     length: i32,
 ) {
-    let mut output = std::slice::from_raw_parts_mut(dest, length as usize);
-    (*adapter).0.render(&[[0.0, 0.0]], &mut output);
+    let output = slice::from_raw_parts_mut(dest, length as usize);
+    let _block = RenderBlock::enter(output.len());
+    let rendered = (*adapter).guard_with_host(|instance, host, routing, tag| {
+        let mut ctx = AudioContext::new(host, routing, tag, output.len());
+        instance.gen_render(&mut ctx, output)
+    });
+    if rendered != Some(true) {
+        silence(output);
+    }
 }
 
 /// [`Plugin::midi_in`](trait.Plugin.html#tymethod.midi_in) FFI.
@@ -614,7 +1154,10 @@ unsafe extern "C" fn plugin_gen_render(
 #[doc(hidden)]
 #[no_mangle]
 unsafe extern "C" fn plugin_midi_in(adapter: *mut PluginAdapter, message: &mut c_int) {
-    (*adapter).0.midi_in(message.into());
+    // the message is passed unchanged if the plugin panics
+    if let Some(action) = (*adapter).guard(|plugin| plugin.midi_in((*message).into())) {
+        action.apply(message);
+    }
 }
 
 /// [`Plugin::save_state`](trait.Plugin.html#tymethod.save_state) FFI.
@@ -627,7 +1170,7 @@ unsafe extern "C" fn plugin_midi_in(adapter: *mut PluginAdapter, message: &mut c
 #[doc(hidden)]
 #[no_mangle]
 unsafe extern "C" fn plugin_save_state(adapter: *mut PluginAdapter, stream: *mut c_void) {
-    (*adapter).0.save_state(StateWriter(stream));
+    (*adapter).guard(|plugin| plugin.save_state(StateWriter(stream)));
 }
 
 /// [`Plugin::load_state`](trait.Plugin.html#tymethod.load_state) FFI.
@@ -640,7 +1183,7 @@ unsafe extern "C" fn plugin_save_state(adapter: *mut PluginAdapter, stream: *mut
 #[doc(hidden)]
 #[no_mangle]
 unsafe extern "C" fn plugin_load_state(adapter: *mut PluginAdapter, stream: *mut c_void) {
-    (*adapter).0.load_state(StateReader(stream));
+    (*adapter).guard(|plugin| plugin.load_state(StateReader(stream)));
 }
 
 /// [`Plugin::loop_in`](Plugin.html#method.loop_in) FFI.
@@ -653,5 +1196,11 @@ unsafe extern "C" fn plugin_load_state(adapter: *mut PluginAdapter, stream: *mut
 #[doc(hidden)]
 #[no_mangle]
 unsafe extern "C" fn plugin_loop_in(adapter: *mut PluginAdapter, message: intptr_t) {
-    (*adapter).0.loop_in(ValuePtr(message));
+    (*adapter).guard(|plugin| plugin.loop_in(ValuePtr(message)));
+}
+
+fn silence(output: &mut [[f32; 2]]) {
+    for sample in output {
+        *sample = [0.0, 0.0];
+    }
 }
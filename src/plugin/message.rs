@@ -3,9 +3,10 @@ use std::mem;
 use std::os::raw::{c_int, c_void};
 
 use crate::host::{GetName, Host};
+use crate::ownership::{HostOwned, RustOwned};
 use crate::plugin;
 use crate::{
-    intptr_t, AsRawPtr, FlMessage, MessageBoxFlags, MessageBoxResult, NameColor, Note, Notes,
+    cstr, intptr_t, AsRawPtr, FlMessage, MessageBoxFlags, MessageBoxResult, NameColor, Note, Notes,
     ParamMenuEntry, SongTime, TNameColor, TParamMenuEntry, Tag, Time, TimeFormat, ValuePtr,
 };
 
@@ -19,6 +20,21 @@ pub trait Message {
     fn send(self, tag: plugin::Tag, host: &mut Host) -> Self::Return;
 }
 
+/// Message which can be sent from the GUI thread, with
+/// [`GuiContext::on_message`](../../host/context/struct.GuiContext.html#method.on_message).
+pub trait GuiMessage: Message {}
+
+/// Message which can be sent from the mixer thread, with
+/// [`AudioContext::on_message`](../../host/context/struct.AudioContext.html#method.on_message)
+/// or [`TickContext::on_message`](../../host/context/struct.TickContext.html#method.on_message).
+pub trait MixerMessage: Message {}
+
+macro_rules! sent_from {
+    ($thread: ident: $($message: ty),* $(,)?) => {
+        $(impl $thread for $message {})*
+    };
+}
+
 macro_rules! impl_message {
     ($message: ident) => {
         impl Message for $message {
@@ -289,68 +305,57 @@ impl Message for TicksToTime {
     type Return = SongTime;
 
     fn send(self, tag: plugin::Tag, host: &mut Host) -> Self::Return {
-        let message = FlMessage::from(self);
-        let time_ptr = message.index;
-        unsafe { host_on_message(*host.host_ptr.get_mut(), tag.0, message) };
-        ValuePtr(time_ptr).get::<Self::Return>()
-    }
-}
-
-impl From<TicksToTime> for FlMessage {
-    fn from(message: TicksToTime) -> Self {
-        let time = SongTime::default();
-        FlMessage {
+        // the host fills it
+        let mut time = RustOwned::new(SongTime::default());
+        let message = FlMessage {
             id: 16,
-            index: (Box::into_raw(Box::new(time)) as *mut c_void).as_raw_ptr(),
-            value: message.0.as_raw_ptr(),
-        }
+            index: time.as_mut_ptr(),
+            value: self.0.as_raw_ptr(),
+        };
+        unsafe { host_on_message(*host.host_ptr.get_mut(), tag.0, message) };
+        time.into_inner()
     }
 }
 
-/// Ask the host to add one or more notes to the piano roll.
+/// Ask the host to add one or more notes to the piano roll or the step sequencer (see
+/// [`Notes::target`](../../struct.Notes.html#structfield.target)).
 #[derive(Debug)]
 pub struct AddToPianoRoll(pub Notes);
 
-impl_message!(AddToPianoRoll);
-
-impl From<AddToPianoRoll> for FlMessage {
-    fn from(mut message: AddToPianoRoll) -> Self {
-        message.0.notes.shrink_to_fit();
-        let notes_ptr = message.0.notes.as_mut_ptr();
-        let len = message.0.notes.len();
-
-        let p_notes_params = unsafe {
-            init_p_notes_params(
-                1,
-                message.0.flags.bits() as c_int,
-                message.0.channel.map(|v| v as c_int).unwrap_or(-1),
-                message.0.pattern.map(|v| v as c_int).unwrap_or(-1),
-                notes_ptr,
-                len as c_int,
-            )
-        };
+impl Message for AddToPianoRoll {
+    type Return = ();
 
-        FlMessage {
+    fn send(self, tag: plugin::Tag, host: &mut Host) -> Self::Return {
+        // TNotesParams is plugin memory, the host copies the notes before returning
+        let mut params = notes_params(&self.0);
+        let message = FlMessage {
             id: 17,
             index: 0,
-            value: p_notes_params,
-        }
+            value: params.as_mut_ptr() as intptr_t,
+        };
+        unsafe { host_on_message(*host.host_ptr.get_mut(), tag.0, message) };
     }
 }
 
-extern "C" {
-    // target:
-    // 0=step seq (not supported yet), 1=piano roll
-    //
-    // so we always use 1 for this
-    fn init_p_notes_params(
-        target: c_int,
-        flags: c_int,
-        ch_num: c_int,
-        pat_num: c_int,
-        notes: *mut Note,
-        count: c_int,
-    ) -> intptr_t;
+// Lay out TNotesParams: five ints followed by the packed notes.
+fn notes_params(notes: &Notes) -> Vec<c_int> {
+    const HEADER: usize = 5;
+    let note_ints = size_of::<Note>() / size_of::<c_int>();
+    let len = HEADER + note_ints * notes.notes.len();
+    let mut params = Vec::with_capacity(len);
+    params.extend_from_slice(&[
+        notes.target as c_int,
+        notes.flags.bits() as c_int,
+        notes.pattern.map(|v| v as c_int).unwrap_or(-1),
+        notes.channel.map(|v| v as c_int).unwrap_or(-1),
+        notes.notes.len() as c_int,
+    ]);
+    unsafe {
+        let first = params.as_mut_ptr().add(HEADER) as *mut Note;
+        first.copy_from_nonoverlapping(notes.notes.as_ptr(), notes.notes.len());
+        params.set_len(len);
+    }
+    params
 }
 
 /// Before the popup menu is shown, you must fill it with the entries set by the host. You use this
@@ -370,14 +375,7 @@ impl Message for GetParamMenuEntry {
     fn send(self, tag: plugin::Tag, host: &mut Host) -> Self::Return {
         let message = FlMessage::from(self);
         let result = unsafe { host_on_message(*host.host_ptr.get_mut(), tag.0, message) };
-
-        if (result as *mut c_void).is_null() {
-            return None;
-        }
-
-        Some(ParamMenuEntry::from_ffi(
-            result as *mut c_void as *mut TParamMenuEntry,
-        ))
+        unsafe { HostOwned::<TParamMenuEntry>::from_raw(result) }.map(ParamMenuEntry::from_ffi)
     }
 }
 
@@ -646,7 +644,7 @@ impl From<EditSample> for FlMessage {
 
 /// (FL 7.0) Call this to let FL know that this plugin is thread-safe (or not). The default is not.
 /// You should do your own thread-sync using
-/// [`Host::lock_mix`](../../host/struct.Host.html#method.lock_mix).
+/// [`GuiContext::lock_mix`](../../host/context/struct.GuiContext.html#method.lock_mix).
 ///
 /// The value is `false` for `not safe` and `true` for `safe`.
 ///
@@ -718,30 +716,26 @@ impl Message for GetMixingTime {
     type Return = Time;
 
     fn send(self, tag: plugin::Tag, host: &mut Host) -> Self::Return {
-        get_time_send(self, tag, host)
-    }
-}
-
-fn get_time_send<T: Into<FlMessage>>(msg: T, tag: plugin::Tag, host: &mut Host) -> Time {
-    let message: FlMessage = msg.into();
-    let time_ptr = message.value;
-    unsafe { host_on_message(*host.host_ptr.get_mut(), tag.0, message) };
-    ValuePtr(time_ptr).get::<Time>()
-}
-
-impl From<GetMixingTime> for FlMessage {
-    fn from(message: GetMixingTime) -> Self {
-        get_time_ffi(36, message.0, message.1)
+        get_time_send(36, self.0, self.1, tag, host)
     }
 }
 
-fn get_time_ffi(id: intptr_t, format: TimeFormat, offset: u64) -> FlMessage {
-    let time = Time(offset as f64, offset as f64);
-    FlMessage {
+fn get_time_send(
+    id: intptr_t,
+    format: TimeFormat,
+    offset: u64,
+    tag: plugin::Tag,
+    host: &mut Host,
+) -> Time {
+    // the host fills it
+    let mut time = RustOwned::new(Time(offset as f64, offset as f64));
+    let message = FlMessage {
         id,
         index: u8::from(format).as_raw_ptr(),
-        value: (Box::into_raw(Box::new(time)) as *mut c_void).as_raw_ptr(),
-    }
+        value: time.as_mut_ptr(),
+    };
+    unsafe { host_on_message(*host.host_ptr.get_mut(), tag.0, message) };
+    time.into_inner()
 }
 
 /// (FL 8.0) Get playback time. See `GetMixingTime` for details.
@@ -752,13 +746,7 @@ impl Message for GetPlaybackTime {
     type Return = Time;
 
     fn send(self, tag: plugin::Tag, host: &mut Host) -> Self::Return {
-        get_time_send(self, tag, host)
-    }
-}
-
-impl From<GetPlaybackTime> for FlMessage {
-    fn from(message: GetPlaybackTime) -> Self {
-        get_time_ffi(37, message.0, message.1)
+        get_time_send(37, self.0, self.1, tag, host)
     }
 }
 
@@ -775,13 +763,7 @@ impl Message for GetSelTime {
     type Return = Time;
 
     fn send(self, tag: plugin::Tag, host: &mut Host) -> Self::Return {
-        get_time_send(self, tag, host)
-    }
-}
-
-impl From<GetSelTime> for FlMessage {
-    fn from(message: GetSelTime) -> Self {
-        get_time_ffi(38, message.0, 0)
+        get_time_send(38, self.0, 0, tag, host)
     }
 }
 
@@ -843,7 +825,7 @@ impl Message for SendSysEx<'_> {
 impl From<SendSysEx<'_>> for FlMessage {
     fn from(message: SendSysEx<'_>) -> Self {
         let len = message.1.len() as i32;
-        let len_bytes: [u8; mem::size_of::<i32>()] = unsafe { mem::transmute(len) };
+        let len_bytes = len.to_ne_bytes();
         let mut final_data = [&len_bytes, message.1].concat();
         let data_ptr = final_data.as_mut_ptr();
         mem::forget(final_data);
@@ -1062,44 +1044,60 @@ impl Message for GetInName {
     type Return = Option<NameColor>;
 
     fn send(self, tag: plugin::Tag, host: &mut Host) -> Self::Return {
-        get_name_dispatcher(self, tag, host)
+        get_name_dispatcher(51, self.0, tag, host)
     }
 }
 
-fn get_name_dispatcher<T: Into<FlMessage>>(
-    msg: T,
+impl GetInName {
+    // Like send, but the host fills a reused name. Returns false if there's no name.
+    pub(crate) fn fill(
+        self,
+        tag: plugin::Tag,
+        host: &mut Host,
+        name_color: &mut RustOwned<TNameColor>,
+    ) -> bool {
+        fill_name_color(51, self.0, tag, host, name_color)
+    }
+}
+
+fn get_name_dispatcher(
+    id: intptr_t,
+    index: usize,
     tag: plugin::Tag,
     host: &mut Host,
 ) -> Option<NameColor> {
-    let message: FlMessage = msg.into();
-    let result_ptr = message.value;
-    let result = unsafe { host_on_message(*host.host_ptr.get_mut(), tag.0, message) };
-
-    if result == 0 || (result_ptr as *mut c_void).is_null() {
+    // the host fills it
+    let mut name_color = RustOwned::new(TNameColor {
+        name: [0; cstr::BUF_LEN],
+        vis_name: [0; cstr::BUF_LEN],
+        color: 0,
+        index: 0,
+    });
+    if !fill_name_color(id, index, tag, host, &mut name_color) {
         return None;
     }
 
-    Some(ValuePtr(result_ptr).get::<TNameColor>().into())
-}
-
-impl From<GetInName> for FlMessage {
-    fn from(message: GetInName) -> Self {
-        get_name_ffi(51, message.0)
-    }
+    Some(name_color.into_inner().into())
 }
 
-fn get_name_ffi(id: intptr_t, index: usize) -> FlMessage {
-    let name_color = TNameColor {
-        name: [0; 256],
-        vis_name: [0; 256],
-        color: 0,
-        index: index as c_int,
-    };
-    FlMessage {
+fn fill_name_color(
+    id: intptr_t,
+    index: usize,
+    tag: plugin::Tag,
+    host: &mut Host,
+    name_color: &mut RustOwned<TNameColor>,
+) -> bool {
+    let target = name_color.get_mut();
+    target.name[0] = 0;
+    target.vis_name[0] = 0;
+    target.color = 0;
+    target.index = index as c_int;
+    let message = FlMessage {
         id,
         index: index.as_raw_ptr(),
-        value: (Box::into_raw(Box::new(name_color)) as *mut c_void).as_raw_ptr(),
-    }
+        value: name_color.as_mut_ptr(),
+    };
+    unsafe { host_on_message(*host.host_ptr.get_mut(), tag.0, message) != 0 }
 }
 
 /// Ask the host the name of the output.
@@ -1116,13 +1114,19 @@ impl Message for GetOutName {
     type Return = Option<NameColor>;
 
     fn send(self, tag: plugin::Tag, host: &mut Host) -> Self::Return {
-        get_name_dispatcher(self, tag, host)
+        get_name_dispatcher(52, self.0, tag, host)
     }
 }
 
-impl From<GetOutName> for FlMessage {
-    fn from(message: GetOutName) -> Self {
-        get_name_ffi(52, message.0)
+impl GetOutName {
+    // Like send, but the host fills a reused name. Returns false if there's no name.
+    pub(crate) fn fill(
+        self,
+        tag: plugin::Tag,
+        host: &mut Host,
+        name_color: &mut RustOwned<TNameColor>,
+    ) -> bool {
+        fill_name_color(52, self.0, tag, host, name_color)
     }
 }
 
@@ -1326,7 +1330,7 @@ impl From<RenderProject> for FlMessage {
 
 /// Get project title, author, comments or URL.
 ///
-/// The result is `String`.
+/// The result is `String`, decoded from the host's UTF-16.
 #[derive(Debug)]
 pub enum GetProjectInfo {
     /// Title.
@@ -1339,7 +1343,15 @@ pub enum GetProjectInfo {
     Url,
 }
 
-impl_message_ty!(GetProjectInfo, String);
+impl Message for GetProjectInfo {
+    type Return = String;
+
+    fn send(self, tag: plugin::Tag, host: &mut Host) -> Self::Return {
+        // the host returns a wide string and keeps it
+        let result = unsafe { host_on_message(*host.host_ptr.get_mut(), tag.0, self.into()) };
+        unsafe { cstr::read_wide(result) }
+    }
+}
 
 impl From<GetProjectInfo> for FlMessage {
     fn from(message: GetProjectInfo) -> Self {
@@ -1377,3 +1389,79 @@ mod tests {
         assert_eq!(15, (value >> 16) & 0xff);
     }
 }
+
+// the mixer time only makes sense while mixing
+sent_from!(
+    GuiMessage: ParamMenu,
+    EditorResized,
+    NamesChanged,
+    ActivateMidi,
+    WantMidiInput,
+    KillAutomation,
+    SetNumPresets,
+    SetNewName,
+    VstiIdle,
+    WantIdle,
+    LocateDataFile,
+    TicksToTime,
+    AddToPianoRoll,
+    GetParamMenuEntry,
+    MessageBox,
+    NoteOn,
+    NoteOff,
+    OnHintDirect,
+    SetNewColor,
+    KillIntCtrl,
+    SetNumParams,
+    PackDataFile,
+    GetProgPath,
+    SetLatency,
+    CallDownloader,
+    EditSample,
+    SetThreadSafe,
+    SmartDisable,
+    SetUid,
+    GetPlaybackTime,
+    GetSelTime,
+    GetTimeMul,
+    Captionize,
+    SendSysEx<'_>,
+    LoadAudioClip,
+    LoadInChannel,
+    ShowInBrowser,
+    DebugLogMsg,
+    GetMainFormHandle,
+    GetProjDataPath,
+    SetDirty,
+    AddToRecent,
+    GetNumInOut,
+    GetInName,
+    GetOutName,
+    ShowEditor,
+    FloatAutomation,
+    ShowSettings,
+    NoteOnOff,
+    ShowPicker,
+    GetIdleOverflow,
+    ModalIdle,
+    RenderProject,
+    GetProjectInfo,
+);
+
+// no dialogs, files or changes to the plugin's setup
+sent_from!(
+    MixerMessage: TicksToTime,
+    NoteOn,
+    NoteOff,
+    NoteOnOff,
+    SetLatency,
+    GetMixingTime,
+    GetPlaybackTime,
+    GetSelTime,
+    GetTimeMul,
+    SendSysEx<'_>,
+    DebugLogMsg,
+    GetNumInOut,
+    GetInName,
+    GetOutName,
+);
@@ -0,0 +1,420 @@
+//! Versioned container for the plugin's state.
+//!
+//! [`Plugin::save_state`](../trait.Plugin.html#tymethod.save_state) and
+//! [`Plugin::load_state`](../trait.Plugin.html#tymethod.load_state) only give you a stream. A
+//! [`Container`](struct.Container.html) puts a structure on it, so the state can change between
+//! versions of the plugin and projects saved with the older versions still load.
+//!
+//! The layout is (all numbers are little-endian):
+//!
+//! - magic `FPST`;
+//! - `u32` plugin UID;
+//! - `u32` schema version;
+//! - `u32` number of chunks;
+//! - chunks, each of them is a 4-byte tag, `u32` length and the data;
+//! - `u32` CRC-32 of everything above.
+//!
+//! ```ignore
+//! const UID: u32 = u32::from_le_bytes(*b"Gain");
+//! const VERSION: u32 = 2;
+//!
+//! fn save_state(&mut self, writer: StateWriter) {
+//!     let mut state = Container::new(UID, VERSION);
+//!     state.insert(*b"gain", self.gain.to_le_bytes().to_vec());
+//!     state.write_to(writer).unwrap_or_else(|e| error!("can't save state: {}", e));
+//! }
+//!
+//! fn load_state(&mut self, reader: StateReader) {
+//!     let state = Container::read_from(reader, UID).and_then(|mut state| {
+//!         // version 1 kept the gain in dB
+//!         state.migrate(VERSION, |state, from| {
+//!             if from == 1 {
+//!                 let db = f32::from_le_bytes(state.get_array(*b"gain")?);
+//!                 state.insert(*b"gain", 10_f32.powf(db / 20.0).to_le_bytes().to_vec());
+//!             }
+//!             Ok(())
+//!         })?;
+//!         Ok(state)
+//!     });
+//!     // ...
+//! }
+//! ```
+use std::error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// The first bytes of the container.
+pub const MAGIC: [u8; 4] = *b"FPST";
+
+/// Chunk tag.
+pub type ChunkTag = [u8; 4];
+
+/// State container error.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the stream failed.
+    Io(io::Error),
+    /// The data isn't a state container.
+    BadMagic,
+    /// The state has been saved by another plugin. Contains the UID found in the state.
+    UidMismatch(u32),
+    /// The state is corrupted.
+    Checksum,
+    /// The state has been saved by a newer version of the plugin. Contains the version found in
+    /// the state.
+    TooNew(u32),
+    /// A chunk is missing or malformed. Contains the tag of the chunk.
+    Chunk(ChunkTag),
+    /// Serialization of a chunk failed.
+    #[cfg(feature = "serde")]
+    Serde(bincode::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "state I/O error: {}", e),
+            Error::BadMagic => write!(f, "not a plugin state"),
+            Error::UidMismatch(uid) => write!(f, "state belongs to plugin {:#010x}", uid),
+            Error::Checksum => write!(f, "state checksum mismatch"),
+            Error::TooNew(version) => write!(f, "state version {} is not supported", version),
+            Error::Chunk(tag) => write!(
+                f,
+                "missing or malformed chunk {}",
+                String::from_utf8_lossy(tag)
+            ),
+            #[cfg(feature = "serde")]
+            Error::Serde(e) => write!(f, "chunk serialization error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            #[cfg(feature = "serde")]
+            Error::Serde(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Serde(e)
+    }
+}
+
+/// State container result.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Tagged chunks of the plugin's state with the plugin UID and the schema version.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Container {
+    uid: u32,
+    version: u32,
+    chunks: Vec<(ChunkTag, Vec<u8>)>,
+}
+
+impl Container {
+    /// Create an empty container.
+    ///
+    /// `uid` identifies the plugin, `version` is the current version of the state schema.
+    pub fn new(uid: u32, version: u32) -> Self {
+        Self {
+            uid,
+            version,
+            chunks: Vec::new(),
+        }
+    }
+
+    /// The plugin UID.
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// The schema version.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Put the chunk, replacing the chunk with the same tag.
+    pub fn insert(&mut self, tag: ChunkTag, data: Vec<u8>) {
+        match self.chunks.iter_mut().find(|(t, _)| *t == tag) {
+            Some((_, d)) => *d = data,
+            None => self.chunks.push((tag, data)),
+        }
+    }
+
+    /// Get the chunk.
+    pub fn get(&self, tag: ChunkTag) -> Option<&[u8]> {
+        self.chunks
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, data)| &data[..])
+    }
+
+    /// Get the chunk of exactly `N` bytes.
+    pub fn get_array<const N: usize>(&self, tag: ChunkTag) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        match self.get(tag) {
+            Some(data) if data.len() == N => {
+                array.copy_from_slice(data);
+                Ok(array)
+            }
+            _ => Err(Error::Chunk(tag)),
+        }
+    }
+
+    /// Remove the chunk.
+    pub fn remove(&mut self, tag: ChunkTag) -> Option<Vec<u8>> {
+        let index = self.chunks.iter().position(|(t, _)| *t == tag)?;
+        Some(self.chunks.remove(index).1)
+    }
+
+    /// Iterate over the chunks in the order they were inserted.
+    pub fn chunks(&self) -> impl Iterator<Item = (ChunkTag, &[u8])> {
+        self.chunks.iter().map(|(tag, data)| (*tag, &data[..]))
+    }
+
+    /// Upgrade the state to the `current` version.
+    ///
+    /// `migrate` is called for each version starting from the version of the state up to
+    /// `current - 1`. It gets the version to upgrade from and should change the chunks to match
+    /// the next version.
+    ///
+    /// Fails with [`Error::TooNew`](enum.Error.html#variant.TooNew) if the state is newer than
+    /// `current`.
+    pub fn migrate<F>(&mut self, current: u32, mut migrate: F) -> Result<()>
+    where
+        F: FnMut(&mut Self, u32) -> Result<()>,
+    {
+        if self.version > current {
+            return Err(Error::TooNew(self.version));
+        }
+
+        while self.version < current {
+            let from = self.version;
+            migrate(self, from)?;
+            self.version = from + 1;
+        }
+
+        Ok(())
+    }
+
+    /// Write the container.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut buf = Vec::with_capacity(
+            16 + self
+                .chunks
+                .iter()
+                .map(|(_, data)| data.len() + 8)
+                .sum::<usize>(),
+        );
+        buf.extend_from_slice(&MAGIC);
+        buf.extend_from_slice(&self.uid.to_le_bytes());
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(&(self.chunks.len() as u32).to_le_bytes());
+        for (tag, data) in &self.chunks {
+            buf.extend_from_slice(tag);
+            buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            buf.extend_from_slice(data);
+        }
+        let crc = crc32(&buf);
+        buf.extend_from_slice(&crc.to_le_bytes());
+
+        writer.write_all(&buf)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read the container of the plugin with `uid`.
+    ///
+    /// It reads only the container, so the stream can have more data after it.
+    pub fn read_from<R: Read>(reader: R, uid: u32) -> Result<Self> {
+        let mut reader = CrcReader {
+            inner: reader,
+            crc: !0,
+        };
+
+        if read_array::<4, _>(&mut reader)? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let state_uid = read_u32(&mut reader)?;
+        if state_uid != uid {
+            return Err(Error::UidMismatch(state_uid));
+        }
+        let version = read_u32(&mut reader)?;
+        let count = read_u32(&mut reader)?;
+
+        let mut chunks = Vec::new();
+        for _ in 0..count {
+            let tag = read_array(&mut reader)?;
+            let len = read_u32(&mut reader)? as u64;
+            // don't trust the length with a preallocation
+            let mut data = Vec::new();
+            (&mut reader).take(len).read_to_end(&mut data)?;
+            if data.len() as u64 != len {
+                return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            chunks.push((tag, data));
+        }
+
+        let crc = !reader.crc;
+        if read_u32(&mut reader.inner)? != crc {
+            return Err(Error::Checksum);
+        }
+
+        Ok(Self {
+            uid,
+            version,
+            chunks,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Container {
+    /// Serialize the value into the chunk.
+    pub fn insert_serde<T: serde::Serialize>(&mut self, tag: ChunkTag, value: &T) -> Result<()> {
+        self.insert(tag, bincode::serialize(value)?);
+        Ok(())
+    }
+
+    /// Deserialize the chunk.
+    ///
+    /// Fails with [`Error::Chunk`](enum.Error.html#variant.Chunk) if there's no such chunk.
+    pub fn get_serde<T: serde::de::DeserializeOwned>(&self, tag: ChunkTag) -> Result<T> {
+        let data = self.get(tag).ok_or(Error::Chunk(tag))?;
+        Ok(bincode::deserialize(data)?)
+    }
+}
+
+fn read_array<const N: usize, R: Read>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    read_array(reader).map(u32::from_le_bytes)
+}
+
+struct CrcReader<R> {
+    inner: R,
+    crc: u32,
+}
+
+impl<R: Read> Read for CrcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.crc = crc32_update(self.crc, &buf[..read]);
+        Ok(read)
+    }
+}
+
+/// CRC-32 (IEEE) of the data.
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: u32 = 0x6e69_6147;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn round_trip_and_migrate() {
+        let mut state = Container::new(UID, 1);
+        state.insert(*b"gain", (-6.0_f32).to_le_bytes().to_vec());
+        state.insert(*b"mode", vec![2]);
+        let mut buf = Vec::new();
+        state.write_to(&mut buf).unwrap();
+        buf.extend_from_slice(b"trailing");
+
+        let mut read = Container::read_from(&buf[..], UID).unwrap();
+        assert_eq!(state, read);
+
+        read.migrate(3, |state, from| {
+            match from {
+                1 => {
+                    let db = f32::from_le_bytes(state.get_array(*b"gain")?);
+                    state.insert(*b"gain", (db * 2.0).to_le_bytes().to_vec());
+                }
+                _ => {
+                    state.remove(*b"mode").ok_or(Error::Chunk(*b"mode"))?;
+                }
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(3, read.version());
+        assert_eq!(-12.0, f32::from_le_bytes(read.get_array(*b"gain").unwrap()));
+        assert_eq!(None, read.get(*b"mode"));
+        assert!(matches!(
+            read.migrate(2, |_, _| Ok(())),
+            Err(Error::TooNew(3))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_data() {
+        let mut buf = Vec::new();
+        Container::new(UID, 1).write_to(&mut buf).unwrap();
+
+        assert!(matches!(
+            Container::read_from(&buf[..], 1),
+            Err(Error::UidMismatch(UID))
+        ));
+        buf[8] = 2;
+        assert!(matches!(
+            Container::read_from(&buf[..], UID),
+            Err(Error::Checksum)
+        ));
+        assert!(matches!(
+            Container::read_from(&b"bincode"[..], UID),
+            Err(Error::BadMagic)
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_chunks() {
+        let mut state = Container::new(UID, 1);
+        state
+            .insert_serde(*b"name", &("Gain".to_string(), 1_u8))
+            .unwrap();
+        assert_eq!(
+            ("Gain".to_string(), 1_u8),
+            state.get_serde(*b"name").unwrap()
+        );
+        assert!(matches!(
+            state.get_serde::<u8>(*b"none"),
+            Err(Error::Chunk(_))
+        ));
+    }
+}
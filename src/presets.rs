@@ -0,0 +1,435 @@
+//! Presets (programs).
+//!
+//! A [`Bank`](struct.Bank.html) holds the factory presets, compiled into the plugin, followed by
+//! the user presets, which are files in a folder. It tells the host how many there are and their
+//! names, and keeps the current one:
+//!
+//! ```ignore
+//! const UID: u32 = u32::from_le_bytes(*b"Gain");
+//!
+//! fn new(mut host: Host, tag: Tag) -> Self {
+//!     let params = /* ... */;
+//!     let factory = vec![
+//!         Preset::from_params("Default", &params),
+//!         Preset::new("Loud", loud_data()),
+//!     ];
+//!     let dir = Bank::default_user_dir(&mut host, tag, "Gain");
+//!     let mut presets = Bank::new(&host, tag, UID, factory).with_user_dir(dir);
+//!     presets.scan().unwrap_or_else(|e| error!("can't read presets: {}", e));
+//!     Self { host, tag, params, presets }
+//! }
+//!
+//! fn handle_message(&mut self, message: host::Message<'_>) -> DispatchResult {
+//!     if let Some(preset) = self.presets.on_message(&message) {
+//!         preset.apply_to(&mut self.params);
+//!     }
+//!     DispatchResult::NONE
+//! }
+//!
+//! fn name_of(&self, value: GetName) -> String {
+//!     self.presets.name_of(&value).unwrap_or_default()
+//! }
+//!
+//! fn save_state(&mut self, writer: StateWriter) {
+//!     let mut state = Container::new(UID, 1);
+//!     self.presets.save_state(&mut state);
+//!     // ...
+//! }
+//! ```
+//!
+//! A preset file is a [state container](../plugin/state/index.html) with the
+//! [`NAME`](constant.NAME.html) and [`DATA`](constant.DATA.html) chunks.
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+use log::{debug, error};
+
+use crate::host::{self, GetName, Host};
+use crate::params::Params;
+use crate::plugin::message::{GetProgPath, NamesChanged, SetNumPresets};
+use crate::plugin::state::{ChunkTag, Container, Error, Result};
+use crate::plugin::Tag;
+
+/// Extension of the preset files.
+pub const EXTENSION: &str = "fpreset";
+/// Chunk with the preset name.
+pub const NAME: ChunkTag = *b"name";
+/// Chunk with the preset data.
+pub const DATA: ChunkTag = *b"data";
+/// Chunk with the current preset index in the plugin's state. See
+/// [`Bank::save_state`](struct.Bank.html#method.save_state).
+pub const PROGRAM: ChunkTag = *b"prog";
+
+const VERSION: u32 = 1;
+
+/// A named preset. The data is up to the plugin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preset {
+    name: String,
+    data: Vec<u8>,
+}
+
+impl Preset {
+    /// Initializer.
+    pub fn new(name: &str, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            data,
+        }
+    }
+
+    /// A preset with the current values of `params`. They're kept by the parameter ids, so
+    /// it can be applied after parameters are added or reordered.
+    pub fn from_params(name: &str, params: &Params) -> Self {
+        let mut data = Vec::new();
+        for (param, value) in params.iter().zip(params.values()) {
+            let id = param.id().as_bytes();
+            data.push(id.len().min(u8::MAX as usize) as u8);
+            data.extend_from_slice(&id[..id.len().min(u8::MAX as usize)]);
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        Self::new(name, data)
+    }
+
+    /// The name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The data.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Set the values of a preset made with [`from_params`](#method.from_params), without
+    /// notifying the host. Unknown parameters are skipped.
+    ///
+    /// Returns the number of values set.
+    pub fn apply_to(&self, params: &mut Params) -> usize {
+        let mut data = &self.data[..];
+        let mut count = 0;
+        while let Some((&len, rest)) = data.split_first() {
+            let len = len as usize;
+            if rest.len() < len + 8 {
+                debug!("preset {} is truncated", self.name);
+                break;
+            }
+            let (id, rest) = rest.split_at(len);
+            let (value, rest) = rest.split_at(8);
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(value);
+            let value = f64::from_le_bytes(bytes);
+            match params.index_of(&String::from_utf8_lossy(id)) {
+                Some(index) => {
+                    params.set_silently(index, value);
+                    count += 1;
+                }
+                None => debug!("preset {} has unknown parameter", self.name),
+            }
+            data = rest;
+        }
+        count
+    }
+
+    /// Write the preset of the plugin with `uid`.
+    pub fn write_to<W: Write>(&self, writer: W, uid: u32) -> Result<()> {
+        let mut container = Container::new(uid, VERSION);
+        container.insert(NAME, self.name.as_bytes().to_vec());
+        container.insert(DATA, self.data.clone());
+        container.write_to(writer)
+    }
+
+    /// Read the preset of the plugin with `uid`.
+    pub fn read_from<R: Read>(reader: R, uid: u32) -> Result<Self> {
+        let mut container = Container::read_from(reader, uid)?;
+        container.migrate(VERSION, |_, _| Ok(()))?;
+        let name = container.remove(NAME).ok_or(Error::Chunk(NAME))?;
+        let data = container.remove(DATA).ok_or(Error::Chunk(DATA))?;
+        Ok(Self {
+            name: String::from_utf8_lossy(&name).into_owned(),
+            data,
+        })
+    }
+
+    /// Save to a file.
+    pub fn save(&self, path: &Path, uid: u32) -> Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?), uid)
+    }
+
+    /// Load from a file.
+    pub fn load(path: &Path, uid: u32) -> Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?), uid)
+    }
+}
+
+/// Factory and user presets of a plugin.
+#[derive(Debug)]
+pub struct Bank {
+    host: Host,
+    tag: Tag,
+    uid: u32,
+    factory: Vec<Preset>,
+    user: Vec<Preset>,
+    user_dir: Option<PathBuf>,
+    current: usize,
+}
+
+impl Bank {
+    /// Initializer. `uid` identifies the plugin in the preset files.
+    pub fn new(host: &Host, tag: Tag, uid: u32, factory: Vec<Preset>) -> Self {
+        Self {
+            host: Host::new(host.host_ptr.load(Ordering::Relaxed)),
+            tag,
+            uid,
+            factory,
+            user: Vec::new(),
+            user_dir: None,
+            current: 0,
+        }
+    }
+
+    /// Keep the user presets in `dir`.
+    pub fn with_user_dir(mut self, dir: PathBuf) -> Self {
+        self.user_dir = Some(dir);
+        self
+    }
+
+    /// `Presets/<plugin name>` in the folder of the FL Studio engine (see
+    /// [`GetProgPath`](../plugin/message/struct.GetProgPath.html)).
+    pub fn default_user_dir(host: &mut Host, tag: Tag, plugin_name: &str) -> PathBuf {
+        Path::new(&host.on_message(tag, GetProgPath))
+            .join("Presets")
+            .join(file_name(plugin_name))
+    }
+
+    /// The folder of the user presets.
+    pub fn user_dir(&self) -> Option<&Path> {
+        self.user_dir.as_deref()
+    }
+
+    /// The number of presets.
+    pub fn len(&self) -> usize {
+        self.factory.len() + self.user.len()
+    }
+
+    /// Whether there are no presets.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The preset. The factory presets go first.
+    pub fn get(&self, index: usize) -> Option<&Preset> {
+        self.factory
+            .get(index)
+            .or_else(|| self.user.get(index.checked_sub(self.factory.len())?))
+    }
+
+    /// Iterate over the presets.
+    pub fn iter(&self) -> impl Iterator<Item = &Preset> {
+        self.factory.iter().chain(&self.user)
+    }
+
+    /// The index of the current preset.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Make the preset current and get it to apply.
+    ///
+    /// Returns `None` if there's no such preset.
+    pub fn select(&mut self, index: usize) -> Option<&Preset> {
+        if index >= self.len() {
+            debug!("unknown preset {}", index);
+            return None;
+        }
+        self.current = index;
+        self.get(index)
+    }
+
+    /// Read the user presets from the folder, sorted by name, and notify the host. It's fine if
+    /// the folder doesn't exist. Files which aren't presets of this plugin are skipped.
+    ///
+    /// Returns the number of user presets.
+    pub fn scan(&mut self) -> Result<usize> {
+        self.user.clear();
+        if let Some(dir) = &self.user_dir {
+            if dir.is_dir() {
+                for entry in fs::read_dir(dir)? {
+                    let path = entry?.path();
+                    if path.extension() != Some(OsStr::new(EXTENSION)) {
+                        continue;
+                    }
+                    match Preset::load(&path, self.uid) {
+                        Ok(preset) => self.user.push(preset),
+                        Err(e) => error!("can't load preset {}: {}", path.display(), e),
+                    }
+                }
+            }
+        }
+        self.user.sort_by(|a, b| a.name.cmp(&b.name));
+        self.current = self.current.min(self.len().saturating_sub(1));
+        self.notify();
+        Ok(self.user.len())
+    }
+
+    /// Save a user preset to the folder, replacing the preset with the same name, and notify the
+    /// host.
+    ///
+    /// Returns the path of the file.
+    pub fn save_user(&mut self, preset: Preset) -> Result<PathBuf> {
+        let dir = self
+            .user_dir
+            .clone()
+            .ok_or_else(|| Error::Io(io::ErrorKind::NotFound.into()))?;
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.{}", file_name(&preset.name), EXTENSION));
+        preset.save(&path, self.uid)?;
+
+        match self.user.iter_mut().find(|user| user.name == preset.name) {
+            Some(user) => *user = preset,
+            None => {
+                let index = self.user.partition_point(|user| user.name < preset.name);
+                self.user.insert(index, preset);
+            }
+        }
+        self.notify();
+        Ok(path)
+    }
+
+    /// Tell the host the number of presets and that their names have changed.
+    pub fn notify(&mut self) {
+        let len = self.len();
+        self.host.on_message(self.tag, SetNumPresets(len));
+        self.host
+            .on_message(self.tag, NamesChanged(GetName::Preset(0)));
+    }
+
+    /// Handle [`host::Message::SetPreset`](../host/enum.Message.html#variant.SetPreset).
+    ///
+    /// Returns the preset to apply.
+    pub fn on_message(&mut self, message: &host::Message<'_>) -> Option<&Preset> {
+        match *message {
+            host::Message::SetPreset(index) => self.select(index as usize),
+            _ => None,
+        }
+    }
+
+    /// Handle [`GetName::Preset`](../host/enum.GetName.html#variant.Preset).
+    ///
+    /// Returns `None` for other names.
+    pub fn name_of(&self, value: &GetName) -> Option<String> {
+        match *value {
+            GetName::Preset(index) => self.get(index).map(|preset| preset.name.clone()),
+            _ => None,
+        }
+    }
+
+    /// Put the current preset index to the plugin's state.
+    pub fn save_state(&self, state: &mut Container) {
+        state.insert(PROGRAM, (self.current as u32).to_le_bytes().to_vec());
+    }
+
+    /// Take the current preset index from the plugin's state. The preset isn't applied, the
+    /// state has the values.
+    pub fn load_state(&mut self, state: &Container) -> Result<()> {
+        let index = u32::from_le_bytes(state.get_array(PROGRAM)?) as usize;
+        self.current = index.min(self.len().saturating_sub(1));
+        Ok(())
+    }
+}
+
+// Replace the characters which can't be in a file name.
+fn file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|ch| match ch {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            ch if ch.is_control() => '_',
+            ch => ch,
+        })
+        .collect();
+    match name.trim() {
+        "" | "." | ".." => "_".to_string(),
+        name => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Param;
+    use crate::testing::{id, HostCall, MockHost, Reply};
+
+    const UID: u32 = 0x6e69_6147;
+
+    #[test]
+    fn factory_and_user_presets() {
+        let mock = MockHost::new();
+        let mut host = mock.host();
+        let tag = Tag(1);
+        let mut params = Params::builder()
+            .param(Param::float("gain", "Gain", 0.0, 2.0, 1.0))
+            .param(Param::choice("mode", "Mode", &["Clean", "Warm"], 0))
+            .build(&host, tag);
+        let default = Preset::from_params("Default", &params);
+        params.set_silently(0, 2.0);
+        let factory = vec![default, Preset::from_params("Loud", &params)];
+
+        let root = std::env::temp_dir().join(format!("fpsdk-presets-{}", std::process::id()));
+        mock.queue_reply(
+            id::GET_PROG_PATH,
+            Reply::String(root.to_string_lossy().into_owned()),
+        );
+        let dir = Bank::default_user_dir(&mut host, tag, "Gain: Stereo");
+        assert_eq!(root.join("Presets").join("Gain_ Stereo"), dir);
+
+        let mut bank = Bank::new(&host, tag, UID, factory).with_user_dir(dir.clone());
+        assert_eq!(0, bank.scan().unwrap());
+        params.set_silently(1, 1.0);
+        let path = bank
+            .save_user(Preset::from_params("Warm/Loud", &params))
+            .unwrap();
+        assert_eq!(dir.join("Warm_Loud.fpreset"), path);
+        fs::write(dir.join("notes.txt"), b"not a preset").unwrap();
+        mock.take_calls();
+
+        let mut bank = Bank::new(&host, tag, UID, bank.factory.clone()).with_user_dir(dir);
+        assert_eq!(1, bank.scan().unwrap());
+        assert_eq!(3, bank.len());
+        assert_eq!(
+            Some("Warm/Loud".to_string()),
+            bank.name_of(&GetName::Preset(2))
+        );
+        assert_eq!(None, bank.name_of(&GetName::Preset(3)));
+        let notified: Vec<_> = mock
+            .take_calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                HostCall::Message { id, value, .. } => Some((id, value)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            vec![(id::SET_NUM_PRESETS, 3), (id::NAMES_CHANGED, 6)],
+            notified
+        );
+
+        params.reset();
+        let preset = bank.on_message(&host::Message::SetPreset(1)).unwrap();
+        assert_eq!(2, preset.apply_to(&mut params));
+        assert_eq!(&[2.0, 0.0], params.values());
+        assert!(bank.on_message(&host::Message::SetPreset(5)).is_none());
+
+        let mut state = Container::new(UID, 1);
+        bank.save_state(&mut state);
+        let mut loaded = Bank::new(&host, tag, UID, Vec::new());
+        loaded.factory = bank.factory.clone();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(1, loaded.current());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+//! Sample-accurate render.
+//!
+//! Parameter changes, MIDI and voice triggers come between render calls, so a plugin which
+//! applies them before the block hears them at its first sample.
+//! [`Driver`](struct.Driver.html) keeps them with a time and splits the block, so each event is
+//! applied at its sample:
+//!
+//! ```ignore
+//! enum Change {
+//!     Gain(f32),
+//!     Note(u8),
+//! }
+//!
+//! fn handle_param(
+//!     &mut self,
+//!     index: usize,
+//!     value: ValuePtr,
+//!     flags: ProcessParamFlags,
+//! ) -> DispatchResult {
+//!     if flags.contains(ProcessParamFlags::UPDATE_VALUE) {
+//!         // stamped with the mixing time
+//!         self.driver.push(Change::Gain(value.get::<f32>()));
+//!     }
+//!     DispatchResult::NONE
+//! }
+//!
+//! fn render(&mut self, ctx: &mut AudioContext<'_>, input: &[[f32; 2]], output: &mut [[f32; 2]]) {
+//!     let state = &mut self.state;
+//!     self.driver.render(ctx, |_ctx, range, changes| {
+//!         for change in changes {
+//!             state.apply(change);
+//!         }
+//!         state.process(&input[range.clone()], &mut output[range]);
+//!     });
+//! }
+//! ```
+//!
+//! Events pushed with [`Driver::push`](struct.Driver.html#method.push) are stamped with
+//! [`GetMixingTime`](../plugin/message/struct.GetMixingTime.html). At render, the driver asks for
+//! the mixing time at the start and at the end of the block (using the sample offset) to find
+//! their samples. If the host doesn't tell the time, they're applied at the start of the block.
+//! Events with a known offset are pushed with
+//! [`Driver::push_at`](struct.Driver.html#method.push_at).
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+use std::vec::Drain;
+
+use crate::host::context::AudioContext;
+use crate::host::Host;
+use crate::plugin::message::GetMixingTime;
+use crate::plugin::Tag;
+use crate::TimeFormat;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stamp {
+    // running ms
+    Time(f64),
+    // samples from the start of the next block
+    Offset(usize),
+}
+
+#[derive(Debug)]
+struct Timed<E> {
+    stamp: Stamp,
+    event: E,
+}
+
+/// Splits render blocks at events.
+#[derive(Debug)]
+pub struct Driver<E> {
+    host: Host,
+    tag: Tag,
+    min_chunk: usize,
+    events: Vec<Timed<E>>,
+}
+
+impl<E> Driver<E> {
+    /// Initializer.
+    ///
+    /// `capacity` is the number of events which can be kept without allocating.
+    ///
+    /// `min_chunk` is the shortest sub-block in samples. An event closer than that to the start
+    /// of the current sub-block is applied at its start.
+    pub fn new(host: &Host, tag: Tag, capacity: usize, min_chunk: usize) -> Self {
+        Self {
+            host: Host::new(host.host_ptr.load(Ordering::Relaxed)),
+            tag,
+            min_chunk: min_chunk.max(1),
+            events: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Keep an event which happens now, stamped with the mixing time.
+    pub fn push(&mut self, event: E) {
+        let time = self
+            .host
+            .on_message(self.tag, GetMixingTime(TimeFormat::RunningMs, 0));
+        self.events.push(Timed {
+            stamp: Stamp::Time(time.0),
+            event,
+        });
+    }
+
+    /// Keep an event which happens `offset` samples into the next block.
+    pub fn push_at(&mut self, offset: usize, event: E) {
+        self.events.push(Timed {
+            stamp: Stamp::Offset(offset),
+            event,
+        });
+    }
+
+    /// Number of kept events.
+    pub fn pending(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Drop the kept events.
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// Split the block and call `process` for each sub-block with its range and the events to
+    /// apply at its start, in the order they were pushed.
+    ///
+    /// Events past the end of the block are kept for the next one.
+    pub fn render<F>(&mut self, ctx: &mut AudioContext<'_>, mut process: F)
+    where
+        F: FnMut(&mut AudioContext<'_>, Range<usize>, Events<'_, E>),
+    {
+        let len = ctx.block_len();
+        if len == 0 {
+            return;
+        }
+        self.resolve(ctx, len);
+
+        let mut start = 0;
+        while start < len {
+            // events past the block stay for the next one
+            let threshold = (start + self.min_chunk).min(len);
+            let count = self
+                .events
+                .iter()
+                .take_while(|timed| offset_of(timed) < threshold)
+                .count();
+            let end = self
+                .events
+                .get(count)
+                .map_or(len, |timed| offset_of(timed).min(len));
+            process(ctx, start..end, Events(self.events.drain(..count)));
+            start = end;
+        }
+
+        for timed in &mut self.events {
+            if let Stamp::Offset(offset) = &mut timed.stamp {
+                *offset -= len;
+            }
+        }
+    }
+
+    // Turn the times into offsets in the block and sort by offset.
+    fn resolve(&mut self, ctx: &mut AudioContext<'_>, len: usize) {
+        if self
+            .events
+            .iter()
+            .any(|timed| matches!(timed.stamp, Stamp::Time(_)))
+        {
+            let start = ctx.on_message(GetMixingTime(TimeFormat::RunningMs, 0)).0;
+            let end = ctx
+                .on_message(GetMixingTime(TimeFormat::RunningMs, len as u64))
+                .0;
+            for timed in &mut self.events {
+                if let Stamp::Time(time) = timed.stamp {
+                    if end <= start || time < start {
+                        // no timing or it's late
+                        timed.stamp = Stamp::Offset(0);
+                    } else if time < end {
+                        let offset = (time - start) / (end - start) * len as f64;
+                        timed.stamp = Stamp::Offset((offset as usize).min(len - 1));
+                    }
+                }
+            }
+        }
+
+        // stable and without allocating, there are few events
+        for index in 1..self.events.len() {
+            let mut current = index;
+            while current > 0
+                && offset_of(&self.events[current - 1]) > offset_of(&self.events[current])
+            {
+                self.events.swap(current - 1, current);
+                current -= 1;
+            }
+        }
+    }
+}
+
+// Events still stamped with a time are past the block.
+fn offset_of<E>(timed: &Timed<E>) -> usize {
+    match timed.stamp {
+        Stamp::Offset(offset) => offset,
+        Stamp::Time(_) => usize::MAX,
+    }
+}
+
+/// Events to apply at the start of a sub-block. See
+/// [`Driver::render`](struct.Driver.html#method.render).
+#[derive(Debug)]
+pub struct Events<'a, E>(Drain<'a, Timed<E>>);
+
+impl<E> Iterator for Events<'_, E> {
+    type Item = E;
+
+    fn next(&mut self) -> Option<E> {
+        self.0.next().map(|timed| timed.event)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<E> ExactSizeIterator for Events<'_, E> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::routing::Routing;
+    use crate::testing::{id, MockHost, Reply};
+
+    #[test]
+    fn splits_at_events() {
+        let mock = MockHost::new();
+        let mut host = mock.host();
+        let tag = Tag(1);
+        let mut routing = Routing::new(&host, tag);
+        let mut driver = Driver::new(&host, tag, 8, 4);
+
+        // the mixing time when the events come, then at the start and at the end of the block
+        for time in [1002.5, 1012.5, 999.0, 1000.0, 1010.0] {
+            mock.queue_reply(id::GET_MIXING_TIME, Reply::Time(time, 0.0));
+        }
+        driver.push("gain");
+        driver.push("next block");
+        driver.push("late");
+        driver.push_at(300, "note");
+        driver.push_at(102, "close");
+        driver.push_at(450, "later");
+
+        let mut chunks = Vec::new();
+        let mut ctx = AudioContext::new(&mut host, &mut routing, tag, 400);
+        driver.render(&mut ctx, |_ctx, range, events| {
+            chunks.push((range, events.collect::<Vec<_>>()))
+        });
+
+        assert_eq!(
+            vec![
+                (0..100, vec!["late"]),
+                (100..300, vec!["gain", "close"]),
+                (300..400, vec!["note"]),
+            ],
+            chunks
+        );
+        assert_eq!(2, driver.pending());
+
+        // the host doesn't tell the time
+        mock.queue_reply(id::GET_MIXING_TIME, Reply::Time(0.0, 0.0));
+        mock.queue_reply(id::GET_MIXING_TIME, Reply::Time(0.0, 0.0));
+        chunks.clear();
+        let mut ctx = AudioContext::new(&mut host, &mut routing, tag, 100);
+        driver.render(&mut ctx, |_ctx, range, events| {
+            chunks.push((range, events.collect::<Vec<_>>()))
+        });
+        assert_eq!(
+            vec![(0..50, vec!["next block"]), (50..100, vec!["later"])],
+            chunks
+        );
+        assert_eq!(0, driver.pending());
+
+        // an event close to the end of the block, but past it
+        driver.push_at(98, "end");
+        driver.push_at(101, "after");
+        chunks.clear();
+        let mut ctx = AudioContext::new(&mut host, &mut routing, tag, 100);
+        driver.render(&mut ctx, |_ctx, range, events| {
+            chunks.push((range, events.collect::<Vec<_>>()))
+        });
+        assert_eq!(vec![(0..98, vec![]), (98..100, vec!["end"])], chunks);
+        assert_eq!(1, driver.pending());
+    }
+}
@@ -0,0 +1,2080 @@
+//! In-process mock of FL Studio, to drive plugins from `cargo test`.
+//!
+//! [`MockHost`](struct.MockHost.html) implements the host side of the SDK in Rust. Plugins created
+//! with [`MockHost::create`](struct.MockHost.html#method.create) run through the same C++ wrapper
+//! FL Studio talks to, so everything they send with [`Host`](../host/struct.Host.html) ends up
+//! recorded as a [`HostCall`](enum.HostCall.html). Replies for dispatcher messages (like
+//! [`GetMixingTime`](../plugin/message/struct.GetMixingTime.html) or
+//! [`GetInName`](../plugin/message/struct.GetInName.html)) can be queued with
+//! [`MockHost::queue_reply`](struct.MockHost.html#method.queue_reply).
+//!
+//! This module is available with the `testing` feature.
+mod ffi;
+
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_long, c_ulong, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use log::error;
+
+use crate::host::sample::{self, Region};
+use crate::host::{self, GetName, Host};
+use crate::plugin::{Effect, Generator, PluginAdapter, Tag};
+use crate::voice;
+use crate::{
+    cstr, intptr_t, MidiMessage, Note, Notes, NotesFlags, NotesTarget, ParamMenuItemFlags,
+    ProcessParamFlags, SampleLoadFlags, SongTime, TNameColor, TParamMenuEntry, TTimeSigInfo, Time,
+    Transport, WAVETABLE_SIZE,
+};
+
+use self::ffi::{FruityPlug, FruityPlugHost, FruityPlugHostVtbl, Stream, StreamVtbl};
+
+/// Implement [`Plugin`](../plugin/trait.Plugin.html) for a plugin used in tests. It has no state
+/// and no names, and it's created with `Default` unless `new` is given. The other methods are
+/// the defaults, or the ones given.
+///
+/// ```ignore
+/// fpsdk::test_plugin!(Half, InfoBuilder::new("Half", "Half", 0).build());
+///
+/// fpsdk::test_plugin!(Sampler, InfoBuilder::new("Sampler", "Sampler", 0).build(), {
+///     fn new(host: Host, _tag: plugin::Tag) -> Self {
+///         Self { host, sample: None }
+///     }
+///
+///     fn handle_message(&mut self, message: host::Message<'_>) -> DispatchResult {
+///         // ...
+///     }
+/// });
+/// ```
+#[macro_export]
+macro_rules! test_plugin {
+    ($plugin:ty, $info:expr) => {
+        $crate::test_plugin!($plugin, $info, {});
+    };
+    ($plugin:ty, $info:expr, { fn new($($args:tt)*) -> Self $new:block $($items:tt)* }) => {
+        impl $crate::plugin::Plugin for $plugin {
+            fn new($($args)*) -> Self $new
+
+            $crate::test_plugin!(@no_op $info);
+
+            $($items)*
+        }
+    };
+    ($plugin:ty, $info:expr, { $($items:tt)* }) => {
+        impl $crate::plugin::Plugin for $plugin {
+            fn new(_host: $crate::host::Host, _tag: $crate::plugin::Tag) -> Self {
+                Default::default()
+            }
+
+            $crate::test_plugin!(@no_op $info);
+
+            $($items)*
+        }
+    };
+    (@no_op $info:expr) => {
+        fn info(&self) -> $crate::plugin::Info {
+            $info
+        }
+
+        fn save_state(&mut self, _writer: $crate::plugin::StateWriter) {}
+
+        fn load_state(&mut self, _reader: $crate::plugin::StateReader) {}
+
+        fn name_of(&self, _name: $crate::host::GetName) -> String {
+            String::new()
+        }
+    };
+}
+
+/// IDs of the messages a plugin sends to the host, as recorded in
+/// [`HostCall::Message`](../enum.HostCall.html#variant.Message).
+pub mod id {
+    use crate::intptr_t;
+
+    /// [`ParamMenu`](../../plugin/message/struct.ParamMenu.html)
+    pub const PARAM_MENU: intptr_t = 0;
+    /// [`EditorResized`](../../plugin/message/struct.EditorResized.html)
+    pub const EDITOR_RESIZED: intptr_t = 2;
+    /// [`NamesChanged`](../../plugin/message/struct.NamesChanged.html)
+    pub const NAMES_CHANGED: intptr_t = 3;
+    /// [`ActivateMidi`](../../plugin/message/struct.ActivateMidi.html)
+    pub const ACTIVATE_MIDI: intptr_t = 4;
+    /// [`WantMidiInput`](../../plugin/message/struct.WantMidiInput.html)
+    pub const WANT_MIDI_INPUT: intptr_t = 5;
+    /// [`KillAutomation`](../../plugin/message/struct.KillAutomation.html)
+    pub const KILL_AUTOMATION: intptr_t = 8;
+    /// [`SetNumPresets`](../../plugin/message/struct.SetNumPresets.html)
+    pub const SET_NUM_PRESETS: intptr_t = 9;
+    /// [`SetNewName`](../../plugin/message/struct.SetNewName.html)
+    pub const SET_NEW_NAME: intptr_t = 10;
+    /// [`VstiIdle`](../../plugin/message/struct.VstiIdle.html)
+    pub const VSTI_IDLE: intptr_t = 11;
+    /// [`WantIdle`](../../plugin/message/enum.WantIdle.html)
+    pub const WANT_IDLE: intptr_t = 13;
+    /// [`LocateDataFile`](../../plugin/message/struct.LocateDataFile.html)
+    pub const LOCATE_DATA_FILE: intptr_t = 14;
+    /// [`TicksToTime`](../../plugin/message/struct.TicksToTime.html)
+    pub const TICKS_TO_TIME: intptr_t = 16;
+    /// [`AddToPianoRoll`](../../plugin/message/struct.AddToPianoRoll.html)
+    pub const ADD_TO_PIANO_ROLL: intptr_t = 17;
+    /// [`GetParamMenuEntry`](../../plugin/message/struct.GetParamMenuEntry.html)
+    pub const GET_PARAM_MENU_ENTRY: intptr_t = 18;
+    /// [`MessageBox`](../../plugin/message/struct.MessageBox.html)
+    pub const MESSAGE_BOX: intptr_t = 19;
+    /// [`NoteOn`](../../plugin/message/struct.NoteOn.html)
+    pub const NOTE_ON: intptr_t = 20;
+    /// [`NoteOff`](../../plugin/message/struct.NoteOff.html)
+    pub const NOTE_OFF: intptr_t = 21;
+    /// [`OnHintDirect`](../../plugin/message/struct.OnHintDirect.html)
+    pub const ON_HINT_DIRECT: intptr_t = 22;
+    /// [`SetNewColor`](../../plugin/message/struct.SetNewColor.html)
+    pub const SET_NEW_COLOR: intptr_t = 23;
+    /// [`KillIntCtrl`](../../plugin/message/struct.KillIntCtrl.html)
+    pub const KILL_INT_CTRL: intptr_t = 25;
+    /// [`SetNumParams`](../../plugin/message/struct.SetNumParams.html)
+    pub const SET_NUM_PARAMS: intptr_t = 27;
+    /// [`PackDataFile`](../../plugin/message/struct.PackDataFile.html)
+    pub const PACK_DATA_FILE: intptr_t = 28;
+    /// [`GetProgPath`](../../plugin/message/struct.GetProgPath.html)
+    pub const GET_PROG_PATH: intptr_t = 29;
+    /// [`SetLatency`](../../plugin/message/struct.SetLatency.html)
+    pub const SET_LATENCY: intptr_t = 30;
+    /// [`CallDownloader`](../../plugin/message/struct.CallDownloader.html)
+    pub const CALL_DOWNLOADER: intptr_t = 31;
+    /// [`EditSample`](../../plugin/message/struct.EditSample.html)
+    pub const EDIT_SAMPLE: intptr_t = 32;
+    /// [`SetThreadSafe`](../../plugin/message/struct.SetThreadSafe.html)
+    pub const SET_THREAD_SAFE: intptr_t = 33;
+    /// [`SmartDisable`](../../plugin/message/struct.SmartDisable.html)
+    pub const SMART_DISABLE: intptr_t = 34;
+    /// [`SetUid`](../../plugin/message/struct.SetUid.html)
+    pub const SET_UID: intptr_t = 35;
+    /// [`GetMixingTime`](../../plugin/message/struct.GetMixingTime.html)
+    pub const GET_MIXING_TIME: intptr_t = 36;
+    /// [`GetPlaybackTime`](../../plugin/message/struct.GetPlaybackTime.html)
+    pub const GET_PLAYBACK_TIME: intptr_t = 37;
+    /// [`GetSelTime`](../../plugin/message/struct.GetSelTime.html)
+    pub const GET_SEL_TIME: intptr_t = 38;
+    /// [`GetTimeMul`](../../plugin/message/struct.GetTimeMul.html)
+    pub const GET_TIME_MUL: intptr_t = 39;
+    /// [`Captionize`](../../plugin/message/struct.Captionize.html)
+    pub const CAPTIONIZE: intptr_t = 40;
+    /// [`SendSysEx`](../../plugin/message/struct.SendSysEx.html)
+    pub const SEND_SYS_EX: intptr_t = 41;
+    /// [`LoadAudioClip`](../../plugin/message/struct.LoadAudioClip.html)
+    pub const LOAD_AUDIO_CLIP: intptr_t = 42;
+    /// [`LoadInChannel`](../../plugin/message/struct.LoadInChannel.html)
+    pub const LOAD_IN_CHANNEL: intptr_t = 43;
+    /// [`ShowInBrowser`](../../plugin/message/struct.ShowInBrowser.html)
+    pub const SHOW_IN_BROWSER: intptr_t = 44;
+    /// [`DebugLogMsg`](../../plugin/message/struct.DebugLogMsg.html)
+    pub const DEBUG_LOG_MSG: intptr_t = 45;
+    /// [`GetMainFormHandle`](../../plugin/message/struct.GetMainFormHandle.html)
+    pub const GET_MAIN_FORM_HANDLE: intptr_t = 46;
+    /// [`GetProjDataPath`](../../plugin/message/struct.GetProjDataPath.html)
+    pub const GET_PROJ_DATA_PATH: intptr_t = 47;
+    /// [`SetDirty`](../../plugin/message/struct.SetDirty.html)
+    pub const SET_DIRTY: intptr_t = 48;
+    /// [`AddToRecent`](../../plugin/message/struct.AddToRecent.html)
+    pub const ADD_TO_RECENT: intptr_t = 49;
+    /// [`GetNumInOut`](../../plugin/message/enum.GetNumInOut.html)
+    pub const GET_NUM_IN_OUT: intptr_t = 50;
+    /// [`GetInName`](../../plugin/message/struct.GetInName.html)
+    pub const GET_IN_NAME: intptr_t = 51;
+    /// [`GetOutName`](../../plugin/message/struct.GetOutName.html)
+    pub const GET_OUT_NAME: intptr_t = 52;
+    /// [`ShowEditor`](../../plugin/message/enum.ShowEditor.html)
+    pub const SHOW_EDITOR: intptr_t = 53;
+    /// [`FloatAutomation`](../../plugin/message/struct.FloatAutomation.html)
+    pub const FLOAT_AUTOMATION: intptr_t = 54;
+    /// [`ShowSettings`](../../plugin/message/struct.ShowSettings.html)
+    pub const SHOW_SETTINGS: intptr_t = 55;
+    /// [`NoteOnOff`](../../plugin/message/struct.NoteOnOff.html)
+    pub const NOTE_ON_OFF: intptr_t = 56;
+    /// [`ShowPicker`](../../plugin/message/enum.ShowPicker.html)
+    pub const SHOW_PICKER: intptr_t = 57;
+    /// [`GetIdleOverflow`](../../plugin/message/struct.GetIdleOverflow.html)
+    pub const GET_IDLE_OVERFLOW: intptr_t = 58;
+    /// [`ModalIdle`](../../plugin/message/struct.ModalIdle.html)
+    pub const MODAL_IDLE: intptr_t = 59;
+    /// [`RenderProject`](../../plugin/message/struct.RenderProject.html)
+    pub const RENDER_PROJECT: intptr_t = 60;
+    /// [`GetProjectInfo`](../../plugin/message/enum.GetProjectInfo.html)
+    pub const GET_PROJECT_INFO: intptr_t = 61;
+}
+
+/// Version of FL Studio the mock host reports (20.8.0).
+pub const HOST_VERSION: i32 = 20_008_000;
+
+/// A reply to a dispatcher message, queued with
+/// [`MockHost::queue_reply`](struct.MockHost.html#method.queue_reply).
+#[derive(Debug, Clone)]
+pub enum Reply {
+    /// Return the value as is.
+    Value(intptr_t),
+    /// Return a string (for messages returning `String`). It's UTF-16 for
+    /// [`GetProjectInfo`](../plugin/message/struct.GetProjectInfo.html).
+    String(String),
+    /// Fill the time for [`GetMixingTime`](../plugin/message/struct.GetMixingTime.html),
+    /// [`GetPlaybackTime`](../plugin/message/struct.GetPlaybackTime.html) and
+    /// [`GetSelTime`](../plugin/message/struct.GetSelTime.html).
+    Time(f64, f64),
+    /// Fill the time for [`TicksToTime`](../plugin/message/struct.TicksToTime.html).
+    SongTime(SongTime),
+    /// Fill the name for [`GetInName`](../plugin/message/struct.GetInName.html) and
+    /// [`GetOutName`](../plugin/message/struct.GetOutName.html).
+    ///
+    /// The values are name, visible name, color and index.
+    NameColor(String, String, u8, usize),
+    /// Return the entry for
+    /// [`GetParamMenuEntry`](../plugin/message/struct.GetParamMenuEntry.html).
+    ParamMenuEntry(String, ParamMenuItemFlags),
+}
+
+/// Decoded data attached to a [`HostCall::Message`](enum.HostCall.html#variant.Message).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    /// The message carries nothing the mock knows how to decode.
+    None,
+    /// The message carries a string.
+    Text(String),
+    /// The notes sent with [`AddToPianoRoll`](../plugin/message/struct.AddToPianoRoll.html).
+    Notes(Notes),
+    /// The data sent with [`SendSysEx`](../plugin/message/struct.SendSysEx.html).
+    SysEx(Vec<u8>),
+}
+
+/// A call the plugin made to the host.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostCall {
+    /// A dispatcher message. See [`id`](id/index.html) for message IDs.
+    Message {
+        /// Message ID.
+        id: intptr_t,
+        /// Raw index.
+        index: intptr_t,
+        /// Raw value.
+        value: intptr_t,
+        /// Decoded data.
+        payload: Payload,
+    },
+    /// [`Host::on_parameter`](../host/struct.Host.html#method.on_parameter).
+    Parameter {
+        /// Parameter index.
+        index: c_int,
+        /// Parameter value.
+        value: c_int,
+    },
+    /// [`Host::on_hint`](../host/struct.Host.html#method.on_hint).
+    Hint(String),
+    /// [`Host::on_controller`](../host/struct.Host.html#method.on_controller).
+    Controller {
+        /// Controller index.
+        index: intptr_t,
+        /// Controller value.
+        value: intptr_t,
+    },
+    /// [`Host::midi_out`](../host/struct.Host.html#method.midi_out).
+    MidiOut(MidiMessage),
+    /// [`Host::midi_out_del`](../host/struct.Host.html#method.midi_out_del).
+    MidiOutDelayed(MidiMessage),
+    /// [`Host::loop_out`](../host/struct.Host.html#method.loop_out).
+    LoopOut(intptr_t),
+    /// [`Host::loop_kill`](../host/struct.Host.html#method.loop_kill).
+    LoopKill(intptr_t),
+    /// [`GuiContext::lock_mix`](../host/context/struct.GuiContext.html#method.lock_mix).
+    LockMix,
+    /// The [`GuiContext::lock_mix`](../host/context/struct.GuiContext.html#method.lock_mix) lock was
+    /// dropped.
+    UnlockMix,
+    /// The plugin locked the output buffer (see
+    /// [`Routing::output`](../host/routing/struct.Routing.html#method.output)).
+    LockOutput(intptr_t),
+    /// The plugin unlocked the output buffer.
+    UnlockOutput(intptr_t),
+    /// The plugin asked to load a sample (see
+    /// [`Sample`](../host/sample/struct.Sample.html)).
+    LoadSample(String),
+    /// The plugin closed the sample with this handle.
+    CloseSample(intptr_t),
+    /// [`GuiContext::lock_plugin`](../host/context/struct.GuiContext.html#method.lock_plugin).
+    LockPlugin,
+    /// The [`GuiContext::lock_plugin`](../host/context/struct.GuiContext.html#method.lock_plugin) lock was
+    /// dropped.
+    UnlockPlugin,
+    /// [`GuiContext::suspend_out`](../host/context/struct.GuiContext.html#method.suspend_out).
+    SuspendOutput,
+    /// The [`GuiContext::suspend_out`](../host/context/struct.GuiContext.html#method.suspend_out) lock was
+    /// dropped.
+    ResumeOutput,
+    /// The plugin released a voice.
+    VoiceRelease(intptr_t),
+    /// The plugin killed a voice.
+    VoiceKill(intptr_t),
+    /// The plugin sent an event for a voice.
+    VoiceEvent {
+        /// Voice tag.
+        tag: intptr_t,
+        /// Event ID.
+        id: intptr_t,
+        /// Event value.
+        value: intptr_t,
+        /// Event flags.
+        flags: intptr_t,
+    },
+    /// The plugin triggered an output voice. The mock host replies with `handle`.
+    OutVoiceTrigger {
+        /// Voice parameters.
+        params: voice::Params,
+        /// Voice output index.
+        index: intptr_t,
+        /// Voice tag.
+        tag: intptr_t,
+        /// Handle returned to the plugin.
+        handle: intptr_t,
+    },
+    /// The plugin released an output voice.
+    OutVoiceRelease(intptr_t),
+    /// The plugin killed an output voice.
+    OutVoiceKill(intptr_t),
+    /// The plugin sent an event for an output voice.
+    OutVoiceEvent {
+        /// Voice handle.
+        handle: intptr_t,
+        /// Event ID.
+        id: intptr_t,
+        /// Event value.
+        value: intptr_t,
+        /// Event flags.
+        flags: intptr_t,
+    },
+    /// The plugin asked to show a prompt. The mock host always cancels it.
+    Prompt {
+        /// Caption.
+        caption: String,
+        /// Initial value.
+        value: String,
+    },
+}
+
+#[derive(Default)]
+struct State {
+    calls: Vec<HostCall>,
+    replies: HashMap<intptr_t, VecDeque<Reply>>,
+    strings: Vec<CString>,
+    wide_strings: Vec<Vec<u16>>,
+    // the plugin reads it as soon as it gets it
+    menu_entry: Option<Box<TParamMenuEntry>>,
+    inputs: Vec<Vec<[f32; 2]>>,
+    outputs: Vec<Vec<[f32; 2]>>,
+    // the handle is the index + 1
+    samples: Vec<MockSample>,
+    last_out_voice: intptr_t,
+    last_tag: intptr_t,
+}
+
+// The host keeps the frames in the format asked with LoadSample (16-bit stereo) and converts them
+// for GetSampleInfo.
+struct MockSample {
+    file_name: String,
+    frames: Vec<[f32; 2]>,
+    pcm16: Vec<[i16; 2]>,
+    regions: Vec<Region>,
+}
+
+// The `TFruityPlugHost` part must come first, the C++ wrapper only sees that.
+#[repr(C)]
+struct HostObject {
+    base: FruityPlugHost,
+    state: Mutex<State>,
+}
+
+/// The mock host.
+///
+/// It records calls from every plugin instance created with it.
+pub struct MockHost {
+    object: Box<HostObject>,
+}
+
+impl std::fmt::Debug for MockHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockHost").finish()
+    }
+}
+
+impl Default for MockHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockHost {
+    /// Initializer.
+    pub fn new() -> Self {
+        Self {
+            object: Box::new(HostObject {
+                base: FruityPlugHost {
+                    vtable: &HOST_VTBL,
+                    host_version: HOST_VERSION,
+                    flags: 0,
+                    app_handle: 0,
+                    wave_tables: [ptr::null_mut(); 10],
+                    temp_buffers: [ptr::null_mut(); 4],
+                    reserved: [0; 30],
+                },
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    /// Get a [`Host`](../host/struct.Host.html) talking to this mock.
+    pub fn host(&self) -> Host {
+        Host::new(self.as_ptr())
+    }
+
+    /// Create an effect of type `E` and wrap it the same way
+    /// [`create_plugin`](../macro.create_plugin.html) does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the plugin's constructor panics.
+    pub fn create_effect<E: Effect>(&self) -> PluginInstance<'_> {
+        self.wrap(PluginAdapter::effect::<E>)
+    }
+
+    /// Create a generator of type `G` and wrap it the same way
+    /// [`create_plugin`](../macro.create_plugin.html) does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the plugin's constructor panics.
+    pub fn create_generator<G: Generator>(&self) -> PluginInstance<'_> {
+        self.wrap(PluginAdapter::generator::<G>)
+    }
+
+    fn wrap(&self, new: fn(*mut c_void, intptr_t) -> Option<PluginAdapter>) -> PluginInstance<'_> {
+        let tag = self.next_tag();
+        let adapter = new(self.as_ptr(), tag.0).expect("plugin constructor panicked");
+        let adapter = Box::into_raw(Box::new(adapter));
+        let raw = unsafe { create_plug_instance_c(self.as_ptr(), tag.0, adapter as *mut c_void) };
+
+        PluginInstance {
+            raw: raw as *mut FruityPlug,
+            tag,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Create an instance of a plugin loaded from a library, using its exported
+    /// [`CreatePlugInstance`](type.CreatePlugInstance.html).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the plugin's constructor panics.
+    ///
+    /// # Safety
+    ///
+    /// `create` must be `CreatePlugInstance` of a plugin built with this crate, and the library
+    /// must stay loaded until the returned instance is dropped.
+    pub unsafe fn instantiate(&self, create: CreatePlugInstance) -> PluginInstance<'_> {
+        let tag = self.next_tag();
+        let raw = create(self.as_ptr(), tag.0);
+        assert!(!raw.is_null(), "plugin constructor panicked");
+
+        PluginInstance {
+            raw: raw as *mut FruityPlug,
+            tag,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Queue a reply for the next dispatcher message with `id` (see [`id`](id/index.html)).
+    ///
+    /// Replies for the same message are used in the order they were queued. Once there are none
+    /// left, the mock host answers like FL Studio would with nothing set up: empty strings, no
+    /// names, `1.0` tempo multiplier and so on.
+    pub fn queue_reply(&self, id: intptr_t, reply: Reply) {
+        self.state().replies.entry(id).or_default().push_back(reply);
+    }
+
+    /// Calls recorded so far.
+    pub fn calls(&self) -> Vec<HostCall> {
+        self.state().calls.clone()
+    }
+
+    /// Take the calls recorded so far, leaving none.
+    pub fn take_calls(&self) -> Vec<HostCall> {
+        mem::take(&mut self.state().calls)
+    }
+
+    /// Dispatcher messages with `id` recorded so far.
+    pub fn messages(&self, id: intptr_t) -> Vec<HostCall> {
+        self.state()
+            .calls
+            .iter()
+            .filter(|call| matches!(call, HostCall::Message { id: call_id, .. } if *call_id == id))
+            .cloned()
+            .collect()
+    }
+
+    /// Set the input buffer at `index` (starting from 1).
+    pub fn set_input(&self, index: usize, samples: Vec<[f32; 2]>) {
+        let mut state = self.state();
+        if state.inputs.len() < index {
+            state.inputs.resize_with(index, Vec::new);
+        }
+        state.inputs[index - 1] = samples;
+    }
+
+    /// Set the output buffer at `index` (starting from 1) to `len` silent samples.
+    pub fn set_output(&self, index: usize, len: usize) {
+        let mut state = self.state();
+        if state.outputs.len() < index {
+            state.outputs.resize_with(index, Vec::new);
+        }
+        state.outputs[index - 1] = vec![[0.0, 0.0]; len];
+    }
+
+    /// Addresses of the memory the host answered with and still owns: the strings and the menu
+    /// entry. The plugin must not free them.
+    pub fn host_memory(&self) -> Vec<usize> {
+        let state = self.state();
+        let strings = state.strings.iter().map(|text| text.as_ptr() as usize);
+        let wide_strings = state.wide_strings.iter().map(|text| text.as_ptr() as usize);
+        let menu_entry = state.menu_entry.as_deref().map(|entry| {
+            let ptr: *const TParamMenuEntry = entry;
+            ptr as usize
+        });
+        strings.chain(wide_strings).chain(menu_entry).collect()
+    }
+
+    /// Get the contents of the output buffer at `index` (starting from 1).
+    pub fn output(&self, index: usize) -> Option<Vec<[f32; 2]>> {
+        self.state().outputs.get(index.wrapping_sub(1)).cloned()
+    }
+
+    /// Add a sample the plugin can load from `file_name`.
+    ///
+    /// The returned handle can be passed to the plugin with
+    /// [`host::Message::ChanSample`](../host/enum.Message.html#variant.ChanSample).
+    pub fn add_sample(
+        &self,
+        file_name: &str,
+        frames: Vec<[f32; 2]>,
+        regions: Vec<Region>,
+    ) -> sample::Handle {
+        let mut state = self.state();
+        let pcm16 = frames
+            .iter()
+            .map(|frame| frame.map(|value| (value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16))
+            .collect();
+        state.samples.push(MockSample {
+            file_name: file_name.to_string(),
+            frames,
+            pcm16,
+            regions,
+        });
+        sample::Handle(state.samples.len() as intptr_t)
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut c_void {
+        ptr::addr_of!(*self.object) as *mut c_void
+    }
+
+    fn next_tag(&self) -> Tag {
+        let mut state = self.state();
+        state.last_tag += 1;
+        Tag(state.last_tag)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.object
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The signature of `CreatePlugInstance`, which [`create_plugin`](../macro.create_plugin.html)
+/// exports from plugin libraries.
+pub type CreatePlugInstance = unsafe extern "C" fn(host: *mut c_void, tag: intptr_t) -> *mut c_void;
+
+extern "C" {
+    fn create_plug_instance_c(
+        host: *mut c_void,
+        tag: intptr_t,
+        adapter: *mut c_void,
+    ) -> *mut c_void;
+}
+
+/// Plugin info as seen by the host.
+#[derive(Debug, Clone)]
+pub struct InstanceInfo {
+    /// SDK version.
+    pub sdk_version: i32,
+    /// The name of the plugin.
+    pub long_name: String,
+    /// The short name of the plugin.
+    pub short_name: String,
+    /// Raw plugin flags.
+    pub flags: i32,
+    /// The number of parameters.
+    pub num_params: i32,
+    /// Preferred (default) maximum polyphony.
+    pub def_poly: i32,
+    /// The number of internal output controllers.
+    pub num_out_ctrls: i32,
+    /// The number of internal output voices.
+    pub num_out_voices: i32,
+}
+
+/// A plugin created by [`MockHost`](struct.MockHost.html).
+///
+/// Its methods call the plugin the way FL Studio does. The plugin is destroyed on drop.
+#[derive(Debug)]
+pub struct PluginInstance<'a> {
+    raw: *mut FruityPlug,
+    tag: Tag,
+    phantom: PhantomData<&'a MockHost>,
+}
+
+impl PluginInstance<'_> {
+    /// The tag the host identifies this plugin with.
+    pub fn tag(&self) -> Tag {
+        self.tag
+    }
+
+    /// Plugin info.
+    pub fn info(&self) -> InstanceInfo {
+        unsafe {
+            let info = &*(*self.raw).info;
+            InstanceInfo {
+                sdk_version: info.sdk_version,
+                long_name: string_from_ptr(info.long_name),
+                short_name: string_from_ptr(info.short_name),
+                flags: info.flags,
+                num_params: info.num_params,
+                def_poly: info.def_poly,
+                num_out_ctrls: info.num_out_ctrls,
+                num_out_voices: info.num_out_voices,
+            }
+        }
+    }
+
+    /// Send a message to the plugin.
+    pub fn dispatch(&mut self, message: host::Message<'_>) -> intptr_t {
+        match message {
+            host::Message::ChanSampleChanged(samples) => {
+                assert!(
+                    samples.len() >= WAVETABLE_SIZE,
+                    "wavetable must be {} samples long",
+                    WAVETABLE_SIZE
+                );
+                self.dispatch_raw(10, 0, samples.as_ptr() as intptr_t)
+            }
+            host::Message::SetTimeSig(signature) => {
+                let mut info = TTimeSigInfo {
+                    steps_per_bar: signature.steps_per_bar as c_int,
+                    steps_per_beat: signature.steps_per_beat as c_int,
+                    ppq: signature.ppq as c_int,
+                };
+                self.dispatch_raw(14, 0, ptr::addr_of_mut!(info) as intptr_t)
+            }
+            host::Message::LoadFile(path) => {
+                let path = CString::new(path).expect("path must not contain nul bytes");
+                self.dispatch_raw(18, 0, path.as_ptr() as intptr_t)
+            }
+            message => {
+                let (id, index, value) = encode_message(message);
+                self.dispatch_raw(id, index, value)
+            }
+        }
+    }
+
+    /// Send a message to the plugin as is.
+    pub fn dispatch_raw(&mut self, id: intptr_t, index: intptr_t, value: intptr_t) -> intptr_t {
+        unsafe { ((*(*self.raw).vtable).dispatcher)(self.raw, id, index, value) }
+    }
+
+    /// Ask the plugin for a name.
+    pub fn name_of(&mut self, name: GetName) -> String {
+        let message: Option<crate::FlMessage> = name.into();
+        let message = message.expect("unknown name section");
+        let mut buf: Vec<c_char> = vec![0; 4096];
+        unsafe {
+            ((*(*self.raw).vtable).get_name)(
+                self.raw,
+                message.id as c_int,
+                message.index as c_int,
+                message.value as c_int,
+                buf.as_mut_ptr(),
+            );
+            string_from_ptr(buf.as_mut_ptr())
+        }
+    }
+
+    /// Send an event to the plugin.
+    pub fn process_event(&mut self, event: host::Event) {
+        let (id, value, flags) = match event {
+            host::Event::Tempo(tempo, samples_per_tick) => {
+                (0, tempo.to_bits() as c_int, samples_per_tick as c_int)
+            }
+            host::Event::MaxPoly(poly) => (1, poly, 0),
+            host::Event::MidiPan(pan, pan_norm) => (2, pan as c_int, pan_norm as c_int),
+            host::Event::MidiVol(vol, vol_norm) => (3, vol as c_int, vol_norm.to_bits() as c_int),
+            host::Event::MidiPitch(pitch) => (4, pitch, 0),
+            host::Event::Unknown => (-1, 0, 0),
+        };
+        unsafe { ((*(*self.raw).vtable).process_event)(self.raw, id, value, flags) };
+    }
+
+    /// Change or query a parameter of the plugin.
+    pub fn process_param(
+        &mut self,
+        index: usize,
+        value: intptr_t,
+        flags: ProcessParamFlags,
+    ) -> intptr_t {
+        unsafe {
+            ((*(*self.raw).vtable).process_param)(
+                self.raw,
+                index as c_int,
+                value as c_int,
+                flags.bits() as c_int,
+            ) as intptr_t
+        }
+    }
+
+    /// Call [`Plugin::idle`](../plugin/trait.Plugin.html#method.idle).
+    pub fn idle(&mut self) {
+        unsafe { ((*(*self.raw).vtable).idle_public)(self.raw) }
+    }
+
+    /// Call [`Plugin::tick`](../plugin/trait.Plugin.html#method.tick).
+    pub fn tick(&mut self) {
+        unsafe { ((*(*self.raw).vtable).new_tick)(self.raw) }
+    }
+
+    /// Call [`Plugin::midi_tick`](../plugin/trait.Plugin.html#method.midi_tick).
+    pub fn midi_tick(&mut self) {
+        unsafe { ((*(*self.raw).vtable).midi_tick)(self.raw) }
+    }
+
+    /// Render a block as an effect.
+    pub fn eff_render(&mut self, input: &[[f32; 2]], output: &mut [[f32; 2]]) {
+        assert_eq!(
+            input.len(),
+            output.len(),
+            "buffers must have the same length"
+        );
+        unsafe {
+            ((*(*self.raw).vtable).eff_render)(
+                self.raw,
+                input.as_ptr(),
+                output.as_mut_ptr(),
+                output.len() as c_int,
+            )
+        }
+    }
+
+    /// Render a block as a generator.
+    pub fn gen_render(&mut self, output: &mut [[f32; 2]]) {
+        let mut length = output.len() as c_int;
+        unsafe { ((*(*self.raw).vtable).gen_render)(self.raw, output.as_mut_ptr(), &mut length) }
+    }
+
+    /// Send a MIDI message to the plugin.
+    ///
+    /// Returns the message after the plugin has processed it.
+    pub fn midi_in(&mut self, message: c_int) -> c_int {
+        let mut message = message;
+        unsafe { ((*(*self.raw).vtable).midi_in)(self.raw, &mut message) };
+        message
+    }
+
+    /// Send a message the plugin scheduled with
+    /// [`Host::loop_out`](../host/struct.Host.html#method.loop_out) back to it.
+    pub fn loop_in(&mut self, message: intptr_t) {
+        unsafe { ((*(*self.raw).vtable).msg_in)(self.raw, message) }
+    }
+
+    /// Save the plugin's state.
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let mut stream = MemoryStream::new(Vec::new());
+        unsafe {
+            ((*(*self.raw).vtable).save_restore_state)(
+                self.raw,
+                ptr::addr_of_mut!(stream) as *mut Stream,
+                1,
+            )
+        };
+        stream.data
+    }
+
+    /// Load the plugin's state.
+    pub fn load_state(&mut self, data: &[u8]) {
+        let mut stream = MemoryStream::new(data.to_vec());
+        unsafe {
+            ((*(*self.raw).vtable).save_restore_state)(
+                self.raw,
+                ptr::addr_of_mut!(stream) as *mut Stream,
+                0,
+            )
+        };
+    }
+
+    /// Trigger a voice. Returns its handle.
+    pub fn trigger_voice(&mut self, params: voice::Params, tag: intptr_t) -> intptr_t {
+        let mut params = params;
+        unsafe { ((*(*self.raw).vtable).trigger_voice)(self.raw, &mut params, tag) }
+    }
+
+    /// Release a voice.
+    pub fn release_voice(&mut self, handle: intptr_t) {
+        unsafe { ((*(*self.raw).vtable).voice_release)(self.raw, handle) }
+    }
+
+    /// Kill a voice.
+    pub fn kill_voice(&mut self, handle: intptr_t) {
+        unsafe { ((*(*self.raw).vtable).voice_kill)(self.raw, handle) }
+    }
+
+    /// Send an event to a voice.
+    pub fn voice_event(&mut self, handle: intptr_t, event: voice::Event) -> intptr_t {
+        let message: Option<crate::FlMessage> = event.into();
+        let message = message.expect("unknown voice event");
+        unsafe {
+            ((*(*self.raw).vtable).voice_process_event)(
+                self.raw,
+                handle,
+                message.id as c_int,
+                message.index as c_int,
+                message.value as c_int,
+            ) as intptr_t
+        }
+    }
+
+    /// Send an event to an output voice.
+    pub fn out_voice_event(&mut self, handle: intptr_t, event: voice::Event) -> intptr_t {
+        let message: Option<crate::FlMessage> = event.into();
+        let message = message.expect("unknown voice event");
+        unsafe {
+            ((*(*self.raw).vtable).output_voice_process_event)(
+                self.raw,
+                handle,
+                message.id as c_int,
+                message.index as c_int,
+                message.value as c_int,
+            ) as intptr_t
+        }
+    }
+
+    /// Kill an output voice.
+    pub fn kill_out_voice(&mut self, handle: intptr_t) {
+        unsafe { ((*(*self.raw).vtable).output_voice_kill)(self.raw, handle) }
+    }
+}
+
+impl Drop for PluginInstance<'_> {
+    fn drop(&mut self) {
+        unsafe { ((*(*self.raw).vtable).destroy_object)(self.raw) }
+    }
+}
+
+fn encode_message(message: host::Message<'_>) -> (intptr_t, intptr_t, intptr_t) {
+    use host::Message;
+
+    match message {
+        // the plugin treats 1 as "hide"
+        Message::ShowEditor(handle) => (0, 0, handle.map(|h| h as intptr_t).unwrap_or(1)),
+        Message::ProcessMode(flags) => (1, 0, flags.bits()),
+        Message::Flush => (2, 0, 0),
+        Message::SetBlockSize(size) => (3, 0, size as intptr_t),
+        Message::SetSampleRate(rate) => (4, 0, rate as intptr_t),
+        Message::WindowMinMax(min, max) => (5, min as intptr_t, max as intptr_t),
+        Message::KillVoice => (6, 0, 0),
+        Message::UseVoiceLevels(index) => (7, index as intptr_t, 0),
+        Message::SetPreset(index) => (9, index as intptr_t, 0),
+        Message::SetEnabled(enabled) => (11, 0, enabled as intptr_t),
+        Message::SetPlaying(playing) => (12, 0, playing as intptr_t),
+        Message::SongPosChanged => (13, 0, 0),
+        Message::CollectFile(index) => (15, index as intptr_t, 0),
+        Message::SetInternalParam => (16, 0, 0),
+        Message::SetNumSends(num) => (17, 0, num as intptr_t),
+        Message::SetFitTime(time) => (19, 0, time.to_bits() as i32 as intptr_t),
+        Message::SetSamplesPerTick(samples) => (20, 0, samples.to_bits() as i32 as intptr_t),
+        Message::SetIdleTime(time) => (21, 0, time as intptr_t),
+        Message::SetFocus(focus) => (22, 0, focus as intptr_t),
+        Message::Transport(transport) => {
+            let (index, value) = encode_transport(transport);
+            (23, index, value)
+        }
+        Message::MidiIn(message) => (24, 0, c_int::from(message) as intptr_t),
+        Message::RoutingChanged => (25, 0, 0),
+        Message::GetParamInfo(index) => (26, index as intptr_t, 0),
+        Message::ProjLoaded => (27, 0, 0),
+        Message::WrapperLoadState => (28, 0, 0),
+        Message::ShowSettings(show) => (29, 0, show as intptr_t),
+        Message::SetIoLatency(index, value) => (30, index as intptr_t, value as intptr_t),
+        Message::PreferredNumIo(num) => (32, num as intptr_t, 0),
+        // handled by the caller since they carry pointers
+        Message::ChanSample(handle) => (10, handle.0, 0),
+        Message::ChanSampleChanged(_) | Message::SetTimeSig(_) | Message::LoadFile(_) => {
+            unreachable!()
+        }
+        Message::Unknown => (-1, 0, 0),
+    }
+}
+
+fn encode_transport(transport: Transport) -> (intptr_t, intptr_t) {
+    match transport {
+        Transport::Jog(jog) => (0, jog.0 as intptr_t),
+        Transport::Jog2(jog) => (1, jog.0 as intptr_t),
+        Transport::Strip(jog) => (2, jog.0 as intptr_t),
+        Transport::StripJog(jog) => (3, jog.0 as intptr_t),
+        Transport::StripHold(jog) => (4, jog.0 as intptr_t),
+        Transport::Previous(button) => (5, button.0 as intptr_t),
+        Transport::Next(button) => (6, button.0 as intptr_t),
+        Transport::PreviousNext(jog) => (7, jog.0 as intptr_t),
+        Transport::MoveJog(jog) => (8, jog.0 as intptr_t),
+        Transport::Play(button) => (10, button.0 as intptr_t),
+        Transport::Stop(button) => (11, button.0 as intptr_t),
+        Transport::Record(button) => (12, button.0 as intptr_t),
+        Transport::Rewind(hold) => (13, hold.0 as intptr_t),
+        Transport::FastForward(hold) => (14, hold.0 as intptr_t),
+        Transport::Loop(button) => (15, button.0 as intptr_t),
+        Transport::Mute(button) => (16, button.0 as intptr_t),
+        Transport::Mode(button) => (17, button.0 as intptr_t),
+        Transport::Undo(button) => (20, button.0 as intptr_t),
+        Transport::UndoUp(button) => (21, button.0 as intptr_t),
+        Transport::UndoJog(jog) => (22, jog.0 as intptr_t),
+        Transport::Punch(hold) => (30, hold.0 as intptr_t),
+        Transport::PunchIn(button) => (31, button.0 as intptr_t),
+        Transport::PunchOut(button) => (32, button.0 as intptr_t),
+        Transport::AddMarker(button) => (33, button.0 as intptr_t),
+        Transport::AddAltMarker(button) => (34, button.0 as intptr_t),
+        Transport::MarkerJumpJog(jog) => (35, jog.0 as intptr_t),
+        Transport::MarkerSelJog(jog) => (36, jog.0 as intptr_t),
+        Transport::Up(button) => (40, button.0 as intptr_t),
+        Transport::Down(button) => (41, button.0 as intptr_t),
+        Transport::Left(button) => (42, button.0 as intptr_t),
+        Transport::Right(button) => (43, button.0 as intptr_t),
+        Transport::HZoomJog(jog) => (44, jog.0 as intptr_t),
+        Transport::VZoomJog(jog) => (45, jog.0 as intptr_t),
+        Transport::Snap(button) => (48, button.0 as intptr_t),
+        Transport::SnapMode(jog) => (49, jog.0 as intptr_t),
+        Transport::Cut(button) => (50, button.0 as intptr_t),
+        Transport::Copy(button) => (51, button.0 as intptr_t),
+        Transport::Paste(button) => (52, button.0 as intptr_t),
+        Transport::Insert(button) => (53, button.0 as intptr_t),
+        Transport::Delete(button) => (54, button.0 as intptr_t),
+        Transport::NextWindow(button) => (58, button.0 as intptr_t),
+        Transport::WindowJog(jog) => (59, jog.0 as intptr_t),
+        Transport::F1(button) => (60, button.0 as intptr_t),
+        Transport::F2(button) => (61, button.0 as intptr_t),
+        Transport::F3(button) => (62, button.0 as intptr_t),
+        Transport::F4(button) => (63, button.0 as intptr_t),
+        Transport::F5(button) => (64, button.0 as intptr_t),
+        Transport::F6(button) => (65, button.0 as intptr_t),
+        Transport::F7(button) => (66, button.0 as intptr_t),
+        Transport::F8(button) => (67, button.0 as intptr_t),
+        Transport::F9(button) => (68, button.0 as intptr_t),
+        Transport::F10(button) => (69, button.0 as intptr_t),
+        Transport::Enter(button) => (80, button.0 as intptr_t),
+        Transport::Escape(button) => (81, button.0 as intptr_t),
+        Transport::Yes(button) => (82, button.0 as intptr_t),
+        Transport::No(button) => (83, button.0 as intptr_t),
+        Transport::Menu(button) => (90, button.0 as intptr_t),
+        Transport::ItemMenu(button) => (91, button.0 as intptr_t),
+        Transport::Save(button) => (92, button.0 as intptr_t),
+        Transport::SaveNew(button) => (93, button.0 as intptr_t),
+        Transport::Unknown => (-1, 0),
+    }
+}
+
+unsafe fn string_from_ptr(value: *const c_char) -> String {
+    if value.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(value).to_string_lossy().to_string()
+}
+
+unsafe fn decode_payload(id: intptr_t, index: intptr_t, value: intptr_t) -> Payload {
+    match id {
+        10 | 14 | 22 | 28 | 32 | 35 | 42 | 43 | 44 | 45 | 49 => {
+            Payload::Text(string_from_ptr(value as *const c_char))
+        }
+        19 => Payload::Text(string_from_ptr(index as *const c_char)),
+        17 => Payload::Notes(decode_notes(value)),
+        41 => {
+            let data = value as *const u8;
+            let len = (data as *const i32).read_unaligned() as usize;
+            Payload::SysEx(slice::from_raw_parts(data.add(size_of::<i32>()), len).to_vec())
+        }
+        _ => Payload::None,
+    }
+}
+
+// TNotesParams is freed by the plugin once the call returns, so the notes are copied
+unsafe fn decode_notes(value: intptr_t) -> Notes {
+    let header = &*(value as *const ffi::NotesParamsHeader);
+    let first = (value as *const u8).add(size_of::<ffi::NotesParamsHeader>()) as *const Note;
+    let notes = (0..header.count.max(0) as usize)
+        .map(|i| first.add(i).read_unaligned())
+        .collect();
+    Notes {
+        target: if header.target == 0 {
+            NotesTarget::StepSequencer
+        } else {
+            NotesTarget::PianoRoll
+        },
+        notes,
+        flags: NotesFlags::from_bits_truncate(header.flags as isize),
+        pattern: if header.pat_num < 0 {
+            None
+        } else {
+            Some(header.pat_num as u32)
+        },
+        channel: if header.chan_num < 0 {
+            None
+        } else {
+            Some(header.chan_num as u32)
+        },
+    }
+}
+
+impl State {
+    unsafe fn reply(
+        &mut self,
+        id: intptr_t,
+        index: intptr_t,
+        value: intptr_t,
+        reply: Option<Reply>,
+    ) -> intptr_t {
+        match (id, reply) {
+            (_, Some(Reply::Value(result))) => result,
+            (61, Some(Reply::String(text))) => self.keep_wide_string(&text),
+            (_, Some(Reply::String(text))) => self.keep_string(text),
+            (36..=38, Some(Reply::Time(t, t2))) => {
+                *(value as *mut Time) = Time(t, t2);
+                0
+            }
+            (16, Some(Reply::SongTime(time))) => {
+                *(index as *mut SongTime) = time;
+                0
+            }
+            (51 | 52, Some(Reply::NameColor(name, vis_name, color, name_index))) => {
+                let target = &mut *(value as *mut TNameColor);
+                cstr::write_to(&name, &mut target.name);
+                cstr::write_to(&vis_name, &mut target.vis_name);
+                target.color = color as c_int;
+                target.index = name_index as c_int;
+                1
+            }
+            (18, Some(Reply::ParamMenuEntry(name, flags))) => {
+                // the entry and its name stay with the host
+                let name = self.keep_string(name) as *mut c_char;
+                let mut entry = Box::new(TParamMenuEntry {
+                    name,
+                    flags: flags.bits(),
+                });
+                let result = ptr::addr_of_mut!(*entry) as intptr_t;
+                self.menu_entry = Some(entry);
+                result
+            }
+            (_, Some(reply)) => {
+                error!("reply {:?} doesn't fit message {}", reply, id);
+                0
+            }
+            (14 | 28 | 29 | 47, None) => self.keep_string(String::new()),
+            (61, None) => self.keep_wide_string(""),
+            (19, None) => 1,
+            (39, None) => 1.0_f32.to_bits() as intptr_t,
+            (50, None) if index == 0 => self.inputs.len() as intptr_t,
+            (50, None) => self.outputs.len() as intptr_t,
+            (_, None) => 0,
+        }
+    }
+
+    fn keep_string(&mut self, text: String) -> intptr_t {
+        let text = CString::new(text).unwrap_or_default();
+        let result = text.as_ptr() as intptr_t;
+        self.strings.push(text);
+        result
+    }
+
+    fn keep_wide_string(&mut self, text: &str) -> intptr_t {
+        let text: Vec<u16> = text.encode_utf16().chain([0]).collect();
+        let result = text.as_ptr() as intptr_t;
+        self.wide_strings.push(text);
+        result
+    }
+}
+
+unsafe fn state<'a>(this: *mut FruityPlugHost) -> MutexGuard<'a, State> {
+    (*(this as *mut HostObject))
+        .state
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+unsafe fn record(this: *mut FruityPlugHost, call: HostCall) {
+    state(this).calls.push(call);
+}
+
+unsafe fn decode_midi_out(msg: intptr_t) -> MidiMessage {
+    let out = &*(msg as *const ffi::MidiOutMsg);
+    let result = MidiMessage {
+        status: out.status,
+        data1: out.data1,
+        data2: out.data2,
+        port: out.port,
+    };
+    ffi::free(msg as *mut c_void);
+    result
+}
+
+unsafe extern "system" fn host_dispatcher(
+    this: *mut FruityPlugHost,
+    _sender: intptr_t,
+    id: intptr_t,
+    index: intptr_t,
+    value: intptr_t,
+) -> intptr_t {
+    let payload = decode_payload(id, index, value);
+    let mut state = state(this);
+    state.calls.push(HostCall::Message {
+        id,
+        index,
+        value,
+        payload,
+    });
+    let reply = state.replies.get_mut(&id).and_then(VecDeque::pop_front);
+    state.reply(id, index, value, reply)
+}
+
+unsafe extern "system" fn host_on_param_changed(
+    this: *mut FruityPlugHost,
+    _sender: intptr_t,
+    index: c_int,
+    value: c_int,
+) {
+    record(this, HostCall::Parameter { index, value });
+}
+
+unsafe extern "system" fn host_on_hint(
+    this: *mut FruityPlugHost,
+    _sender: intptr_t,
+    text: *mut c_char,
+) {
+    record(this, HostCall::Hint(string_from_ptr(text)));
+}
+
+unsafe extern "system" fn host_compute_lr_vol_old(
+    _this: *mut FruityPlugHost,
+    left: *mut f32,
+    right: *mut f32,
+    pan: c_int,
+    volume: f32,
+) {
+    host_compute_lr_vol(_this, left, right, pan as f32 / 64.0, volume);
+}
+
+unsafe extern "system" fn host_voice_release(this: *mut FruityPlugHost, tag: intptr_t) {
+    record(this, HostCall::VoiceRelease(tag));
+}
+
+unsafe extern "system" fn host_voice_kill(this: *mut FruityPlugHost, tag: intptr_t, _kill: c_int) {
+    record(this, HostCall::VoiceKill(tag));
+}
+
+unsafe extern "system" fn host_voice_process_event(
+    this: *mut FruityPlugHost,
+    tag: intptr_t,
+    id: intptr_t,
+    value: intptr_t,
+    flags: intptr_t,
+) -> c_int {
+    record(
+        this,
+        HostCall::VoiceEvent {
+            tag,
+            id,
+            value,
+            flags,
+        },
+    );
+    0
+}
+
+unsafe extern "system" fn host_lock_mix(this: *mut FruityPlugHost) {
+    record(this, HostCall::LockMix);
+}
+
+unsafe extern "system" fn host_unlock_mix(this: *mut FruityPlugHost) {
+    record(this, HostCall::UnlockMix);
+}
+
+unsafe extern "system" fn host_midi_out_delayed(
+    this: *mut FruityPlugHost,
+    _sender: intptr_t,
+    msg: intptr_t,
+) {
+    record(this, HostCall::MidiOutDelayed(decode_midi_out(msg)));
+}
+
+unsafe extern "system" fn host_midi_out(
+    this: *mut FruityPlugHost,
+    _sender: intptr_t,
+    msg: intptr_t,
+) {
+    record(this, HostCall::MidiOut(decode_midi_out(msg)));
+}
+
+unsafe extern "system" fn host_add_wave(
+    _this: *mut FruityPlugHost,
+    _source: *mut c_void,
+    _dest: *mut c_void,
+    _length: c_int,
+    _left: f32,
+    _right: f32,
+    _last_left: *mut f32,
+    _last_right: *mut f32,
+) {
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+// TSampleInfo.Format
+const SAMPLE_FORMAT_16I: c_int = 0;
+const SAMPLE_FORMAT_32F: c_int = 1;
+
+// The user always cancels the dialog. Like FL Studio, it only loads 16-bit 44 kHz stereo.
+unsafe extern "system" fn host_load_sample(
+    this: *mut FruityPlugHost,
+    handle: *mut intptr_t,
+    file_name: *mut c_char,
+    format: *mut c_void,
+    flags: c_int,
+) -> bool {
+    let mut state = state(this);
+    let flags = SampleLoadFlags::from_bits_truncate(flags as isize);
+    let name = string_from_ptr(file_name);
+    state.calls.push(HostCall::LoadSample(name.clone()));
+    if flags.contains(SampleLoadFlags::SHOW_DIALOG) {
+        return false;
+    }
+    let format = (format as *const ffi::WaveFormatEx).read_unaligned();
+    let format = (
+        format.format_tag,
+        format.channels,
+        format.samples_per_sec,
+        format.bits_per_sample,
+        format.block_align,
+    );
+    if format != (WAVE_FORMAT_PCM, 2, 44100, 16, 4) {
+        error!("unsupported sample format {:?}", format);
+        return false;
+    }
+
+    let index = if *handle != 0 && flags.contains(SampleLoadFlags::GET_NAME) {
+        (*handle as usize).wrapping_sub(1)
+    } else {
+        match state
+            .samples
+            .iter()
+            .position(|sample| sample.file_name == name)
+        {
+            Some(index) => index,
+            None => return false,
+        }
+    };
+    match state.samples.get(index) {
+        Some(sample) => {
+            cstr::write_to(
+                &sample.file_name,
+                &mut *(file_name as *mut [u8; cstr::BUF_LEN]),
+            );
+            *handle = index as intptr_t + 1;
+            true
+        }
+        None => false,
+    }
+}
+
+unsafe extern "system" fn host_get_sample_data(
+    this: *mut FruityPlugHost,
+    handle: intptr_t,
+    length: *mut c_int,
+) -> *mut c_void {
+    let mut state = state(this);
+    match state.samples.get_mut((handle as usize).wrapping_sub(1)) {
+        Some(sample) => {
+            *length = sample.pcm16.len() as c_int;
+            sample.pcm16.as_mut_ptr() as *mut c_void
+        }
+        None => {
+            *length = 0;
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe extern "system" fn host_close_sample(this: *mut FruityPlugHost, handle: intptr_t) {
+    record(this, HostCall::CloseSample(handle));
+}
+
+unsafe extern "system" fn host_get_song_mixing_time(_this: *mut FruityPlugHost) -> c_int {
+    0
+}
+
+unsafe extern "system" fn host_get_song_time(_this: *mut FruityPlugHost) -> f64 {
+    0.0
+}
+
+unsafe extern "system" fn host_on_controller_changed(
+    this: *mut FruityPlugHost,
+    _sender: intptr_t,
+    index: intptr_t,
+    value: intptr_t,
+) {
+    record(this, HostCall::Controller { index, value });
+}
+
+unsafe extern "system" fn host_get_send_buffer(
+    _this: *mut FruityPlugHost,
+    _num: intptr_t,
+) -> *mut c_void {
+    ptr::null_mut()
+}
+
+unsafe extern "system" fn host_plug_msg_delayed(
+    this: *mut FruityPlugHost,
+    _sender: intptr_t,
+    msg: intptr_t,
+) {
+    record(this, HostCall::LoopOut(msg));
+}
+
+unsafe extern "system" fn host_plug_msg_kill(
+    this: *mut FruityPlugHost,
+    _sender: intptr_t,
+    msg: intptr_t,
+) {
+    record(this, HostCall::LoopKill(msg));
+}
+
+unsafe extern "system" fn host_get_sample_info(
+    this: *mut FruityPlugHost,
+    handle: intptr_t,
+    info: *mut ffi::SampleInfo,
+) {
+    let mut state = state(this);
+    if let Some(sample) = state.samples.get_mut((handle as usize).wrapping_sub(1)) {
+        // only stereo is kept
+        (*info).data = match ((*info).num_chans, (*info).format) {
+            (2, SAMPLE_FORMAT_32F) => sample.frames.as_mut_ptr() as *mut c_void,
+            (2, SAMPLE_FORMAT_16I) => sample.pcm16.as_mut_ptr() as *mut c_void,
+            _ => ptr::null_mut(),
+        };
+        (*info).length = sample.frames.len() as c_int;
+        (*info).solid_length = sample.frames.len() as c_int;
+        (*info).loop_start = -1;
+        (*info).loop_end = -1;
+        (*info).smp_rate_conv = 1.0;
+        (*info).num_regions = sample.regions.len() as c_int;
+    }
+}
+
+unsafe extern "system" fn host_dist_wave(
+    _this: *mut FruityPlugHost,
+    _dist_type: c_int,
+    _dist_thres: c_int,
+    _source: *mut c_void,
+    _length: c_int,
+    _dry: f32,
+    _wet: f32,
+    _mul: f32,
+) {
+}
+
+unsafe extern "system" fn host_get_mix_buffer(
+    _this: *mut FruityPlugHost,
+    _num: c_int,
+) -> *mut c_void {
+    ptr::null_mut()
+}
+
+unsafe extern "system" fn host_get_ins_buffer(
+    _this: *mut FruityPlugHost,
+    _sender: intptr_t,
+    _offset: c_int,
+) -> *mut c_void {
+    ptr::null_mut()
+}
+
+unsafe extern "system" fn host_prompt_edit(
+    this: *mut FruityPlugHost,
+    _x: c_int,
+    _y: c_int,
+    caption: *mut c_char,
+    value: *mut c_char,
+    _color: *mut c_int,
+) -> bool {
+    record(
+        this,
+        HostCall::Prompt {
+            caption: string_from_ptr(caption),
+            value: string_from_ptr(value),
+        },
+    );
+    false
+}
+
+unsafe extern "system" fn host_suspend_output(this: *mut FruityPlugHost) {
+    record(this, HostCall::SuspendOutput);
+}
+
+unsafe extern "system" fn host_resume_output(this: *mut FruityPlugHost) {
+    record(this, HostCall::ResumeOutput);
+}
+
+unsafe extern "system" fn host_get_sample_region(
+    this: *mut FruityPlugHost,
+    handle: intptr_t,
+    region_num: c_int,
+    region: *mut ffi::SampleRegion,
+) {
+    let state = state(this);
+    let found = state
+        .samples
+        .get((handle as usize).wrapping_sub(1))
+        .and_then(|sample| sample.regions.get(region_num as usize));
+    if let Some(found) = found {
+        let region = &mut *region;
+        region.sample_start = found.start as c_int;
+        region.sample_end = found.end as c_int;
+        cstr::write_to(&found.name, &mut region.name);
+        cstr::write_to(&found.info, &mut region.info);
+        region.time = found.time.unwrap_or(-1.0);
+        region.key_num = found.key.map_or(-1, c_int::from);
+    }
+}
+
+unsafe extern "system" fn host_compute_lr_vol(
+    _this: *mut FruityPlugHost,
+    left: *mut f32,
+    right: *mut f32,
+    pan: f32,
+    volume: f32,
+) {
+    *left = volume * (1.0 - pan.max(0.0));
+    *right = volume * (1.0 + pan.min(0.0));
+}
+
+unsafe extern "system" fn host_lock_plugin(this: *mut FruityPlugHost, _sender: intptr_t) {
+    record(this, HostCall::LockPlugin);
+}
+
+unsafe extern "system" fn host_unlock_plugin(this: *mut FruityPlugHost, _sender: intptr_t) {
+    record(this, HostCall::UnlockPlugin);
+}
+
+unsafe extern "system" fn host_noop(_this: *mut FruityPlugHost) {}
+
+unsafe extern "system" fn host_get_in_buffer(
+    this: *mut FruityPlugHost,
+    _sender: intptr_t,
+    index: intptr_t,
+    buffer: *mut ffi::IoBuffer,
+) {
+    let mut state = state(this);
+    if let Some(input) = state.inputs.get_mut((index as usize).wrapping_sub(1)) {
+        (*buffer).buffer = input.as_mut_ptr() as *mut c_void;
+        (*buffer).flags = if input.iter().any(|s| s[0] != 0.0 || s[1] != 0.0) {
+            1
+        } else {
+            0
+        };
+    }
+}
+
+unsafe extern "system" fn host_get_out_buffer(
+    this: *mut FruityPlugHost,
+    _sender: intptr_t,
+    index: intptr_t,
+    buffer: *mut ffi::IoBuffer,
+) {
+    let mut state = state(this);
+    // the plugin passes IO_Lock (0) or IO_Unlock (1)
+    state.calls.push(if (*buffer).flags == 1 {
+        HostCall::UnlockOutput(index)
+    } else {
+        HostCall::LockOutput(index)
+    });
+    if let Some(output) = state.outputs.get_mut((index as usize).wrapping_sub(1)) {
+        (*buffer).buffer = output.as_mut_ptr() as *mut c_void;
+    }
+}
+
+unsafe extern "system" fn host_trigger_output_voice(
+    this: *mut FruityPlugHost,
+    params: *mut voice::Params,
+    index: intptr_t,
+    tag: intptr_t,
+) -> intptr_t {
+    let mut state = state(this);
+    state.last_out_voice += 1;
+    let handle = state.last_out_voice;
+    state.calls.push(HostCall::OutVoiceTrigger {
+        params: (*params).clone(),
+        index,
+        tag,
+        handle,
+    });
+    handle
+}
+
+unsafe extern "system" fn host_output_voice_release(this: *mut FruityPlugHost, handle: intptr_t) {
+    record(this, HostCall::OutVoiceRelease(handle));
+}
+
+unsafe extern "system" fn host_output_voice_kill(this: *mut FruityPlugHost, handle: intptr_t) {
+    record(this, HostCall::OutVoiceKill(handle));
+}
+
+unsafe extern "system" fn host_output_voice_process_event(
+    this: *mut FruityPlugHost,
+    handle: intptr_t,
+    id: intptr_t,
+    value: intptr_t,
+    flags: intptr_t,
+) -> c_int {
+    record(
+        this,
+        HostCall::OutVoiceEvent {
+            handle,
+            id,
+            value,
+            flags,
+        },
+    );
+    0
+}
+
+static HOST_VTBL: FruityPlugHostVtbl = FruityPlugHostVtbl {
+    dispatcher: host_dispatcher,
+    on_param_changed: host_on_param_changed,
+    on_hint: host_on_hint,
+    compute_lr_vol_old: host_compute_lr_vol_old,
+    voice_release: host_voice_release,
+    voice_kill: host_voice_kill,
+    voice_process_event: host_voice_process_event,
+    lock_mix: host_lock_mix,
+    unlock_mix: host_unlock_mix,
+    midi_out_delayed: host_midi_out_delayed,
+    midi_out: host_midi_out,
+    add_wave_32fm_32fs_ramp: host_add_wave,
+    add_wave_32fs_32fs_ramp: host_add_wave,
+    load_sample: host_load_sample,
+    get_sample_data: host_get_sample_data,
+    close_sample: host_close_sample,
+    get_song_mixing_time: host_get_song_mixing_time,
+    get_song_mixing_time_a: host_get_song_time,
+    get_song_playing_time: host_get_song_time,
+    on_controller_changed: host_on_controller_changed,
+    get_send_buffer: host_get_send_buffer,
+    plug_msg_delayed: host_plug_msg_delayed,
+    plug_msg_kill: host_plug_msg_kill,
+    get_sample_info: host_get_sample_info,
+    dist_wave_32fm: host_dist_wave,
+    get_mix_buffer: host_get_mix_buffer,
+    get_ins_buffer: host_get_ins_buffer,
+    prompt_edit: host_prompt_edit,
+    suspend_output: host_suspend_output,
+    resume_output: host_resume_output,
+    get_sample_region: host_get_sample_region,
+    compute_lr_vol: host_compute_lr_vol,
+    lock_plugin: host_lock_plugin,
+    unlock_plugin: host_unlock_plugin,
+    lock_mix_shared_old: host_noop,
+    unlock_mix_shared_old: host_noop,
+    get_in_buffer: host_get_in_buffer,
+    get_out_buffer: host_get_out_buffer,
+    trigger_output_voice: host_trigger_output_voice,
+    output_voice_release: host_output_voice_release,
+    output_voice_kill: host_output_voice_kill,
+    output_voice_process_event: host_output_voice_process_event,
+};
+
+// In-memory IStream for save_state/load_state.
+#[repr(C)]
+struct MemoryStream {
+    base: Stream,
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl MemoryStream {
+    fn new(data: Vec<u8>) -> Self {
+        Self {
+            base: Stream {
+                vtable: &STREAM_VTBL,
+            },
+            data,
+            position: 0,
+        }
+    }
+}
+
+unsafe extern "system" fn stream_query_interface(
+    _this: *mut Stream,
+    _iid: *const c_void,
+    _object: *mut *mut c_void,
+) -> c_long {
+    ffi::E_NOTIMPL
+}
+
+unsafe extern "system" fn stream_ref(_this: *mut Stream) -> c_ulong {
+    1
+}
+
+unsafe extern "system" fn stream_read(
+    this: *mut Stream,
+    data: *mut c_void,
+    size: c_ulong,
+    read: *mut c_ulong,
+) -> c_long {
+    let stream = &mut *(this as *mut MemoryStream);
+    let available = &stream.data[stream.position.min(stream.data.len())..];
+    let len = available.len().min(size as usize);
+    ptr::copy_nonoverlapping(available.as_ptr(), data as *mut u8, len);
+    stream.position += len;
+    if !read.is_null() {
+        *read = len as c_ulong;
+    }
+
+    if len < size as usize {
+        ffi::S_FALSE
+    } else {
+        ffi::S_OK
+    }
+}
+
+unsafe extern "system" fn stream_write(
+    this: *mut Stream,
+    data: *const c_void,
+    size: c_ulong,
+    written: *mut c_ulong,
+) -> c_long {
+    let stream = &mut *(this as *mut MemoryStream);
+    let data = slice::from_raw_parts(data as *const u8, size as usize);
+    let end = stream.position + data.len();
+    if stream.data.len() < end {
+        stream.data.resize(end, 0);
+    }
+    stream.data[stream.position..end].copy_from_slice(data);
+    stream.position = end;
+    if !written.is_null() {
+        *written = size;
+    }
+    ffi::S_OK
+}
+
+unsafe extern "system" fn stream_seek(
+    _this: *mut Stream,
+    _offset: i64,
+    _origin: c_int,
+    _position: *mut u64,
+) -> c_long {
+    ffi::E_NOTIMPL
+}
+
+unsafe extern "system" fn stream_set_size(_this: *mut Stream, _size: u64) -> c_long {
+    ffi::E_NOTIMPL
+}
+
+unsafe extern "system" fn stream_copy_to(
+    _this: *mut Stream,
+    _target: *mut Stream,
+    _size: u64,
+    _read: *mut u64,
+    _written: *mut u64,
+) -> c_long {
+    ffi::E_NOTIMPL
+}
+
+unsafe extern "system" fn stream_commit(_this: *mut Stream, _flags: c_int) -> c_long {
+    ffi::E_NOTIMPL
+}
+
+unsafe extern "system" fn stream_revert(_this: *mut Stream) -> c_long {
+    ffi::E_NOTIMPL
+}
+
+unsafe extern "system" fn stream_region(
+    _this: *mut Stream,
+    _offset: u64,
+    _size: u64,
+    _lock_type: c_int,
+) -> c_long {
+    ffi::E_NOTIMPL
+}
+
+unsafe extern "system" fn stream_stat(
+    _this: *mut Stream,
+    _stat: *mut c_void,
+    _flags: c_int,
+) -> c_long {
+    ffi::E_NOTIMPL
+}
+
+unsafe extern "system" fn stream_clone(_this: *mut Stream, _clone: *mut *mut Stream) -> c_long {
+    ffi::E_NOTIMPL
+}
+
+static STREAM_VTBL: StreamVtbl = StreamVtbl {
+    query_interface: stream_query_interface,
+    add_ref: stream_ref,
+    release: stream_ref,
+    read: stream_read,
+    write: stream_write,
+    seek: stream_seek,
+    set_size: stream_set_size,
+    copy_to: stream_copy_to,
+    commit: stream_commit,
+    revert: stream_revert,
+    lock_region: stream_region,
+    unlock_region: stream_region,
+    stat: stream_stat,
+    clone: stream_clone,
+};
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+    use crate::host::context::{AudioContext, TickContext};
+    use crate::host::Message;
+    use crate::plugin::message::{DebugLogMsg, GetMixingTime};
+    use crate::plugin::{Info, InfoBuilder, Plugin, StateReader, StateWriter};
+    use crate::voice::engine::{Engine, SynthVoice};
+    use crate::voice::{LevelParams, ReceiveVoiceHandler, Voice};
+    use crate::{AsRawPtr, DispatchResult, TimeFormat, ValuePtr};
+
+    #[derive(Debug)]
+    struct Gain {
+        host: Host,
+        tag: Tag,
+        gain: f32,
+    }
+
+    impl Plugin for Gain {
+        fn new(host: Host, tag: Tag) -> Self {
+            Self {
+                host,
+                tag,
+                gain: 1.0,
+            }
+        }
+
+        fn info(&self) -> Info {
+            InfoBuilder::new("Gain", "Gain", 1).build()
+        }
+
+        fn save_state(&mut self, mut writer: StateWriter) {
+            writer.write_all(&self.gain.to_le_bytes()).unwrap();
+        }
+
+        fn load_state(&mut self, mut reader: StateReader) {
+            let mut buf = [0; 4];
+            reader.read_exact(&mut buf).unwrap();
+            self.gain = f32::from_le_bytes(buf);
+        }
+
+        fn handle_message(&mut self, message: Message<'_>) -> DispatchResult {
+            if let Message::SetEnabled(true) = message {
+                let time = self
+                    .host
+                    .on_message(self.tag, GetMixingTime(TimeFormat::Beats, 0));
+                self.host
+                    .on_message(self.tag, DebugLogMsg(format!("{}", time.0)));
+                self.host.on_hint(self.tag, "enabled".to_string());
+            }
+            DispatchResult::NONE
+        }
+
+        fn name_of(&self, name: GetName) -> String {
+            match name {
+                GetName::Param(0) => "Gain".to_string(),
+                _ => String::new(),
+            }
+        }
+
+        fn handle_param(
+            &mut self,
+            index: usize,
+            value: ValuePtr,
+            _flags: ProcessParamFlags,
+        ) -> DispatchResult {
+            if index == 0 {
+                self.gain = value.get::<f32>();
+                self.host.on_parameter(self.tag, index, value);
+            }
+            DispatchResult::NONE
+        }
+    }
+
+    impl Effect for Gain {
+        fn render(
+            &mut self,
+            _ctx: &mut AudioContext<'_>,
+            input: &[[f32; 2]],
+            output: &mut [[f32; 2]],
+        ) {
+            for (i, o) in input.iter().zip(output.iter_mut()) {
+                o[0] = i[0] * self.gain;
+                o[1] = i[1] * self.gain;
+            }
+        }
+    }
+
+    #[test]
+    fn records_calls_and_replies() {
+        let host = MockHost::new();
+        host.queue_reply(id::GET_MIXING_TIME, Reply::Time(4.5, 0.0));
+        let mut plugin = host.create_effect::<Gain>();
+        assert_eq!("Gain", plugin.info().long_name);
+
+        plugin.dispatch(Message::SetEnabled(true));
+
+        let log = host.messages(id::DEBUG_LOG_MSG);
+        assert!(matches!(
+            &log[..],
+            [HostCall::Message { payload: Payload::Text(text), .. }] if text == "4.5"
+        ));
+        let calls = host.take_calls();
+        assert_eq!(Some(&HostCall::Hint("enabled".to_string())), calls.last());
+        assert!(host.calls().is_empty());
+    }
+
+    #[derive(Debug, Default)]
+    struct Hum;
+
+    impl SynthVoice for Hum {
+        fn start(&mut self, _params: &voice::Params) {}
+
+        fn release(&mut self) {}
+
+        fn render(&mut self, _output: &mut [[f32; 2]]) {}
+
+        fn is_done(&self) -> bool {
+            false
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct Pad;
+
+    crate::test_plugin!(Pad, InfoBuilder::new("Pad", "Pad", 0).build());
+
+    impl Generator for Pad {
+        type Voices = Engine<Hum>;
+
+        const MAX_VOICES: usize = 2;
+
+        fn voices(host: &Host, _tag: Tag) -> Self::Voices {
+            Engine::new(host, 2, Hum::default)
+        }
+
+        fn render(
+            &mut self,
+            _ctx: &mut AudioContext<'_>,
+            _output: &mut [[f32; 2]],
+            _voices: &mut Self::Voices,
+        ) {
+        }
+    }
+
+    #[test]
+    fn voices_over_the_limit_are_ignored() {
+        let host = MockHost::new();
+        let mut plugin = host.create_generator::<Pad>();
+        let levels = LevelParams {
+            pan: 0.0,
+            vol: 1.0,
+            pitch: 6000.0,
+            mod_x: 0.0,
+            mod_y: 0.0,
+        };
+        let params = voice::Params {
+            init_levels: levels.clone(),
+            final_levels: levels,
+        };
+
+        let first = plugin.trigger_voice(params.clone(), 1);
+        assert_ne!(-1, plugin.trigger_voice(params.clone(), 2));
+        assert_eq!(-1, plugin.trigger_voice(params.clone(), 3));
+        plugin.kill_voice(first);
+        assert_ne!(-1, plugin.trigger_voice(params, 4));
+    }
+
+    #[test]
+    fn renders_and_keeps_state() {
+        let host = MockHost::new();
+        let mut plugin = host.create_effect::<Gain>();
+        assert_eq!("Gain", plugin.name_of(GetName::Param(0)));
+
+        plugin.process_param(0, 0.5_f32.as_raw_ptr(), ProcessParamFlags::UPDATE_VALUE);
+        let mut output = [[0.0; 2]; 4];
+        plugin.eff_render(&[[1.0, -1.0]; 4], &mut output);
+        assert_eq!([[0.5, -0.5]; 4], output);
+
+        let state = plugin.save_state();
+        let mut other = host.create_effect::<Gain>();
+        other.load_state(&state);
+        other.eff_render(&[[1.0, 1.0]; 4], &mut output);
+        assert_eq!([[0.5, 0.5]; 4], output);
+    }
+
+    #[derive(Debug, Default)]
+    struct Broken {
+        ticks: usize,
+    }
+
+    crate::test_plugin!(Broken, InfoBuilder::new("Broken", "Broken", 0).build(), {
+        fn tick(&mut self, _ctx: &mut TickContext<'_>) {
+            self.ticks += 1;
+            assert!(self.ticks < 2, "ticked too much");
+        }
+    });
+
+    impl Effect for Broken {
+        fn render(
+            &mut self,
+            _ctx: &mut AudioContext<'_>,
+            _input: &[[f32; 2]],
+            output: &mut [[f32; 2]],
+        ) {
+            output[0] = [1.0, 1.0];
+            panic!("render is broken");
+        }
+    }
+
+    #[test]
+    fn faults_on_panic() {
+        let host = MockHost::new();
+        let mut plugin = host.create_effect::<Broken>();
+        assert_eq!(0, plugin.info().flags & 1);
+
+        let mut output = [[0.5; 2]; 4];
+        plugin.eff_render(&[[1.0, 1.0]; 4], &mut output);
+        assert_eq!([[0.0; 2]; 4], output);
+
+        let log = host.messages(id::DEBUG_LOG_MSG);
+        assert!(matches!(
+            &log[..],
+            [HostCall::Message { payload: Payload::Text(text), .. }]
+                if text.contains("render is broken")
+        ));
+
+        // the faulted plugin is never called again
+        plugin.tick();
+        plugin.tick();
+        assert_eq!("", plugin.name_of(GetName::Param(0)));
+        assert_eq!(1, host.messages(id::DEBUG_LOG_MSG).len());
+    }
+
+    #[derive(Debug, Default)]
+    struct Drone;
+
+    #[derive(Debug, Default)]
+    struct DroneVoices(Vec<DroneVoice>);
+
+    #[derive(Debug)]
+    struct DroneVoice(voice::Tag, f32);
+
+    impl Voice for DroneVoice {
+        fn tag(&self) -> voice::Tag {
+            self.0
+        }
+    }
+
+    impl ReceiveVoiceHandler for DroneVoices {
+        fn trigger(&mut self, params: voice::Params, tag: voice::Tag) {
+            self.0.push(DroneVoice(tag, params.final_levels.vol));
+        }
+
+        fn release(&mut self, _tag: voice::Tag) {}
+
+        fn kill(&mut self, tag: voice::Tag) {
+            self.0.retain(|voice| voice.0 != tag);
+        }
+    }
+
+    // the generator flag follows from the trait
+    crate::test_plugin!(Drone, InfoBuilder::new("Drone", "Drone", 0).build());
+
+    impl Generator for Drone {
+        type Voices = DroneVoices;
+
+        fn voices(_host: &Host, _tag: Tag) -> Self::Voices {
+            DroneVoices::default()
+        }
+
+        fn render(
+            &mut self,
+            _ctx: &mut AudioContext<'_>,
+            output: &mut [[f32; 2]],
+            voices: &mut Self::Voices,
+        ) {
+            let level = voices.0.iter().map(|voice| voice.1).sum();
+            output.iter_mut().for_each(|frame| *frame = [level; 2]);
+        }
+    }
+
+    #[test]
+    fn generator_renders_voices() {
+        let host = MockHost::new();
+        let mut plugin = host.create_generator::<Drone>();
+        assert_eq!(1, plugin.info().flags & 1);
+
+        let levels = LevelParams {
+            pan: 0.0,
+            vol: 0.25,
+            pitch: 6000.0,
+            mod_x: 0.0,
+            mod_y: 0.0,
+        };
+        let params = voice::Params {
+            init_levels: levels.clone(),
+            final_levels: levels,
+        };
+        let first = plugin.trigger_voice(params.clone(), 1);
+        plugin.trigger_voice(params.clone(), 2);
+
+        let mut output = [[0.0; 2]; 2];
+        plugin.gen_render(&mut output);
+        assert_eq!([[0.5; 2]; 2], output);
+
+        plugin.kill_voice(first);
+        plugin.gen_render(&mut output);
+        assert_eq!([[0.25; 2]; 2], output);
+
+        // the new voice takes the slot, the old handle doesn't reach it
+        let third = plugin.trigger_voice(params, 3);
+        assert_ne!(first, third);
+        plugin.kill_voice(first);
+        plugin.gen_render(&mut output);
+        assert_eq!([[0.5; 2]; 2], output);
+
+        // generators don't process effect buffers
+        plugin.eff_render(&[[1.0; 2]; 2], &mut output);
+        assert_eq!([[0.0; 2]; 2], output);
+    }
+}
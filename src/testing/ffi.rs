@@ -0,0 +1,264 @@
+//! Memory layouts of the FL SDK C++ classes the mock host stands in for.
+//!
+//! The C++ wrapper only ever talks to `TFruityPlugHost` and `IStream` through their virtual
+//! methods, so a `#[repr(C)]` struct starting with a pointer to a table of functions is all it
+//! takes to look like one. The other way around, the plugin is a `TFruityPlug` we call through
+//! its own table. The order of the tables must follow `fp_plugclass.h`.
+use std::os::raw::{c_char, c_int, c_long, c_ulong, c_void};
+
+use crate::intptr_t;
+use crate::voice;
+
+/// `TFruityPlugHost` object.
+#[repr(C)]
+pub(crate) struct FruityPlugHost {
+    pub(crate) vtable: *const FruityPlugHostVtbl,
+    pub(crate) host_version: c_int,
+    pub(crate) flags: c_int,
+    pub(crate) app_handle: intptr_t,
+    pub(crate) wave_tables: [*mut c_void; 10],
+    pub(crate) temp_buffers: [*mut c_void; 4],
+    pub(crate) reserved: [c_int; 30],
+}
+
+type This = *mut FruityPlugHost;
+
+/// `TFruityPlugHost` virtual methods.
+#[repr(C)]
+pub(crate) struct FruityPlugHostVtbl {
+    pub(crate) dispatcher:
+        unsafe extern "system" fn(This, intptr_t, intptr_t, intptr_t, intptr_t) -> intptr_t,
+    pub(crate) on_param_changed: unsafe extern "system" fn(This, intptr_t, c_int, c_int),
+    pub(crate) on_hint: unsafe extern "system" fn(This, intptr_t, *mut c_char),
+    pub(crate) compute_lr_vol_old: unsafe extern "system" fn(This, *mut f32, *mut f32, c_int, f32),
+    pub(crate) voice_release: unsafe extern "system" fn(This, intptr_t),
+    pub(crate) voice_kill: unsafe extern "system" fn(This, intptr_t, c_int),
+    pub(crate) voice_process_event:
+        unsafe extern "system" fn(This, intptr_t, intptr_t, intptr_t, intptr_t) -> c_int,
+    pub(crate) lock_mix: unsafe extern "system" fn(This),
+    pub(crate) unlock_mix: unsafe extern "system" fn(This),
+    pub(crate) midi_out_delayed: unsafe extern "system" fn(This, intptr_t, intptr_t),
+    pub(crate) midi_out: unsafe extern "system" fn(This, intptr_t, intptr_t),
+    pub(crate) add_wave_32fm_32fs_ramp: unsafe extern "system" fn(
+        This,
+        *mut c_void,
+        *mut c_void,
+        c_int,
+        f32,
+        f32,
+        *mut f32,
+        *mut f32,
+    ),
+    pub(crate) add_wave_32fs_32fs_ramp: unsafe extern "system" fn(
+        This,
+        *mut c_void,
+        *mut c_void,
+        c_int,
+        f32,
+        f32,
+        *mut f32,
+        *mut f32,
+    ),
+    pub(crate) load_sample:
+        unsafe extern "system" fn(This, *mut intptr_t, *mut c_char, *mut c_void, c_int) -> bool,
+    pub(crate) get_sample_data:
+        unsafe extern "system" fn(This, intptr_t, *mut c_int) -> *mut c_void,
+    pub(crate) close_sample: unsafe extern "system" fn(This, intptr_t),
+    pub(crate) get_song_mixing_time: unsafe extern "system" fn(This) -> c_int,
+    pub(crate) get_song_mixing_time_a: unsafe extern "system" fn(This) -> f64,
+    pub(crate) get_song_playing_time: unsafe extern "system" fn(This) -> f64,
+    pub(crate) on_controller_changed: unsafe extern "system" fn(This, intptr_t, intptr_t, intptr_t),
+    pub(crate) get_send_buffer: unsafe extern "system" fn(This, intptr_t) -> *mut c_void,
+    pub(crate) plug_msg_delayed: unsafe extern "system" fn(This, intptr_t, intptr_t),
+    pub(crate) plug_msg_kill: unsafe extern "system" fn(This, intptr_t, intptr_t),
+    pub(crate) get_sample_info: unsafe extern "system" fn(This, intptr_t, *mut SampleInfo),
+    pub(crate) dist_wave_32fm:
+        unsafe extern "system" fn(This, c_int, c_int, *mut c_void, c_int, f32, f32, f32),
+    pub(crate) get_mix_buffer: unsafe extern "system" fn(This, c_int) -> *mut c_void,
+    pub(crate) get_ins_buffer: unsafe extern "system" fn(This, intptr_t, c_int) -> *mut c_void,
+    pub(crate) prompt_edit:
+        unsafe extern "system" fn(This, c_int, c_int, *mut c_char, *mut c_char, *mut c_int) -> bool,
+    pub(crate) suspend_output: unsafe extern "system" fn(This),
+    pub(crate) resume_output: unsafe extern "system" fn(This),
+    pub(crate) get_sample_region:
+        unsafe extern "system" fn(This, intptr_t, c_int, *mut SampleRegion),
+    pub(crate) compute_lr_vol: unsafe extern "system" fn(This, *mut f32, *mut f32, f32, f32),
+    pub(crate) lock_plugin: unsafe extern "system" fn(This, intptr_t),
+    pub(crate) unlock_plugin: unsafe extern "system" fn(This, intptr_t),
+    pub(crate) lock_mix_shared_old: unsafe extern "system" fn(This),
+    pub(crate) unlock_mix_shared_old: unsafe extern "system" fn(This),
+    pub(crate) get_in_buffer: unsafe extern "system" fn(This, intptr_t, intptr_t, *mut IoBuffer),
+    pub(crate) get_out_buffer: unsafe extern "system" fn(This, intptr_t, intptr_t, *mut IoBuffer),
+    pub(crate) trigger_output_voice:
+        unsafe extern "system" fn(This, *mut voice::Params, intptr_t, intptr_t) -> intptr_t,
+    pub(crate) output_voice_release: unsafe extern "system" fn(This, intptr_t),
+    pub(crate) output_voice_kill: unsafe extern "system" fn(This, intptr_t),
+    pub(crate) output_voice_process_event:
+        unsafe extern "system" fn(This, intptr_t, intptr_t, intptr_t, intptr_t) -> c_int,
+}
+
+/// `TFruityPlugInfo`.
+#[repr(C, packed(4))]
+pub(crate) struct FruityPlugInfo {
+    pub(crate) sdk_version: c_int,
+    pub(crate) long_name: *mut c_char,
+    pub(crate) short_name: *mut c_char,
+    pub(crate) flags: c_int,
+    pub(crate) num_params: c_int,
+    pub(crate) def_poly: c_int,
+    pub(crate) num_out_ctrls: c_int,
+    pub(crate) num_out_voices: c_int,
+    pub(crate) reserved: [c_int; 30],
+}
+
+/// `TFruityPlug` object.
+#[repr(C)]
+pub(crate) struct FruityPlug {
+    pub(crate) vtable: *const FruityPlugVtbl,
+    pub(crate) host_tag: intptr_t,
+    pub(crate) info: *mut FruityPlugInfo,
+    pub(crate) editor_handle: *mut c_void,
+    pub(crate) mono_render: c_int,
+    pub(crate) reserved: [c_int; 32],
+}
+
+type PlugThis = *mut FruityPlug;
+
+/// `TFruityPlug` virtual methods. The destructor follows them, but it's never called directly:
+/// `DestroyObject` takes care of that.
+#[repr(C)]
+pub(crate) struct FruityPlugVtbl {
+    pub(crate) destroy_object: unsafe extern "system" fn(PlugThis),
+    pub(crate) dispatcher:
+        unsafe extern "system" fn(PlugThis, intptr_t, intptr_t, intptr_t) -> intptr_t,
+    pub(crate) idle_public: unsafe extern "system" fn(PlugThis),
+    pub(crate) save_restore_state: unsafe extern "system" fn(PlugThis, *mut Stream, c_int),
+    pub(crate) get_name: unsafe extern "system" fn(PlugThis, c_int, c_int, c_int, *mut c_char),
+    pub(crate) process_event: unsafe extern "system" fn(PlugThis, c_int, c_int, c_int) -> c_int,
+    pub(crate) process_param: unsafe extern "system" fn(PlugThis, c_int, c_int, c_int) -> c_int,
+    pub(crate) eff_render:
+        unsafe extern "system" fn(PlugThis, *const [f32; 2], *mut [f32; 2], c_int),
+    pub(crate) gen_render: unsafe extern "system" fn(PlugThis, *mut [f32; 2], *mut c_int),
+    pub(crate) trigger_voice:
+        unsafe extern "system" fn(PlugThis, *mut voice::Params, intptr_t) -> intptr_t,
+    pub(crate) voice_release: unsafe extern "system" fn(PlugThis, intptr_t),
+    pub(crate) voice_kill: unsafe extern "system" fn(PlugThis, intptr_t),
+    pub(crate) voice_process_event:
+        unsafe extern "system" fn(PlugThis, intptr_t, c_int, c_int, c_int) -> c_int,
+    pub(crate) voice_render:
+        unsafe extern "system" fn(PlugThis, intptr_t, *mut [f32; 2], *mut c_int) -> c_int,
+    pub(crate) new_tick: unsafe extern "system" fn(PlugThis),
+    pub(crate) midi_tick: unsafe extern "system" fn(PlugThis),
+    pub(crate) midi_in: unsafe extern "system" fn(PlugThis, *mut c_int),
+    pub(crate) msg_in: unsafe extern "system" fn(PlugThis, intptr_t),
+    pub(crate) output_voice_process_event:
+        unsafe extern "system" fn(PlugThis, intptr_t, c_int, c_int, c_int) -> c_int,
+    pub(crate) output_voice_kill: unsafe extern "system" fn(PlugThis, intptr_t),
+}
+
+/// `TIOBuffer`.
+#[repr(C, packed)]
+pub(crate) struct IoBuffer {
+    pub(crate) buffer: *mut c_void,
+    pub(crate) flags: c_int,
+}
+
+/// `WAVEFORMATEX`, the start of `TWaveFormatExtensible`.
+#[repr(C, packed)]
+pub(crate) struct WaveFormatEx {
+    pub(crate) format_tag: u16,
+    pub(crate) channels: u16,
+    pub(crate) samples_per_sec: u32,
+    pub(crate) avg_bytes_per_sec: u32,
+    pub(crate) block_align: u16,
+    pub(crate) bits_per_sample: u16,
+    pub(crate) size: u16,
+}
+
+/// `TSampleInfo`.
+#[repr(C, packed)]
+pub(crate) struct SampleInfo {
+    pub(crate) size: c_int,
+    pub(crate) data: *mut c_void,
+    pub(crate) length: c_int,
+    pub(crate) solid_length: c_int,
+    pub(crate) loop_start: c_int,
+    pub(crate) loop_end: c_int,
+    pub(crate) smp_rate_conv: f64,
+    pub(crate) num_regions: c_int,
+    pub(crate) num_beats: f32,
+    pub(crate) tempo: f32,
+    pub(crate) num_chans: c_int,
+    pub(crate) format: c_int,
+    pub(crate) reserved: [c_int; 13],
+}
+
+/// `TSampleRegion`.
+#[repr(C)]
+pub(crate) struct SampleRegion {
+    pub(crate) sample_start: c_int,
+    pub(crate) sample_end: c_int,
+    pub(crate) name: [u8; 256],
+    pub(crate) info: [u8; 256],
+    pub(crate) time: f32,
+    pub(crate) key_num: c_int,
+    pub(crate) reserved: [c_int; 4],
+}
+
+/// `TMIDIOutMsg`.
+#[repr(C)]
+pub(crate) struct MidiOutMsg {
+    pub(crate) status: u8,
+    pub(crate) data1: u8,
+    pub(crate) data2: u8,
+    pub(crate) port: u8,
+}
+
+/// The header of `TNotesParams`, which is followed by `count` notes.
+#[repr(C)]
+pub(crate) struct NotesParamsHeader {
+    pub(crate) target: c_int,
+    pub(crate) flags: c_int,
+    pub(crate) pat_num: c_int,
+    pub(crate) chan_num: c_int,
+    pub(crate) count: c_int,
+}
+
+/// `IStream` object.
+#[repr(C)]
+pub(crate) struct Stream {
+    pub(crate) vtable: *const StreamVtbl,
+}
+
+type StreamThis = *mut Stream;
+
+/// `IStream` virtual methods.
+#[repr(C)]
+pub(crate) struct StreamVtbl {
+    pub(crate) query_interface:
+        unsafe extern "system" fn(StreamThis, *const c_void, *mut *mut c_void) -> c_long,
+    pub(crate) add_ref: unsafe extern "system" fn(StreamThis) -> c_ulong,
+    pub(crate) release: unsafe extern "system" fn(StreamThis) -> c_ulong,
+    pub(crate) read:
+        unsafe extern "system" fn(StreamThis, *mut c_void, c_ulong, *mut c_ulong) -> c_long,
+    pub(crate) write:
+        unsafe extern "system" fn(StreamThis, *const c_void, c_ulong, *mut c_ulong) -> c_long,
+    pub(crate) seek: unsafe extern "system" fn(StreamThis, i64, c_int, *mut u64) -> c_long,
+    pub(crate) set_size: unsafe extern "system" fn(StreamThis, u64) -> c_long,
+    pub(crate) copy_to:
+        unsafe extern "system" fn(StreamThis, StreamThis, u64, *mut u64, *mut u64) -> c_long,
+    pub(crate) commit: unsafe extern "system" fn(StreamThis, c_int) -> c_long,
+    pub(crate) revert: unsafe extern "system" fn(StreamThis) -> c_long,
+    pub(crate) lock_region: unsafe extern "system" fn(StreamThis, u64, u64, c_int) -> c_long,
+    pub(crate) unlock_region: unsafe extern "system" fn(StreamThis, u64, u64, c_int) -> c_long,
+    pub(crate) stat: unsafe extern "system" fn(StreamThis, *mut c_void, c_int) -> c_long,
+    pub(crate) clone: unsafe extern "system" fn(StreamThis, *mut StreamThis) -> c_long,
+}
+
+pub(crate) const S_OK: c_long = 0;
+pub(crate) const S_FALSE: c_long = 1;
+pub(crate) const E_NOTIMPL: c_long = 0x8000_4001_u32 as i32 as c_long;
+
+extern "C" {
+    pub(crate) fn free(ptr: *mut c_void);
+}
@@ -1,9 +1,13 @@
 //! Voices used by generators to track events like their instantiation, release, freeing and
 //! processing some events.
-use std::os::raw::c_void;
+pub mod engine;
+mod slots;
 
+use log::debug;
+
+pub(crate) use self::slots::VoiceSlots;
 use crate::plugin::PluginAdapter;
-use crate::{intptr_t, AsRawPtr, FlMessage, ValuePtr};
+use crate::{intptr_t, AsRawPtr, DispatchResult, FlMessage, ValuePtr};
 
 crate::implement_tag!();
 
@@ -13,23 +17,47 @@ crate::implement_tag!();
 pub trait ReceiveVoiceHandler: Send + Sync {
     /// The host calls this to let it create a voice.
     ///
-    /// The `tag` parameter is an identifier the host uses to identify the voice.
-    fn trigger(&mut self, params: Params, tag: Tag) -> &mut dyn Voice;
+    /// The `tag` parameter is an identifier the host uses to identify the voice. The host gets a
+    /// handle for it, which is checked before the other methods are called, so they only get the
+    /// tags of the voices which are alive. There are
+    /// [`Generator::MAX_VOICES`](../plugin/trait.Generator.html#associatedconstant.MAX_VOICES)
+    /// handles, the voices triggered while they're all taken are ignored.
+    fn trigger(&mut self, params: Params, tag: Tag);
     /// This gets called by the host when the voice enters the envelope release state (note off).
     fn release(&mut self, tag: Tag);
     /// Called when the voice has to be discarded.
     fn kill(&mut self, tag: Tag);
     /// Process a voice event.
+    ///
+    /// The result is boxed, so this allocates on every call. Implement
+    /// [`handle_event`](#method.handle_event) instead, it's called by the host and by default
+    /// forwards to this method.
     fn on_event(&mut self, _tag: Tag, _event: Event) -> Box<dyn AsRawPtr> {
-        Box::new(0)
+        Box::new(DispatchResult::NONE)
+    }
+    /// Like [`on_event`](#method.on_event), but doesn't allocate for the result.
+    fn handle_event(&mut self, tag: Tag, event: Event) -> DispatchResult {
+        self.on_event(tag, event).into()
     }
     /// Getter for [`SendVoiceHandler`](trait.SendVoiceHandler.html).
     fn out_handler(&mut self) -> Option<&mut dyn SendVoiceHandler> {
         None
     }
+    /// Kill the weakest voice, when the host asks with
+    /// [`host::Message::KillVoice`](../host/enum.Message.html#variant.KillVoice) and the plugin
+    /// doesn't answer it.
+    ///
+    /// Returns `true` if a voice was killed.
+    fn kill_weakest(&mut self) -> bool {
+        false
+    }
+    /// The maximum polyphony has changed (see
+    /// [`host::Event::MaxPoly`](../host/enum.Event.html#variant.MaxPoly)). A value <= 0 means
+    /// infinite polyphony.
+    fn set_max_poly(&mut self, _max_poly: i32) {}
 }
 
-/// You should implement this trait to your voice type.
+/// A voice created with [`SendVoiceHandler::trigger`](trait.SendVoiceHandler.html#method.trigger).
 pub trait Voice: Send + Sync {
     /// Get ID of the voice.
     fn tag(&self) -> Tag;
@@ -40,7 +68,7 @@ pub trait Voice: Send + Sync {
 /// levels are also available for, for example, note layering. In any case the initial levels are
 /// made to be checked once the voice is triggered, while the other ones are to be checked every
 /// time.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 #[repr(C)]
 pub struct Params {
     /// Made to be checked once the voice is triggered.
@@ -54,7 +82,7 @@ pub struct Params {
 /// [`Params`](struct.Params.html).
 ///
 /// **All of these parameters can go outside their defined range!**
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 #[repr(C)]
 pub struct LevelParams {
     /// Panning (-1..1).
@@ -200,14 +228,20 @@ unsafe extern "C" fn voice_handler_trigger(
     params: Params,
     tag: intptr_t,
 ) -> intptr_t {
-    (*adapter)
-        .0
-        .voice_handler()
-        .map(|handler| {
-            let voice_ptr: *mut &mut dyn Voice =
-                Box::leak(Box::new(handler.trigger(params, Tag(tag))));
-            voice_ptr as *mut c_void as intptr_t
+    // the voice can't be triggered without a handle to give the host
+    if (*adapter).voices.is_full() {
+        debug!("no free handle for voice {}", tag);
+        return -1;
+    }
+    let triggered = (*adapter)
+        .guard_instance(|instance| {
+            instance
+                .voice_handler()
+                .map(|handler| handler.trigger(params, Tag(tag)))
         })
+        .flatten();
+    triggered
+        .and_then(|_| (*adapter).voices.insert(Tag(tag)))
         .unwrap_or(-1)
 }
 
@@ -220,16 +254,16 @@ unsafe extern "C" fn voice_handler_trigger(
 /// Unsafe
 #[doc(hidden)]
 #[no_mangle]
-unsafe extern "C" fn voice_handler_release(
-    adapter: *mut PluginAdapter,
-    voice: *mut &mut dyn Voice,
-) {
-    // We don't call Box::from_raw because:
-    // 1. Host calls this then voice_handler_kill — this way we'll get double deallocation
-    // 2. Given FL SDK documentation, we shouldn't deallocate voices here
-    if let Some(handler) = (*adapter).0.voice_handler() {
-        handler.release((*voice).tag())
-    }
+unsafe extern "C" fn voice_handler_release(adapter: *mut PluginAdapter, handle: intptr_t) {
+    let tag = match (*adapter).voices.get(handle) {
+        Some(tag) => tag,
+        None => return debug!("release of a dead voice {}", handle),
+    };
+    (*adapter).guard_instance(|instance| {
+        if let Some(handler) = instance.voice_handler() {
+            handler.release(tag)
+        }
+    });
 }
 
 /// [`ReceiveVoiceHandler::kill`](trait.ReceiveVoiceHandler.html#tymethod.kill) FFI.
@@ -241,11 +275,16 @@ unsafe extern "C" fn voice_handler_release(
 /// Unsafe
 #[doc(hidden)]
 #[no_mangle]
-unsafe extern "C" fn voice_handler_kill(adapter: *mut PluginAdapter, voice: *mut &mut dyn Voice) {
-    let r_voice = Box::from_raw(voice);
-    if let Some(handler) = (*adapter).0.voice_handler() {
-        handler.kill(r_voice.tag())
-    }
+unsafe extern "C" fn voice_handler_kill(adapter: *mut PluginAdapter, handle: intptr_t) {
+    let tag = match (*adapter).voices.remove(handle) {
+        Some(tag) => tag,
+        None => return debug!("kill of a dead voice {}", handle),
+    };
+    (*adapter).guard_instance(|instance| {
+        if let Some(handler) = instance.voice_handler() {
+            handler.kill(tag)
+        }
+    });
 }
 
 /// [`ReceiveVoiceHandler::kill_out`](trait.ReceiveVoiceHandler.html#tymethod.kill_out) FFI.
@@ -258,14 +297,16 @@ unsafe extern "C" fn voice_handler_kill(adapter: *mut PluginAdapter, voice: *mut
 #[doc(hidden)]
 #[no_mangle]
 unsafe extern "C" fn out_voice_handler_kill(adapter: *mut PluginAdapter, tag: intptr_t) {
-    (*adapter).0.voice_handler().and_then(|handler| {
-        handler.out_handler().map(|out_handler| {
-            out_handler.kill(Tag(tag));
+    (*adapter).guard_instance(|instance| {
+        instance.voice_handler().and_then(|handler| {
+            handler.out_handler().map(|out_handler| {
+                out_handler.kill(Tag(tag));
+            })
         })
     });
 }
 
-/// [`ReceiveVoiceHandler::on_event`](trait.ReceiveVoiceHandler.html#tymethod.on_event) FFI.
+/// [`ReceiveVoiceHandler::handle_event`](trait.ReceiveVoiceHandler.html#method.handle_event) FFI.
 ///
 /// It supposed to be used internally. Don't use it.
 ///
@@ -276,17 +317,23 @@ unsafe extern "C" fn out_voice_handler_kill(adapter: *mut PluginAdapter, tag: in
 #[no_mangle]
 unsafe extern "C" fn voice_handler_on_event(
     adapter: *mut PluginAdapter,
-    voice: *mut &mut dyn Voice,
+    handle: intptr_t,
     message: FlMessage,
 ) -> intptr_t {
+    let tag = match (*adapter).voices.get(handle) {
+        Some(tag) => tag,
+        None => {
+            debug!("event for a dead voice {}", handle);
+            return -1;
+        }
+    };
     (*adapter)
-        .0
-        .voice_handler()
-        .map(|handler| {
-            handler
-                .on_event((*voice).tag(), message.into())
-                .as_raw_ptr()
+        .guard_instance(|instance| {
+            instance
+                .voice_handler()
+                .map(|handler| handler.handle_event(tag, message.into()).as_raw_ptr())
         })
+        .flatten()
         .unwrap_or(-1)
 }
 
@@ -305,11 +352,14 @@ unsafe extern "C" fn out_voice_handler_on_event(
     message: FlMessage,
 ) -> intptr_t {
     (*adapter)
-        .0
-        .voice_handler()
-        .and_then(|handler| handler.out_handler())
-        .and_then(|out_handler| out_handler.on_event(Tag(tag), message.into()))
-        .map(|result| result.0)
+        .guard_instance(|instance| {
+            instance
+                .voice_handler()
+                .and_then(|handler| handler.out_handler())
+                .and_then(|out_handler| out_handler.on_event(Tag(tag), message.into()))
+                .map(|result| result.0)
+        })
+        .flatten()
         .unwrap_or(-1)
 }
 
@@ -0,0 +1,611 @@
+//! Polyphonic voice engine.
+//!
+//! [`Engine`](struct.Engine.html) implements
+//! [`ReceiveVoiceHandler`](../trait.ReceiveVoiceHandler.html) on top of a pool of your
+//! [`SynthVoice`](trait.SynthVoice.html)s. It maps the host's voices to the pool, steals voices
+//! when the pool is full, handles mono and legato modes and keeps releasing voices until they're
+//! done. The pool is allocated once, so nothing is allocated while playing.
+//!
+//! Use it as [`Generator::Voices`](../../plugin/trait.Generator.html#associatedtype.Voices):
+//!
+//! ```ignore
+//! impl Generator for Synth {
+//!     type Voices = Engine<Osc>;
+//!
+//!     fn voices(host: &Host, _tag: plugin::Tag) -> Self::Voices {
+//!         Engine::new(host, 16, Osc::default)
+//!     }
+//!
+//!     fn render(
+//!         &mut self,
+//!         _ctx: &mut AudioContext<'_>,
+//!         output: &mut [[f32; 2]],
+//!         voices: &mut Self::Voices,
+//!     ) {
+//!         output.iter_mut().for_each(|frame| *frame = [0.0; 2]);
+//!         voices.render(output);
+//!     }
+//! }
+//! ```
+use log::trace;
+
+use super::{Event, Params, ReceiveVoiceHandler, Tag};
+use crate::host::{Host, Voicer};
+use crate::voice::SendVoiceHandler;
+use crate::DispatchResult;
+
+/// A voice of [`Engine`](struct.Engine.html).
+pub trait SynthVoice: Send + Sync {
+    /// Start playing a note.
+    fn start(&mut self, params: &Params);
+    /// Move to another note without restarting (used in [`Mode::Legato`](enum.Mode.html)).
+    ///
+    /// Restarts by default.
+    fn glide(&mut self, params: &Params) {
+        self.start(params);
+    }
+    /// The note is released. The voice keeps playing until [`is_done`](#tymethod.is_done).
+    fn release(&mut self);
+    /// Add the voice's output to `output`.
+    fn render(&mut self, output: &mut [[f32; 2]]);
+    /// Whether the voice has finished playing (its release tail is over).
+    fn is_done(&self) -> bool;
+    /// Current level, used by [`Steal::Quietest`](enum.Steal.html) and
+    /// [`kill_weakest`](../trait.ReceiveVoiceHandler.html#method.kill_weakest).
+    fn level(&self) -> f32 {
+        0.0
+    }
+}
+
+/// Which voice to take when the pool is full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Steal {
+    /// The oldest voice.
+    Oldest,
+    /// The voice with the lowest [`level`](trait.SynthVoice.html#method.level).
+    Quietest,
+    /// The voice playing the same note, even if the pool isn't full. Falls back to the oldest.
+    SameNote,
+}
+
+/// Voice allocation mode.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Every note gets its own voice.
+    Poly,
+    /// One voice, restarted for every note. Releasing a note goes back to the previous held one.
+    Mono,
+    /// Like [`Mono`](#variant.Mono), but glides between held notes instead of restarting.
+    Legato,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Free,
+    Held,
+    Released,
+}
+
+#[derive(Debug)]
+struct Slot<V> {
+    voice: V,
+    state: State,
+    // index in Engine::handles
+    handle: Option<usize>,
+    note: i32,
+    age: u64,
+}
+
+// The engine's side of a host voice. It stays until the host kills the voice, even if its slot
+// is taken by another voice.
+#[derive(Debug)]
+struct Handle {
+    tag: Tag,
+    slot: Option<usize>,
+    alive: bool,
+}
+
+/// Voice engine.
+///
+/// It's a [`ReceiveVoiceHandler`](../trait.ReceiveVoiceHandler.html) with a fixed pool of
+/// voices. Voices that have finished or been stolen are killed on the host side during
+/// [`render`](#method.render).
+pub struct Engine<V> {
+    slots: Vec<Slot<V>>,
+    // the last one is used when all others are taken
+    handles: Vec<Handle>,
+    // notes held in mono modes, the last one is playing
+    held: Vec<(Tag, Params)>,
+    // voices to kill on the host side
+    pending_kills: Vec<Tag>,
+    voicer: Voicer,
+    max_poly: usize,
+    steal: Steal,
+    mode: Mode,
+    age: u64,
+}
+
+impl<V: SynthVoice> std::fmt::Debug for Engine<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Engine")
+            .field("capacity", &self.slots.len())
+            .field("active", &self.active())
+            .field("max_poly", &self.max_poly)
+            .field("steal", &self.steal)
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+impl<V: SynthVoice> Engine<V> {
+    /// Initializer. Creates `capacity` voices with `new_voice`, at least one.
+    pub fn new(host: &Host, capacity: usize, mut new_voice: impl FnMut() -> V) -> Self {
+        let capacity = capacity.max(1);
+        // stolen voices hold their handles until the host kills them
+        let num_handles = capacity * 2 + 1;
+        Self {
+            slots: (0..capacity)
+                .map(|_| Slot {
+                    voice: new_voice(),
+                    state: State::Free,
+                    handle: None,
+                    note: 0,
+                    age: 0,
+                })
+                .collect(),
+            handles: (0..num_handles)
+                .map(|_| Handle {
+                    tag: Tag(0),
+                    slot: None,
+                    alive: false,
+                })
+                .collect(),
+            held: Vec::with_capacity(num_handles),
+            pending_kills: Vec::with_capacity(num_handles),
+            voicer: host.voice_handler(),
+            max_poly: capacity,
+            steal: Steal::Oldest,
+            mode: Mode::Poly,
+            age: 0,
+        }
+    }
+
+    /// Set the stealing policy. The default is [`Steal::Oldest`](enum.Steal.html).
+    pub fn set_steal(&mut self, steal: Steal) {
+        self.steal = steal;
+    }
+
+    /// Set the mode. The default is [`Mode::Poly`](enum.Mode.html).
+    pub fn set_mode(&mut self, mode: Mode) {
+        if mode != self.mode {
+            self.kill_all();
+            self.mode = mode;
+        }
+    }
+
+    /// The number of voices in the pool.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// The number of voices playing, including releasing ones.
+    pub fn active(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| slot.state != State::Free)
+            .count()
+    }
+
+    /// Iterate over the voices playing.
+    pub fn voices_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.slots
+            .iter_mut()
+            .filter(|slot| slot.state != State::Free)
+            .map(|slot| &mut slot.voice)
+    }
+
+    /// Render the playing voices, adding them to `output`, and free the finished ones.
+    pub fn render(&mut self, output: &mut [[f32; 2]]) {
+        for index in 0..self.slots.len() {
+            let slot = &mut self.slots[index];
+            if slot.state == State::Free {
+                continue;
+            }
+            slot.voice.render(output);
+            if slot.voice.is_done() {
+                self.free(index);
+            }
+        }
+        self.flush_kills();
+    }
+
+    /// Stop all voices immediately.
+    pub fn kill_all(&mut self) {
+        for index in 0..self.slots.len() {
+            if self.slots[index].state != State::Free {
+                self.free(index);
+            }
+        }
+        self.held.clear();
+    }
+
+    fn limit(&self) -> usize {
+        match self.mode {
+            Mode::Poly => self.max_poly.min(self.slots.len()),
+            Mode::Mono | Mode::Legato => 1,
+        }
+    }
+
+    fn find_handle(&self, tag: Tag) -> Option<usize> {
+        self.handles
+            .iter()
+            .position(|handle| handle.alive && handle.tag == tag)
+    }
+
+    fn new_handle(&mut self, tag: Tag) -> usize {
+        let last = self.handles.len() - 1;
+        let free = self.handles[..last].iter().position(|handle| !handle.alive);
+        let index = match free {
+            Some(index) => index,
+            None => {
+                // out of handles, the voice stays silent
+                trace!("no handle for voice {}", tag);
+                self.queue_kill(tag);
+                last
+            }
+        };
+        self.handles[index] = Handle {
+            tag,
+            slot: None,
+            alive: index != last,
+        };
+        index
+    }
+
+    fn queue_kill(&mut self, tag: Tag) {
+        if self.pending_kills.len() < self.pending_kills.capacity() {
+            self.pending_kills.push(tag);
+        }
+    }
+
+    fn flush_kills(&mut self) {
+        if self.pending_kills.is_empty() {
+            return;
+        }
+        for tag in self.pending_kills.drain(..) {
+            self.voicer.kill(tag);
+        }
+    }
+
+    // Frees the slot and asks the host to kill the voice that held it.
+    fn free(&mut self, index: usize) {
+        let slot = &mut self.slots[index];
+        slot.state = State::Free;
+        if let Some(handle) = slot.handle.take() {
+            self.handles[handle].slot = None;
+            let tag = self.handles[handle].tag;
+            self.queue_kill(tag);
+        }
+    }
+
+    fn assign(&mut self, index: usize, handle: usize, note: i32) {
+        if let Some(previous) = self.slots[index].handle.replace(handle) {
+            self.handles[previous].slot = None;
+        }
+        self.handles[handle].slot = Some(index);
+        self.age += 1;
+        let slot = &mut self.slots[index];
+        slot.state = State::Held;
+        slot.note = note;
+        slot.age = self.age;
+    }
+
+    fn victim(&self, note: i32) -> usize {
+        let slots = &self.slots;
+        if self.steal == Steal::SameNote {
+            if let Some(index) = slots
+                .iter()
+                .position(|slot| slot.state != State::Free && slot.note == note)
+            {
+                return index;
+            }
+        }
+        if self.active() < self.limit() {
+            if let Some(index) = slots.iter().position(|slot| slot.state == State::Free) {
+                return index;
+            }
+        }
+        // releasing voices go first
+        let released = slots.iter().any(|slot| slot.state == State::Released);
+        let candidates = slots.iter().enumerate().filter(|(_, slot)| {
+            if released {
+                slot.state == State::Released
+            } else {
+                slot.state != State::Free
+            }
+        });
+        match self.steal {
+            Steal::Quietest => candidates
+                .min_by(|(_, a), (_, b)| a.voice.level().total_cmp(&b.voice.level()))
+                .map(|(index, _)| index),
+            Steal::Oldest | Steal::SameNote => candidates
+                .min_by_key(|(_, slot)| slot.age)
+                .map(|(index, _)| index),
+        }
+        .unwrap_or(0)
+    }
+
+    fn trigger_poly(&mut self, params: &Params, handle: usize) {
+        let note = note_of(params);
+        let index = self.victim(note);
+        if self.slots[index].state != State::Free {
+            trace!("steal voice {}", index);
+            self.free(index);
+        }
+        self.assign(index, handle, note);
+        self.slots[index].voice.start(params);
+    }
+
+    fn trigger_mono(&mut self, params: &Params, tag: Tag, handle: usize) {
+        let note = note_of(params);
+        let gliding = self.mode == Mode::Legato && self.slots[0].state == State::Held;
+        if self.held.len() < self.held.capacity() {
+            self.held.push((tag, params.clone()));
+        }
+        self.assign(0, handle, note);
+        if gliding {
+            self.slots[0].voice.glide(params);
+        } else {
+            self.slots[0].voice.start(params);
+        }
+    }
+
+    fn release_mono(&mut self, tag: Tag, handle: usize) {
+        self.held.retain(|(held, _)| *held != tag);
+        if self.handles[handle].slot.is_none() {
+            // another note took the voice
+            self.queue_kill(tag);
+            return;
+        }
+        let previous = self
+            .held
+            .last()
+            .and_then(|(held, params)| self.find_handle(*held).map(|h| (h, params.clone())));
+        match previous {
+            Some((previous, params)) => {
+                self.queue_kill(tag);
+                self.assign(0, previous, note_of(&params));
+                if self.mode == Mode::Legato {
+                    self.slots[0].voice.glide(&params);
+                } else {
+                    self.slots[0].voice.start(&params);
+                }
+            }
+            None => {
+                self.slots[0].state = State::Released;
+                self.slots[0].voice.release();
+            }
+        }
+    }
+}
+
+impl<V: SynthVoice> ReceiveVoiceHandler for Engine<V> {
+    fn trigger(&mut self, params: Params, tag: Tag) {
+        let handle = self.new_handle(tag);
+        if self.handles[handle].alive {
+            match self.mode {
+                Mode::Poly => self.trigger_poly(&params, handle),
+                Mode::Mono | Mode::Legato => self.trigger_mono(&params, tag, handle),
+            }
+        }
+    }
+
+    fn release(&mut self, tag: Tag) {
+        let handle = match self.find_handle(tag) {
+            Some(handle) => handle,
+            None => return,
+        };
+        if self.mode != Mode::Poly {
+            return self.release_mono(tag, handle);
+        }
+        match self.handles[handle].slot {
+            Some(index) => {
+                self.slots[index].state = State::Released;
+                self.slots[index].voice.release();
+            }
+            // stolen
+            None => self.queue_kill(tag),
+        }
+    }
+
+    fn kill(&mut self, tag: Tag) {
+        let handle = match self.find_handle(tag) {
+            Some(handle) => handle,
+            None => return,
+        };
+        if let Some(index) = self.handles[handle].slot.take() {
+            let slot = &mut self.slots[index];
+            slot.state = State::Free;
+            slot.handle = None;
+        }
+        self.handles[handle].alive = false;
+        self.held.retain(|(held, _)| *held != tag);
+        self.pending_kills.retain(|pending| *pending != tag);
+    }
+
+    fn kill_weakest(&mut self) -> bool {
+        let weakest = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.state != State::Free)
+            .min_by(|(_, a), (_, b)| a.voice.level().total_cmp(&b.voice.level()))
+            .map(|(index, _)| index);
+        match weakest {
+            Some(index) => {
+                self.free(index);
+                // the host may not render before asking again
+                self.flush_kills();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // nothing to answer, without boxing the result like the default
+    fn handle_event(&mut self, _tag: Tag, _event: Event) -> DispatchResult {
+        DispatchResult::NONE
+    }
+
+    fn set_max_poly(&mut self, max_poly: i32) {
+        self.max_poly = if max_poly <= 0 {
+            self.slots.len()
+        } else {
+            max_poly as usize
+        };
+    }
+}
+
+// Semitone of the note. Pitch is in cents.
+fn note_of(params: &Params) -> i32 {
+    (params.init_levels.pitch / 100.0).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{HostCall, MockHost};
+    use crate::voice::LevelParams;
+
+    #[derive(Debug, Default)]
+    struct Beep {
+        note: i32,
+        level: f32,
+        releasing: bool,
+        glides: usize,
+    }
+
+    impl SynthVoice for Beep {
+        fn start(&mut self, params: &Params) {
+            self.note = note_of(params);
+            self.level = params.final_levels.vol;
+            self.releasing = false;
+        }
+
+        fn glide(&mut self, params: &Params) {
+            self.note = note_of(params);
+            self.glides += 1;
+        }
+
+        fn release(&mut self) {
+            self.releasing = true;
+        }
+
+        fn render(&mut self, output: &mut [[f32; 2]]) {
+            for frame in output.iter_mut() {
+                frame[0] += self.level;
+                frame[1] += self.level;
+            }
+            if self.releasing {
+                self.level /= 2.0;
+            }
+        }
+
+        fn is_done(&self) -> bool {
+            self.releasing && self.level < 0.3
+        }
+
+        fn level(&self) -> f32 {
+            self.level
+        }
+    }
+
+    fn params(note: i32, vol: f32) -> Params {
+        let levels = LevelParams {
+            pan: 0.0,
+            vol,
+            pitch: note as f32 * 100.0,
+            mod_x: 0.0,
+            mod_y: 0.0,
+        };
+        Params {
+            init_levels: levels.clone(),
+            final_levels: levels,
+        }
+    }
+
+    fn kills(host: &MockHost) -> Vec<HostCall> {
+        host.take_calls()
+            .into_iter()
+            .filter(|call| matches!(call, HostCall::VoiceKill(_)))
+            .collect()
+    }
+
+    #[test]
+    fn steals_and_tracks_release() {
+        let host = MockHost::new();
+        let mut engine = Engine::new(&host.host(), 2, Beep::default);
+        engine.trigger(params(60, 1.0), Tag(1));
+        engine.trigger(params(64, 0.5), Tag(2));
+        assert_eq!(2, engine.active());
+
+        // the oldest goes
+        engine.trigger(params(67, 1.0), Tag(3));
+        let mut notes: Vec<_> = engine.voices_mut().map(|voice| voice.note).collect();
+        notes.sort_unstable();
+        assert_eq!(vec![64, 67], notes);
+        let mut output = [[0.0; 2]; 2];
+        engine.render(&mut output);
+        assert_eq!([[1.5; 2]; 2], output);
+        assert_eq!(vec![HostCall::VoiceKill(1)], kills(&host));
+        engine.kill(Tag(1));
+
+        // the releasing voice plays until it's done
+        engine.release(Tag(3));
+        engine.render(&mut [[0.0; 2]; 2]);
+        assert_eq!(2, engine.active());
+        engine.render(&mut [[0.0; 2]; 2]);
+        assert_eq!(1, engine.active());
+        assert_eq!(vec![HostCall::VoiceKill(3)], kills(&host));
+
+        // quietest and max poly
+        engine.set_steal(Steal::Quietest);
+        engine.set_max_poly(1);
+        engine.trigger(params(72, 1.0), Tag(4));
+        engine.render(&mut [[0.0; 2]; 2]);
+        assert_eq!(vec![HostCall::VoiceKill(2)], kills(&host));
+        assert!(engine.kill_weakest());
+        assert_eq!(0, engine.active());
+        assert_eq!(vec![HostCall::VoiceKill(4)], kills(&host));
+        assert!(!engine.kill_weakest());
+
+        // there's always a voice
+        assert_eq!(1, Engine::new(&host.host(), 0, Beep::default).capacity());
+    }
+
+    #[test]
+    fn same_note_and_legato() {
+        let host = MockHost::new();
+        let mut engine = Engine::new(&host.host(), 4, Beep::default);
+        engine.set_steal(Steal::SameNote);
+        engine.trigger(params(60, 1.0), Tag(1));
+        engine.trigger(params(60, 1.0), Tag(2));
+        assert_eq!(1, engine.active());
+        engine.render(&mut [[0.0; 2]; 1]);
+        assert_eq!(vec![HostCall::VoiceKill(1)], kills(&host));
+
+        let mut engine = Engine::new(&host.host(), 4, Beep::default);
+        engine.set_mode(Mode::Legato);
+        engine.trigger(params(60, 1.0), Tag(1));
+        engine.trigger(params(62, 1.0), Tag(2));
+        assert_eq!(1, engine.active());
+        assert_eq!(1, engine.voices_mut().next().unwrap().glides);
+
+        // back to the held note
+        engine.release(Tag(2));
+        let voice = engine.voices_mut().next().unwrap();
+        assert_eq!((60, false), (voice.note, voice.releasing));
+        engine.release(Tag(1));
+        assert!(engine.voices_mut().next().unwrap().releasing);
+    }
+}
@@ -0,0 +1,126 @@
+//! Voice handles given to the host.
+//!
+//! A handle packs a slot index and the slot's generation, which changes every time the slot is
+//! freed, so a handle the host uses after killing the voice doesn't reach the voice which took the
+//! slot.
+use log::debug;
+
+use super::Tag;
+use crate::intptr_t;
+
+const INDEX_BITS: u32 = usize::BITS / 2;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+// one bit less, so the handles are positive and never FVH_Null (-1)
+const GENERATION_MASK: usize = (1 << (INDEX_BITS - 1)) - 1;
+
+#[derive(Debug)]
+struct Slot {
+    generation: usize,
+    tag: Option<Tag>,
+}
+
+#[derive(Debug)]
+pub(crate) struct VoiceSlots {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+}
+
+impl VoiceSlots {
+    // All the slots are allocated here, so the mixer thread never allocates.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        // the index is stored + 1, so no handle is 0
+        let capacity = capacity.min(INDEX_MASK);
+        Self {
+            slots: (0..capacity)
+                .map(|_| Slot {
+                    generation: 0,
+                    tag: None,
+                })
+                .collect(),
+            free: (0..capacity).rev().collect(),
+        }
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.free.is_empty()
+    }
+
+    // Returns `None` if all the slots are taken.
+    pub(crate) fn insert(&mut self, tag: Tag) -> Option<intptr_t> {
+        let index = self.free.pop()?;
+        let slot = &mut self.slots[index];
+        slot.tag = Some(tag);
+        Some((slot.generation << INDEX_BITS | (index + 1)) as intptr_t)
+    }
+
+    // The tag of the voice, or `None` if the handle is stale or unknown.
+    pub(crate) fn get(&self, handle: intptr_t) -> Option<Tag> {
+        let (index, generation) = Self::split(handle)?;
+        match self.slots.get(index) {
+            Some(slot) if slot.generation == generation => slot.tag,
+            _ => {
+                debug!("unknown voice handle {}", handle);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, handle: intptr_t) -> Option<Tag> {
+        let tag = self.get(handle)?;
+        let (index, _) = Self::split(handle)?;
+        let slot = &mut self.slots[index];
+        slot.tag = None;
+        slot.generation = (slot.generation + 1) & GENERATION_MASK;
+        self.free.push(index);
+        Some(tag)
+    }
+
+    // Frees all the slots.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = Tag> + '_ {
+        self.free.clear();
+        self.free.extend((0..self.slots.len()).rev());
+        self.slots.iter_mut().filter_map(|slot| {
+            let tag = slot.tag.take()?;
+            slot.generation = (slot.generation + 1) & GENERATION_MASK;
+            Some(tag)
+        })
+    }
+
+    fn split(handle: intptr_t) -> Option<(usize, usize)> {
+        let handle = handle as usize;
+        let index = (handle & INDEX_MASK).checked_sub(1)?;
+        Some((index, handle >> INDEX_BITS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_handles_are_ignored() {
+        let mut slots = VoiceSlots::with_capacity(2);
+        let first = slots.insert(Tag(10)).unwrap();
+        let second = slots.insert(Tag(20)).unwrap();
+        assert!(first > 0 && second > 0 && first != second);
+        assert_eq!(Some(Tag(10)), slots.get(first));
+        assert!(slots.is_full());
+        assert_eq!(None, slots.insert(Tag(40)));
+
+        assert_eq!(Some(Tag(10)), slots.remove(first));
+        assert_eq!(None, slots.remove(first));
+        // the slot is reused with another generation
+        let third = slots.insert(Tag(30)).unwrap();
+        assert_ne!(first, third);
+        assert_eq!(None, slots.get(first));
+        assert_eq!(Some(Tag(30)), slots.get(third));
+        assert_eq!(None, slots.get(0));
+        assert_eq!(None, slots.get(-1));
+
+        let mut left: Vec<_> = slots.drain().collect();
+        left.sort_by_key(|tag| tag.0);
+        assert_eq!(vec![Tag(20), Tag(30)], left);
+        assert_eq!(None, slots.get(second));
+        assert!(slots.insert(Tag(50)).is_some());
+    }
+}
@@ -0,0 +1,420 @@
+//! Memory the plugin allocates and frees, counted by a global allocator of its own, so it doesn't
+//! replace the allocator of the other tests.
+//!
+//! Run with `cargo test --features testing --test allocations`.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use fpsdk::host::context::AudioContext;
+use fpsdk::host::{self, Host, OutVoicer};
+use fpsdk::plugin::message::{
+    AddToPianoRoll, GetInName, GetMixingTime, GetParamMenuEntry, LocateDataFile,
+};
+use fpsdk::plugin::{Effect, Generator, InfoBuilder, Tag};
+use fpsdk::testing::{id, HostCall, MockHost, Payload, Reply};
+use fpsdk::voice::engine::{Engine, SynthVoice};
+use fpsdk::voice::{self, LevelParams, ReceiveVoiceHandler, SendVoiceHandler};
+use fpsdk::{
+    AsRawPtr, DispatchResult, Note, Notes, NotesFlags, NotesTarget, ParamMenuItemFlags,
+    ProcessParamFlags, TimeFormat, ValuePtr,
+};
+
+// Counts the bytes allocated on each thread, so tests running in parallel don't interfere.
+// While watching, it also remembers the freed pointers until they're allocated again.
+struct CountingAlloc;
+
+const WATCHED: usize = 64;
+
+thread_local! {
+    static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static WATCHING: Cell<bool> = const { Cell::new(false) };
+    static FREED: Cell<[usize; WATCHED]> = const { Cell::new([0; WATCHED]) };
+    static FREED_LEN: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|bytes| bytes.set(bytes.get() + layout.size() as isize));
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        let ptr = System.alloc(layout);
+        if WATCHING.try_with(Cell::get).unwrap_or(false) {
+            let _ = FREED.try_with(|freed| {
+                let mut list = freed.get();
+                list.iter_mut()
+                    .filter(|freed| **freed == ptr as usize)
+                    .for_each(|freed| *freed = 0);
+                freed.set(list);
+            });
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = ALLOCATED.try_with(|bytes| bytes.set(bytes.get() - layout.size() as isize));
+        if WATCHING.try_with(Cell::get).unwrap_or(false) {
+            let _ = FREED.try_with(|freed| {
+                FREED_LEN.with(|len| {
+                    let mut list = freed.get();
+                    list[len.get() % WATCHED] = ptr as usize;
+                    freed.set(list);
+                    len.set(len.get() + 1);
+                })
+            });
+        }
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+fn allocated() -> isize {
+    ALLOCATED.with(Cell::get)
+}
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+// Run `f` and return the pointers it freed.
+fn freed_by(f: impl FnOnce()) -> Vec<usize> {
+    FREED_LEN.with(|len| len.set(0));
+    WATCHING.with(|watching| watching.set(true));
+    f();
+    WATCHING.with(|watching| watching.set(false));
+    let len = FREED_LEN.with(Cell::get);
+    assert!(len <= WATCHED, "too many frees to watch");
+    FREED.with(Cell::get)[..len].to_vec()
+}
+
+fn params() -> voice::Params {
+    let levels = LevelParams {
+        pan: 0.0,
+        vol: 1.0,
+        pitch: 6000.0,
+        mod_x: 0.0,
+        mod_y: 0.0,
+    };
+    voice::Params {
+        init_levels: levels.clone(),
+        final_levels: levels,
+    }
+}
+
+// Answers without boxing the results.
+#[derive(Debug, Default)]
+struct Realtime {
+    gain: f32,
+}
+
+fpsdk::test_plugin!(
+    Realtime,
+    InfoBuilder::new("Realtime", "Realtime", 1).build(),
+    {
+        fn handle_message(&mut self, message: host::Message<'_>) -> DispatchResult {
+            match message {
+                host::Message::Flush => DispatchResult::from(true),
+                _ => DispatchResult::NONE,
+            }
+        }
+
+        fn handle_param(
+            &mut self,
+            _index: usize,
+            value: ValuePtr,
+            flags: ProcessParamFlags,
+        ) -> DispatchResult {
+            if flags.contains(ProcessParamFlags::UPDATE_VALUE) {
+                self.gain = value.get::<f32>();
+            }
+            DispatchResult::from(self.gain)
+        }
+    }
+);
+
+impl Effect for Realtime {
+    fn render(
+        &mut self,
+        _ctx: &mut AudioContext<'_>,
+        _input: &[[f32; 2]],
+        _output: &mut [[f32; 2]],
+    ) {
+    }
+}
+
+// Answers with the boxed results.
+#[derive(Debug, Default)]
+struct Boxed;
+
+fpsdk::test_plugin!(Boxed, InfoBuilder::new("Boxed", "Boxed", 0).build(), {
+    fn on_message(&mut self, _message: host::Message<'_>) -> Box<dyn AsRawPtr> {
+        Box::new(DispatchResult::NONE)
+    }
+});
+
+impl Effect for Boxed {
+    fn render(
+        &mut self,
+        _ctx: &mut AudioContext<'_>,
+        _input: &[[f32; 2]],
+        _output: &mut [[f32; 2]],
+    ) {
+    }
+}
+
+#[derive(Debug, Default)]
+struct Hum;
+
+impl SynthVoice for Hum {
+    fn start(&mut self, _params: &voice::Params) {}
+
+    fn release(&mut self) {}
+
+    fn render(&mut self, _output: &mut [[f32; 2]]) {}
+
+    fn is_done(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pad;
+
+fpsdk::test_plugin!(Pad, InfoBuilder::new("Pad", "Pad", 0).build());
+
+impl Generator for Pad {
+    type Voices = Engine<Hum>;
+
+    fn voices(host: &Host, _tag: Tag) -> Self::Voices {
+        Engine::new(host, 2, Hum::default)
+    }
+
+    fn render(
+        &mut self,
+        _ctx: &mut AudioContext<'_>,
+        _output: &mut [[f32; 2]],
+        _voices: &mut Self::Voices,
+    ) {
+    }
+}
+
+#[test]
+fn dispatch_without_allocations() {
+    let host = MockHost::new();
+    let mut plugin = host.create_effect::<Realtime>();
+    let mut boxed = host.create_effect::<Boxed>();
+
+    let before = allocations();
+    assert_eq!(1, plugin.dispatch(host::Message::Flush));
+    assert_eq!(0, plugin.dispatch(host::Message::SetSamplesPerTick(100.0)));
+    let raw = plugin.process_param(
+        0,
+        0.5_f32.as_raw_ptr(),
+        ProcessParamFlags::UPDATE_VALUE | ProcessParamFlags::GET_VALUE,
+    );
+    assert_eq!(0.5_f32.as_raw_ptr(), raw);
+    assert_eq!(before, allocations());
+
+    // voice events of the engine
+    let mut pad = host.create_generator::<Pad>();
+    let handle = pad.trigger_voice(params(), 1);
+    let before = allocations();
+    assert_eq!(0, pad.voice_event(handle, voice::Event::Retrigger));
+    assert_eq!(before, allocations());
+
+    // the boxed results still work
+    assert_eq!(0, boxed.dispatch(host::Message::Flush));
+    assert!(allocations() > before);
+}
+
+static DESTROYED: AtomicUsize = AtomicUsize::new(0);
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+struct Tidy {
+    _buffer: Vec<f32>,
+}
+
+impl Drop for Tidy {
+    fn drop(&mut self) {
+        DROPPED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// Sends every voice to an output, too.
+#[derive(Debug)]
+struct TidyVoices {
+    voices: Vec<voice::Tag>,
+    out: OutVoicer,
+}
+
+impl ReceiveVoiceHandler for TidyVoices {
+    fn trigger(&mut self, params: voice::Params, tag: voice::Tag) {
+        self.out.trigger(params, 0, tag);
+        self.voices.push(tag);
+    }
+
+    fn release(&mut self, _tag: voice::Tag) {}
+
+    fn kill(&mut self, tag: voice::Tag) {
+        self.voices.retain(|voice| *voice != tag);
+    }
+}
+
+fpsdk::test_plugin!(
+    Tidy,
+    InfoBuilder::new("Tidy", "Tidy", 0).get_note_input().build(),
+    {
+        fn new(_host: Host, _tag: Tag) -> Self {
+            Self {
+                _buffer: vec![0.0; 1024],
+            }
+        }
+
+        fn on_destroy(&mut self) {
+            assert_eq!(
+                DESTROYED.fetch_add(1, Ordering::SeqCst),
+                DROPPED.load(Ordering::SeqCst)
+            );
+        }
+    }
+);
+
+impl Generator for Tidy {
+    type Voices = TidyVoices;
+
+    fn voices(host: &Host, _tag: Tag) -> Self::Voices {
+        TidyVoices {
+            voices: Vec::with_capacity(4),
+            out: host.out_voice_handler(4),
+        }
+    }
+
+    fn render(
+        &mut self,
+        _ctx: &mut AudioContext<'_>,
+        _output: &mut [[f32; 2]],
+        _voices: &mut Self::Voices,
+    ) {
+    }
+}
+
+#[test]
+fn destroy_frees_everything() {
+    let host = MockHost::new();
+    // the first instance installs the panic hook, which stays
+    let first = host.create_generator::<Tidy>();
+    // the kind comes from create_generator, the builder can't set it
+    assert_eq!(1, first.info().flags & 1);
+    drop(first);
+    host.take_calls();
+
+    let before = allocated();
+    let mut plugin = host.create_generator::<Tidy>();
+    let first = plugin.trigger_voice(params(), 1);
+    plugin.trigger_voice(params(), 2);
+    plugin.kill_voice(first);
+    // killing twice is ignored
+    plugin.kill_voice(first);
+    // the second voice and both output voices are left for destroy
+    drop(plugin);
+    drop(host.take_calls());
+
+    assert_eq!(before, allocated());
+    assert_eq!(2, DESTROYED.load(Ordering::SeqCst));
+    assert_eq!(2, DROPPED.load(Ordering::SeqCst));
+}
+
+#[test]
+fn host_memory_is_not_freed() {
+    let mock = MockHost::new();
+    let mut host = mock.host();
+    let tag = Tag(1);
+    mock.queue_reply(
+        id::GET_PARAM_MENU_ENTRY,
+        Reply::ParamMenuEntry("Linear".to_string(), ParamMenuItemFlags::CHECKED),
+    );
+    mock.queue_reply(
+        id::LOCATE_DATA_FILE,
+        Reply::String("C:\\data\\file.wav".to_string()),
+    );
+
+    let mut entry = None;
+    let mut path = String::new();
+    let freed = freed_by(|| {
+        entry = host.on_message(tag, GetParamMenuEntry(0, 0));
+        path = host.on_message(tag, LocateDataFile("file.wav".to_string()));
+    });
+
+    let entry = entry.unwrap();
+    assert_eq!("Linear", entry.name);
+    assert_eq!(ParamMenuItemFlags::CHECKED, entry.flags);
+    assert_eq!("C:\\data\\file.wav", path);
+    // the entry's name, the path and the entry
+    let owned = mock.host_memory();
+    assert_eq!(3, owned.len());
+    for ptr in owned {
+        assert!(!freed.contains(&ptr), "the host's {:#x} was freed", ptr);
+    }
+
+    // the plugin's own memory isn't leaked, even when the host doesn't answer
+    mock.take_calls();
+    mock.queue_reply(id::GET_MIXING_TIME, Reply::Time(1.0, 0.0));
+    let before = allocated();
+    assert_eq!(
+        1.0,
+        host.on_message(tag, GetMixingTime(TimeFormat::Beats, 0)).0
+    );
+    assert!(host.on_message(tag, GetInName(1)).is_none());
+    drop(mock.take_calls());
+    assert_eq!(before, allocated());
+}
+
+#[test]
+fn notes_are_freed_after_sending() {
+    let mock = MockHost::new();
+    let mut host = mock.host();
+    let note = Note {
+        position: 96,
+        length: 48,
+        pan: 0,
+        vol: 100,
+        note: 60,
+        color: 0,
+        pitch: 0,
+        mod_x: 1.0,
+        mod_y: 0.0,
+    };
+    let notes = Notes {
+        target: NotesTarget::PianoRoll,
+        notes: vec![note.clone(), note],
+        flags: NotesFlags::EMPTY_FIRST,
+        pattern: Some(2),
+        channel: None,
+    };
+
+    let message = AddToPianoRoll(notes.clone());
+    let freed = freed_by(|| host.on_message(Tag(1), message));
+
+    let calls = mock.take_calls();
+    match &calls[..] {
+        [HostCall::Message {
+            id: 17,
+            value,
+            payload: Payload::Notes(sent),
+            ..
+        }] => {
+            assert_eq!(&notes, sent);
+            assert!(freed.contains(&(*value as usize)), "the notes were leaked");
+        }
+        calls => panic!("unexpected calls {:?}", calls),
+    }
+
+    let before = allocated();
+    host.on_message(Tag(1), AddToPianoRoll(notes));
+    drop(mock.take_calls());
+    assert_eq!(before - size_of::<Note>() as isize * 2, allocated());
+}